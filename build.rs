@@ -0,0 +1,40 @@
+//! Embed build information (git sha, build time) for the readiness probe.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+    // HEAD only changes on a branch switch, a commit moves the branch it points to
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(head_ref) = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|head_ref| head_ref.trim().to_string()))
+    {
+        watch(&format!(".git/{}", head_ref));
+    }
+    watch(".git/packed-refs");
+}
+
+/// Rerun when `path` changes, a missing path would rerun on every build
+fn watch(path: &str) {
+    if Path::new(path).exists() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
@@ -0,0 +1,180 @@
+//! In-process cache split into independently locked shards,
+//! each evicting its least recently used entry when full.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::Cache;
+use crate::errors::ApiError;
+
+const SHARDS: usize = 16;
+
+pub struct MemoryCache {
+    shards: Vec<Mutex<Shard>>,
+    ttl: Duration,
+}
+
+struct Entry {
+    value: String,
+    expires_at: Instant,
+    used_at: u64,
+}
+
+struct Shard {
+    entries: HashMap<String, Entry>,
+    /// Keys by the tick of their last use, oldest first
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    capacity: usize,
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Shard {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            capacity,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, key: &str) -> Option<String> {
+        let now = Instant::now();
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires_at <= now {
+            self.remove(key);
+            return None;
+        }
+
+        self.recency.remove(&entry.used_at);
+        entry.used_at = tick;
+        self.recency.insert(tick, key.to_string());
+        Some(entry.value.clone())
+    }
+
+    fn set(&mut self, key: &str, value: &str, ttl: Duration) {
+        self.remove(key);
+        let tick = self.tick();
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                value: value.to_string(),
+                expires_at: Instant::now() + ttl,
+                used_at: tick,
+            },
+        );
+        self.recency.insert(tick, key.to_string());
+
+        while self.entries.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used_at);
+        }
+    }
+}
+
+impl MemoryCache {
+    /// A cache holding about `capacity` entries
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self::with_shards(capacity, SHARDS, ttl)
+    }
+
+    fn with_shards(capacity: usize, shards: usize, ttl: Duration) -> Self {
+        let per_shard = capacity.div_ceil(shards).max(1);
+        MemoryCache {
+            shards: (0..shards).map(|_| Mutex::new(Shard::new(per_shard))).collect(),
+            ttl,
+        }
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, key: &str) -> Result<Option<String>, ApiError> {
+        Ok(self.shard(key).lock().expect("Could not acquire lock").get(key))
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), ApiError> {
+        self.shard(key).lock().expect("Could not acquire lock").set(key, value, ttl);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ApiError> {
+        self.shard(key).lock().expect("Could not acquire lock").remove(key);
+        Ok(())
+    }
+
+    fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn it_sets_gets_and_deletes_entries() {
+        let cache = MemoryCache::new(100, MINUTE);
+        cache.set("key", "value", MINUTE).unwrap();
+        assert_eq!(cache.get("key").unwrap(), Some("value".to_string()));
+
+        cache.delete("key").unwrap();
+        assert_eq!(cache.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn it_expires_entries_after_their_ttl() {
+        let cache = MemoryCache::new(100, MINUTE);
+        cache.set("expired", "value", Duration::from_secs(0)).unwrap();
+        assert_eq!(cache.get("expired").unwrap(), None);
+    }
+
+    #[test]
+    fn it_evicts_the_least_recently_used_entry() {
+        let cache = MemoryCache::with_shards(2, 1, MINUTE);
+        cache.set("a", "1", MINUTE).unwrap();
+        cache.set("b", "2", MINUTE).unwrap();
+        cache.get("a").unwrap();
+        cache.set("c", "3", MINUTE).unwrap();
+
+        assert_eq!(cache.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(cache.get("b").unwrap(), None);
+        assert_eq!(cache.get("c").unwrap(), Some("3".to_string()));
+    }
+
+    #[test]
+    fn it_replaces_an_existing_entry() {
+        let cache = MemoryCache::with_shards(2, 1, MINUTE);
+        cache.set("a", "1", MINUTE).unwrap();
+        cache.set("a", "2", MINUTE).unwrap();
+        cache.set("b", "3", MINUTE).unwrap();
+        assert_eq!(cache.get("a").unwrap(), Some("2".to_string()));
+        assert_eq!(cache.get("b").unwrap(), Some("3".to_string()));
+    }
+}
@@ -0,0 +1,118 @@
+//! Key-value cache with per-entry time to live.
+//!
+//! The cache is never the source of truth: values are loaded from MongoDB on a
+//! miss, and a failing backend only costs the round trip to the database.
+
+mod memory;
+mod redis;
+
+pub use self::memory::MemoryCache;
+pub use self::redis::RedisCache;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::errors::ApiError;
+
+pub trait Cache: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, ApiError>;
+    fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), ApiError>;
+    fn delete(&self, key: &str) -> Result<(), ApiError>;
+
+    /// Time to live of entries cached with `get_or_load`
+    fn ttl(&self) -> Duration;
+
+    /// Whether every instance of the server sees the same entries, so that
+    /// invalidating one on any instance takes effect everywhere
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+/// Create the cache selected by `cache_backend`: "memory" (default) or "redis"
+pub fn from_config(config: &Config) -> Result<Box<dyn Cache>, ApiError> {
+    let ttl = Duration::from_secs(config.cache_ttl_seconds);
+    match config.cache_backend.as_str() {
+        "redis" => Ok(Box::new(RedisCache::new(&config.cache_url, ttl)?)),
+        _ => Ok(Box::new(MemoryCache::new(config.cache_capacity, ttl))),
+    }
+}
+
+/// Get a cached value, or load it and cache it on a miss.
+/// Errors of `load` are returned and never cached.
+pub fn get_or_load<T, F>(cache: &dyn Cache, key: &str, load: F) -> Result<T, ApiError>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T, ApiError>,
+{
+    get_or_load_for(cache, key, cache.ttl(), load)
+}
+
+/// `get_or_load` caching for `ttl` instead of the cache's own time to live
+pub fn get_or_load_for<T, F>(cache: &dyn Cache, key: &str, ttl: Duration, load: F) -> Result<T, ApiError>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T, ApiError>,
+{
+    match cache.get(key) {
+        Ok(Some(cached)) => match serde_json::from_str(&cached) {
+            Ok(value) => return Ok(value),
+            Err(e) => warn!("cache entry {} is unreadable: {}", key, e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("cache get {} failed: {}", key, e),
+    }
+
+    let value = load()?;
+    match serde_json::to_string(&value) {
+        Ok(serialized) => {
+            if let Err(e) = cache.set(key, &serialized, ttl) {
+                warn!("cache set {} failed: {}", key, e);
+            }
+        }
+        Err(e) => warn!("cache entry {} is not serializable: {}", key, e),
+    }
+    Ok(value)
+}
+
+/// Drop a cached value after the data behind it changed
+pub fn invalidate(cache: &dyn Cache, key: &str) {
+    if let Err(e) = cache.delete(key) {
+        warn!("cache delete {} failed: {}", key, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn it_loads_only_on_a_miss() {
+        let cache = MemoryCache::new(10, Duration::from_secs(60));
+        let loads = Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok(vec![1, 2, 3])
+        };
+
+        assert_eq!(get_or_load(&cache, "numbers", load).unwrap(), vec![1, 2, 3]);
+        assert_eq!(get_or_load(&cache, "numbers", load).unwrap(), vec![1, 2, 3]);
+        assert_eq!(loads.get(), 1);
+
+        invalidate(&cache, "numbers");
+        get_or_load(&cache, "numbers", load).unwrap();
+        assert_eq!(loads.get(), 2);
+    }
+
+    #[test]
+    fn it_does_not_cache_errors() {
+        let cache = MemoryCache::new(10, Duration::from_secs(60));
+        let failed: Result<i64, ApiError> =
+            get_or_load(&cache, "missing", || Err(ApiError::NotFound("missing".into())));
+        assert!(failed.is_err());
+        assert_eq!(cache.get("missing").unwrap(), None);
+    }
+}
@@ -0,0 +1,369 @@
+//! Cache backed by a Redis-protocol server (Redis, KeyDB, Dragonfly...),
+//! speaking RESP over a small pool of TCP connections.
+//!
+//! Commands run outside any lock, each on a connection of its own. After
+//! repeated failures the cache stops calling the server for a growing backoff,
+//! so an unreachable server costs callers nothing but the database lookup.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::Cache;
+use crate::errors::ApiError;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(1);
+/// Idle connections kept open for the next commands
+const POOL_SIZE: usize = 8;
+/// Consecutive failures before commands stop reaching the server
+const FAILURE_THRESHOLD: u32 = 3;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+type Connection = BufReader<TcpStream>;
+
+pub struct RedisCache {
+    address: String,
+    idle: Mutex<Vec<Connection>>,
+    breaker: Mutex<Breaker>,
+    ttl: Duration,
+}
+
+/// Circuit breaker: open after `FAILURE_THRESHOLD` consecutive failures,
+/// letting a single command through once the backoff has passed
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    /// Whether a command may reach the server now
+    fn allows(&mut self, now: Instant) -> bool {
+        match self.open_until {
+            Some(until) if now < until => false,
+            Some(_) => {
+                // Half open: this command probes the server, the others wait for its outcome
+                self.open_until = Some(now + self.backoff());
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= FAILURE_THRESHOLD {
+            self.open_until = Some(now + self.backoff());
+        }
+    }
+
+    /// Doubles with each failure past the threshold, up to `MAX_BACKOFF`
+    fn backoff(&self) -> Duration {
+        let doublings = self.failures.saturating_sub(FAILURE_THRESHOLD).min(6);
+        (MIN_BACKOFF * 2u32.pow(doublings)).min(MAX_BACKOFF)
+    }
+}
+
+/// A reply of the server
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    /// The server refused the command, the connection is still usable
+    Error(String),
+}
+
+impl RedisCache {
+    /// `url` is redis://host:port, connections are opened on first use
+    pub fn new(url: &str, ttl: Duration) -> Result<Self, ApiError> {
+        let address = url
+            .strip_prefix("redis://")
+            .map(|rest| rest.split('/').next().unwrap_or_default().to_string())
+            .filter(|address| !address.is_empty())
+            .ok_or_else(|| ApiError::CacheError(format!("Invalid cache_url {}, expected redis://host:port", url)))?;
+        Ok(RedisCache {
+            address,
+            idle: Mutex::new(Vec::new()),
+            breaker: Mutex::new(Breaker::default()),
+            ttl,
+        })
+    }
+
+    fn connect(&self) -> Result<Connection, ApiError> {
+        let address = self
+            .address
+            .to_socket_addrs()
+            .map_err(cache_error)?
+            .next()
+            .ok_or_else(|| ApiError::CacheError(format!("Cannot resolve {}", self.address)))?;
+        let stream = TcpStream::connect_timeout(&address, IO_TIMEOUT).map_err(cache_error)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(cache_error)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(cache_error)?;
+        Ok(BufReader::new(stream))
+    }
+
+    /// Send a command and read its reply, failing fast while the breaker is open.
+    /// A connection that failed is dropped, the others go back to the pool.
+    fn command(&self, args: &[&str]) -> Result<Reply, ApiError> {
+        if !self.breaker.lock().expect("Could not acquire lock").allows(Instant::now()) {
+            return Err(ApiError::CacheError(format!("{} is unavailable, backing off", self.address)));
+        }
+
+        let pooled = self.idle.lock().expect("Could not acquire lock").pop();
+        let result = pooled.map_or_else(|| self.connect(), Ok).and_then(|mut connection| {
+            connection.get_mut().write_all(&encode_command(args)).map_err(cache_error)?;
+            let reply = read_reply(&mut connection)?;
+            self.release(connection);
+            Ok(reply)
+        });
+
+        let mut breaker = self.breaker.lock().expect("Could not acquire lock");
+        match result {
+            Ok(Reply::Error(message)) => {
+                breaker.succeeded();
+                Err(ApiError::CacheError(message))
+            }
+            Ok(reply) => {
+                breaker.succeeded();
+                Ok(reply)
+            }
+            Err(e) => {
+                breaker.failed(Instant::now());
+                Err(e)
+            }
+        }
+    }
+
+    fn release(&self, connection: Connection) {
+        let mut idle = self.idle.lock().expect("Could not acquire lock");
+        if idle.len() < POOL_SIZE {
+            idle.push(connection);
+        }
+    }
+}
+
+impl Cache for RedisCache {
+    fn get(&self, key: &str) -> Result<Option<String>, ApiError> {
+        match self.command(&["GET", key])? {
+            Reply::Bulk(value) => Ok(value),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), ApiError> {
+        // PX must be positive, a zero TTL is treated as already expired
+        let milliseconds = ttl.as_millis().max(1).to_string();
+        match self.command(&["SET", key, value, "PX", &milliseconds])? {
+            Reply::Status(_) => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ApiError> {
+        match self.command(&["DEL", key])? {
+            Reply::Integer(_) => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend(format!("${}\r\n", arg.len()).into_bytes());
+        command.extend(arg.as_bytes());
+        command.extend(b"\r\n");
+    }
+    command
+}
+
+fn read_reply(stream: &mut impl BufRead) -> Result<Reply, ApiError> {
+    let line = read_line(stream)?;
+    let (kind, rest) = line.split_at(1.min(line.len()));
+    match kind {
+        "+" => Ok(Reply::Status(rest.into())),
+        "-" => Ok(Reply::Error(rest.into())),
+        ":" => rest.parse().map(Reply::Integer).map_err(|_| malformed(&line)),
+        "$" => {
+            let length: i64 = rest.parse().map_err(|_| malformed(&line))?;
+            if length < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            let mut value = vec![0; length as usize + 2];
+            stream.read_exact(&mut value).map_err(cache_error)?;
+            value.truncate(length as usize);
+            String::from_utf8(value)
+                .map(|value| Reply::Bulk(Some(value)))
+                .map_err(|_| malformed(&line))
+        }
+        _ => Err(malformed(&line)),
+    }
+}
+
+fn read_line(stream: &mut impl BufRead) -> Result<String, ApiError> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).map_err(cache_error)? == 0 {
+        return Err(ApiError::CacheError("Connection closed".into()));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn cache_error(error: std::io::Error) -> ApiError {
+    ApiError::CacheError(error.to_string())
+}
+
+fn malformed(line: &str) -> ApiError {
+    ApiError::CacheError(format!("Malformed reply: {}", line))
+}
+
+fn unexpected(reply: Reply) -> ApiError {
+    ApiError::CacheError(format!("Unexpected reply: {:?}", reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Minimal stand-in for a Redis server, answering GET, SET and DEL
+    fn start_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut store: HashMap<String, String> = HashMap::new();
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                while let Ok(args) = read_command(&mut reader) {
+                    let reply = match args[0].as_str() {
+                        "GET" => match store.get(&args[1]) {
+                            Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+                            None => "$-1\r\n".to_string(),
+                        },
+                        "SET" => {
+                            store.insert(args[1].clone(), args[2].clone());
+                            "+OK\r\n".to_string()
+                        }
+                        "DEL" => format!(":{}\r\n", store.remove(&args[1]).map_or(0, |_| 1)),
+                        _ => "-ERR unknown command\r\n".to_string(),
+                    };
+                    reader.get_mut().write_all(reply.as_bytes()).unwrap();
+                }
+            }
+        });
+        format!("redis://{}", address)
+    }
+
+    fn read_command(reader: &mut BufReader<TcpStream>) -> Result<Vec<String>, ApiError> {
+        let header = read_line(reader)?;
+        let count: usize = header[1..].parse().map_err(|_| malformed(&header))?;
+        (0..count)
+            .map(|_| match read_reply(reader)? {
+                Reply::Bulk(Some(arg)) => Ok(arg),
+                reply => Err(unexpected(reply)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_encodes_commands_as_resp_arrays() {
+        assert_eq!(encode_command(&["GET", "key"]), b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec());
+    }
+
+    #[test]
+    fn it_reads_replies() {
+        let read = |bytes: &[u8]| read_reply(&mut Cursor::new(bytes.to_vec()));
+        assert_eq!(read(b"+OK\r\n").unwrap(), Reply::Status("OK".into()));
+        assert_eq!(read(b":1\r\n").unwrap(), Reply::Integer(1));
+        assert_eq!(read(b"$5\r\nhello\r\n").unwrap(), Reply::Bulk(Some("hello".into())));
+        assert_eq!(read(b"$-1\r\n").unwrap(), Reply::Bulk(None));
+        assert_eq!(read(b"-ERR wrong type\r\n").unwrap(), Reply::Error("ERR wrong type".into()));
+        assert!(read(b"?\r\n").is_err());
+    }
+
+    #[test]
+    fn it_round_trips_values_through_a_server() {
+        let cache = RedisCache::new(&start_server(), Duration::from_secs(60)).unwrap();
+        cache.set("key", "value\r\nwith a line break", cache.ttl()).unwrap();
+        assert_eq!(cache.get("key").unwrap(), Some("value\r\nwith a line break".into()));
+
+        cache.delete("key").unwrap();
+        assert_eq!(cache.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn it_fails_without_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let cache = RedisCache::new(&url, Duration::from_secs(60)).unwrap();
+        assert!(cache.get("key").is_err());
+    }
+
+    #[test]
+    fn it_backs_off_after_repeated_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let cache = RedisCache::new(&url, Duration::from_secs(60)).unwrap();
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(cache.get("key").is_err());
+        }
+        match cache.get("key") {
+            Err(ApiError::CacheError(message)) => assert!(message.contains("backing off"), "{}", message),
+            other => panic!("Expected the breaker to be open, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_closes_the_breaker_once_the_server_answers() {
+        let start = Instant::now();
+        let mut breaker = Breaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.failed(start);
+        }
+        assert!(!breaker.allows(start));
+        assert_eq!(breaker.backoff(), MIN_BACKOFF);
+
+        // Past the backoff a single probe goes through
+        let later = start + MIN_BACKOFF;
+        assert!(breaker.allows(later));
+        assert!(!breaker.allows(later));
+        breaker.succeeded();
+        assert!(breaker.allows(later));
+    }
+
+    #[test]
+    fn it_reuses_pooled_connections() {
+        let cache = RedisCache::new(&start_server(), Duration::from_secs(60)).unwrap();
+        cache.set("key", "value", cache.ttl()).unwrap();
+        cache.get("key").unwrap();
+        assert_eq!(cache.idle.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_an_invalid_url() {
+        assert!(RedisCache::new("localhost:6379", Duration::from_secs(60)).is_err());
+    }
+}
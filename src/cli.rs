@@ -0,0 +1,372 @@
+//! Command-line interface: run the server or administer the database.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::context::AppContext;
+use crate::database;
+use crate::errors::ApiError;
+use crate::jwt::{create_jwt, hash, PrivateClaim};
+use crate::jobs::snapshot_portfolios;
+use crate::migrations;
+use crate::models::amount::Amount;
+use crate::models::asset::{enabled_asset, register_defaults};
+use crate::models::coin::create_holding;
+use crate::models::transaction::reconcile;
+use crate::models::transfer::{recover_transfers, stale_cutoff};
+use crate::models::user::{find_user, forget_cached_user, update_password, UserResponse};
+use crate::validate::validate_password;
+use chrono::prelude::*;
+use std::env;
+use std::io::{self, BufRead, IsTerminal};
+
+/// Environment variable holding the password for the user commands
+const PASSWORD_VAR: &str = "USER_PASSWORD";
+
+pub fn app() -> App<'static, 'static> {
+    App::new(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .help("TOML or YAML config file, overrides CONFIG_FILE")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("set")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .help("Override a config setting, wins over the file and environment")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .subcommand(SubCommand::with_name("serve").about("Run the HTTP server (default)"))
+        .subcommand(
+            SubCommand::with_name("create-admin")
+                .about("Create a user with the admin role, password from USER_PASSWORD or stdin")
+                .arg(Arg::with_name("uid").long("uid").takes_value(true).required(true))
+                .arg(Arg::with_name("email").long("email").takes_value(true).required(true))
+                .arg(Arg::with_name("name").long("name").takes_value(true).default_value(""))
+                .arg(Arg::with_name("phone").long("phone").takes_value(true).default_value("")),
+        )
+        .subcommand(
+            SubCommand::with_name("reset-password")
+                .about("Set a new password for a user, from USER_PASSWORD or stdin")
+                .arg(Arg::with_name("uid").long("uid").takes_value(true).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("mint-jwt")
+                .about("Print a JWT for a user, for debugging")
+                .arg(Arg::with_name("uid").long("uid").takes_value(true).required(true))
+                .arg(Arg::with_name("email").long("email").takes_value(true).default_value("")),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Run or inspect schema migrations")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("run").about("Apply pending migrations"))
+                .subcommand(SubCommand::with_name("status").about("List migrations"))
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Roll back the last applied migrations")
+                        .arg(Arg::with_name("steps").default_value("1")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("seed")
+                .about("Insert a demo user with coin holdings, password from USER_PASSWORD or stdin")
+                .arg(Arg::with_name("uid").long("uid").takes_value(true).default_value("demouser")),
+        )
+        .subcommand(SubCommand::with_name("ping").about("Check connectivity to MongoDB"))
+        .subcommand(
+            SubCommand::with_name("reconcile")
+                .about("Recompute coin balances from the transaction ledger and report drift"),
+        )
+        .subcommand(
+            SubCommand::with_name("recover-transfers")
+                .about("Refund the senders of transfers interrupted part way"),
+        )
+        .subcommand(SubCommand::with_name("snapshot").about("Snapshot every portfolio now"))
+}
+
+/// Run an administrative subcommand
+pub fn run(ctx: &AppContext, name: &str, matches: &ArgMatches) -> Result<(), ApiError> {
+    match name {
+        "create-admin" => create_admin(ctx, matches),
+        "reset-password" => reset_password(ctx, matches),
+        "mint-jwt" => mint_jwt(ctx, matches),
+        "migrate" => migrate(ctx, matches),
+        "seed" => seed(ctx, matches),
+        "ping" => ping(ctx),
+        "reconcile" => reconcile_balances(ctx),
+        "recover-transfers" => recover(ctx),
+        "snapshot" => snapshot(ctx),
+        _ => Err(ApiError::BadRequest(format!("Unknown command: {}", name))),
+    }
+}
+
+/// Required arguments are enforced by clap, defaults fill in the rest
+fn value<'a>(matches: &'a ArgMatches, name: &str) -> &'a str {
+    matches.value_of(name).unwrap_or_default()
+}
+
+/// Password for the user commands, from `USER_PASSWORD` or else a line of stdin,
+/// never from the arguments where other users can read it in the process list
+fn read_password() -> Result<String, ApiError> {
+    let stdin = io::stdin();
+    if env::var_os(PASSWORD_VAR).is_none() && stdin.is_terminal() {
+        eprint!("Password: ");
+    }
+    read_password_from(env::var(PASSWORD_VAR).ok(), &mut stdin.lock())
+}
+
+fn read_password_from(var: Option<String>, input: &mut impl BufRead) -> Result<String, ApiError> {
+    let password = match var {
+        Some(password) => password,
+        None => {
+            let mut line = String::new();
+            input
+                .read_line(&mut line)
+                .map_err(|e| ApiError::BadRequest(format!("Could not read the password: {}", e)))?;
+            line.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+    if password.is_empty() {
+        return Err(ApiError::BadRequest(format!(
+            "No password given, set {} or write it to stdin",
+            PASSWORD_VAR
+        )));
+    }
+    Ok(password)
+}
+
+fn create_admin(ctx: &AppContext, matches: &ArgMatches) -> Result<(), ApiError> {
+    let uid = value(matches, "uid");
+    let email = value(matches, "email");
+    let password = &read_password()?;
+    validate_password(&ctx.config, password, uid, email)?;
+
+    let user = doc! {
+        "uid": uid,
+        "email": email,
+        "password": hash(&ctx.config, password),
+        "name": value(matches, "name"),
+        "phone": value(matches, "phone"),
+        "roles": ["admin", "user"],
+        "created_at": Utc::now(),
+        "updated_at": Utc::now()
+    };
+    database::create(&*ctx.db, "users", user)?;
+    println!("Created admin {}", uid);
+    Ok(())
+}
+
+fn reset_password(ctx: &AppContext, matches: &ArgMatches) -> Result<(), ApiError> {
+    // Fail on an unknown user before asking for a password
+    let user = find_user(&*ctx.db, value(matches, "uid"))?;
+    set_password(ctx, &user, &read_password()?)?;
+    println!("Password reset for {}", user.uid);
+    Ok(())
+}
+
+/// Replace the password of `user`, checked against its stored uid and email
+fn set_password(ctx: &AppContext, user: &UserResponse, password: &str) -> Result<(), ApiError> {
+    validate_password(&ctx.config, password, &user.uid, &user.email)?;
+    update_password(&*ctx.db, &user.uid, &hash(&ctx.config, password))?;
+    forget_cached_user(&*ctx.cache, &user.uid);
+    Ok(())
+}
+
+fn mint_jwt(ctx: &AppContext, matches: &ArgMatches) -> Result<(), ApiError> {
+    let private_claim = PrivateClaim::new(&ctx.config, value(matches, "uid").into(), value(matches, "email").into());
+    println!("{}", create_jwt(&ctx.config, private_claim)?);
+    Ok(())
+}
+
+fn migrate(ctx: &AppContext, matches: &ArgMatches) -> Result<(), ApiError> {
+    let db = &*ctx.db;
+    match matches.subcommand() {
+        ("status", _) => {
+            for migration in migrations::status(db)? {
+                let applied_at = migration
+                    .applied_at
+                    .map(|at| at.to_rfc3339())
+                    .unwrap_or_else(|| "pending".into());
+                println!("{:>4} {:<30} {}", migration.version, migration.name, applied_at);
+            }
+        }
+        ("rollback", Some(rollback)) => {
+            let steps = value(rollback, "steps");
+            let steps = steps
+                .parse()
+                .map_err(|_| ApiError::BadRequest(format!("Invalid number of steps: {}", steps)))?;
+            let versions = migrations::rollback(db, steps)?;
+            println!("Rolled back {} migration(s): {:?}", versions.len(), versions);
+        }
+        _ => {
+            let versions = migrations::run(db)?;
+            println!("Applied {} migration(s): {:?}", versions.len(), versions);
+        }
+    }
+    Ok(())
+}
+
+fn seed(ctx: &AppContext, matches: &ArgMatches) -> Result<(), ApiError> {
+    let uid = value(matches, "uid");
+    let password = &read_password()?;
+    let email = format!("{}@example.com", uid);
+    validate_password(&ctx.config, password, uid, &email)?;
+
+    let user = doc! {
+        "uid": uid,
+        "email": email,
+        "password": hash(&ctx.config, password),
+        "name": "Demo User",
+        "phone": "",
+        "roles": ["user"],
+        "created_at": Utc::now(),
+        "updated_at": Utc::now()
+    };
+    database::create(&*ctx.db, "users", user)?;
+
+    register_defaults(&*ctx.db)?;
+    for (ticker, amount) in &[("BTC", "1.5"), ("ETH", "20")] {
+        let asset = enabled_asset(&*ctx.db, ticker)?;
+        let amount = Amount::parse(amount, asset.decimals)?;
+        create_holding(&*ctx.db, uid, &asset.name, ticker, amount, "cli")?;
+    }
+    println!("Seeded user {} with coin holdings", uid);
+    Ok(())
+}
+
+fn ping(ctx: &AppContext) -> Result<(), ApiError> {
+    let started = Utc::now();
+    ctx.db.ping()?;
+    let elapsed = Utc::now() - started;
+    println!("MongoDB is reachable ({} ms)", elapsed.num_milliseconds());
+    Ok(())
+}
+
+fn reconcile_balances(ctx: &AppContext) -> Result<(), ApiError> {
+    let drifts = reconcile(&*ctx.db)?;
+    for drift in &drifts {
+        println!(
+            "{} {}: balance {} but ledger {}",
+            drift.uid, drift.ticker, drift.balance, drift.ledger
+        );
+    }
+    if drifts.is_empty() {
+        println!("All balances match the ledger");
+        Ok(())
+    } else {
+        Err(ApiError::Conflict(format!("{} balance(s) drift from the ledger", drifts.len())))
+    }
+}
+
+fn recover(ctx: &AppContext) -> Result<(), ApiError> {
+    let recovered = recover_transfers(&*ctx.db, stale_cutoff())?;
+    println!("Recovered {} interrupted transfer(s)", recovered);
+    Ok(())
+}
+
+fn snapshot(ctx: &AppContext) -> Result<(), ApiError> {
+    let written = snapshot_portfolios(ctx, Utc::now())?;
+    println!("Took {} portfolio snapshot(s)", written);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::tests::{unique_id, Fixtures};
+    use crate::tests::helpers::tests::test_context;
+
+    #[test]
+    fn it_parses_admin_subcommands() {
+        let matches = app()
+            .get_matches_from_safe(vec!["rust-mongodb", "mint-jwt", "--uid", "bsjung"])
+            .unwrap();
+        let (name, sub) = matches.subcommand();
+        assert_eq!(name, "mint-jwt");
+        assert_eq!(value(sub.unwrap(), "uid"), "bsjung");
+        assert_eq!(value(sub.unwrap(), "email"), "");
+    }
+
+    #[test]
+    fn it_does_not_take_passwords_as_arguments() {
+        let matches = app().get_matches_from_safe(vec![
+            "rust-mongodb", "create-admin", "--uid", "admin1", "--email", "admin1@example.com",
+            "--password", "secret",
+        ]);
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn it_reads_the_password_from_the_variable_before_stdin() {
+        let mut input = io::Cursor::new("from-stdin\n");
+        let password = read_password_from(Some("from-env".into()), &mut input).unwrap();
+        assert_eq!(password, "from-env");
+
+        let password = read_password_from(None, &mut input).unwrap();
+        assert_eq!(password, "from-stdin");
+    }
+
+    #[test]
+    fn it_requires_a_password() {
+        let error = read_password_from(None, &mut io::Cursor::new("\r\n")).unwrap_err();
+        assert!(error.to_string().contains("USER_PASSWORD"));
+        assert!(read_password_from(Some("".into()), &mut io::empty()).is_err());
+    }
+
+    #[test]
+    fn it_does_not_reset_the_password_of_an_unknown_user() {
+        let matches = app()
+            .get_matches_from_safe(vec!["rust-mongodb", "reset-password", "--uid", "nobody"])
+            .unwrap();
+        let error = run(&test_context(), "reset-password", matches.subcommand_matches("reset-password").unwrap());
+        assert!(matches!(error, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn it_checks_a_reset_password_against_the_stored_email() {
+        let fixtures = Fixtures::new(test_context());
+        let email = format!("{}@example.com", unique_id("zebrafinch"));
+        let user = fixtures.user().email(&email).create();
+        let ctx = fixtures.ctx();
+        let user = find_user(&*ctx.db, &user.uid).unwrap();
+        let local_part = email.split('@').next().unwrap();
+
+        assert!(set_password(&ctx, &user, &format!("{}-Tr0ub4dor&3", local_part)).is_err());
+        set_password(&ctx, &user, "Tr0ub4dor&3").unwrap();
+        assert_eq!(find_user(&*ctx.db, &user.uid).unwrap().password, hash(&ctx.config, "Tr0ub4dor&3"));
+    }
+
+    #[test]
+    fn it_defaults_rollback_to_one_step() {
+        let matches = app()
+            .get_matches_from_safe(vec!["rust-mongodb", "migrate", "rollback"])
+            .unwrap();
+        let migrate = matches.subcommand_matches("migrate").unwrap();
+        let rollback = migrate.subcommand_matches("rollback").unwrap();
+        assert_eq!(value(rollback, "steps"), "1");
+    }
+
+    #[test]
+    fn it_accepts_config_flags_before_the_subcommand() {
+        let matches = app()
+            .get_matches_from_safe(vec![
+                "rust-mongodb", "--config", "config.toml", "--set", "server=0.0.0.0:80",
+                "--set", "log_format=json", "serve",
+            ])
+            .unwrap();
+        assert_eq!(matches.value_of("config"), Some("config.toml"));
+        let values: Vec<&str> = matches.values_of("set").unwrap().collect();
+        assert_eq!(values, vec!["server=0.0.0.0:80", "log_format=json"]);
+    }
+}
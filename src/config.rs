@@ -1,6 +1,27 @@
+//! Layered configuration: defaults, then an optional TOML/YAML file,
+//! then environment variables (and `_FILE` secrets), then CLI flags.
+
 use dotenv::dotenv;
-use serde::Deserialize;
-use lazy_static::lazy_static;
+use serde::de::{self, Deserialize};
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::jobs::Schedule;
+
+/// Environment variable naming the config file when `--config` is not given
+const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+/// Suffix of variables whose value is read from the file they point to
+const FILE_SUFFIX: &str = "_file";
+
+/// Minimum length of the JWT and session signing keys
+const MIN_KEY_LENGTH: usize = 32;
+
+/// Minimum salt length accepted by argon2
+const MIN_SALT_LENGTH: usize = 8;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
@@ -14,36 +35,671 @@ pub struct Config {
     pub session_name: String,
     pub session_secure: bool,
     pub session_timeout: i64,
+    #[serde(default = "default_environment")]
+    pub environment: String,
+    #[serde(default = "default_password_min_length")]
+    pub password_min_length: usize,
+    #[serde(default)]
+    pub password_require_lowercase: bool,
+    #[serde(default)]
+    pub password_require_uppercase: bool,
+    #[serde(default)]
+    pub password_require_digit: bool,
+    #[serde(default)]
+    pub password_require_symbol: bool,
+    #[serde(default = "default_totp_issuer")]
+    pub totp_issuer: String,
+    #[serde(default = "default_mfa_pending_expiration")]
+    pub mfa_pending_expiration: i64,
+    #[serde(default = "default_mfa_max_failures")]
+    pub mfa_max_failures: i64,
+    #[serde(default = "default_mfa_lockout_minutes")]
+    pub mfa_lockout_minutes: i64,
+    #[serde(default = "default_health_timeout_ms")]
+    pub health_timeout_ms: u64,
+    #[serde(default = "default_log_format")]
+    pub log_format: String,
+    #[serde(default = "default_log_filters")]
+    pub log_filters: String,
+    #[serde(default = "default_trace_exporter")]
+    pub trace_exporter: String,
+    #[serde(default = "default_trace_file")]
+    pub trace_file: String,
+    #[serde(default = "default_cache_backend")]
+    pub cache_backend: String,
+    #[serde(default = "default_cache_url")]
+    pub cache_url: String,
+    #[serde(default = "default_cache_capacity")]
+    pub cache_capacity: usize,
+    #[serde(default = "default_cache_ttl_seconds")]
+    pub cache_ttl_seconds: u64,
+    #[serde(default = "default_price_source")]
+    pub price_source: String,
+    #[serde(default)]
+    pub price_file: String,
+    #[serde(default = "default_price_url")]
+    pub price_url: String,
+    #[serde(default = "default_price_ttl_seconds")]
+    pub price_ttl_seconds: u64,
+    #[serde(default = "default_currency")]
+    pub default_currency: String,
+    #[serde(default = "default_snapshot_schedule")]
+    pub snapshot_schedule: String,
+    #[serde(default = "default_transfer_recovery_schedule")]
+    pub transfer_recovery_schedule: String,
+    #[serde(default = "default_token_cleanup_schedule")]
+    pub token_cleanup_schedule: String,
+    #[serde(default = "default_session_purge_schedule")]
+    pub session_purge_schedule: String,
+    #[serde(default = "default_job_history_days")]
+    pub job_history_days: u64,
+}
+
+/// "development", "test" or "production"
+fn default_environment() -> String {
+    "development".into()
+}
+
+/// Minimum password length used when PASSWORD_MIN_LENGTH is not set
+fn default_password_min_length() -> usize {
+    8
+}
+
+/// Issuer shown in authenticator apps when TOTP_ISSUER is not set
+fn default_totp_issuer() -> String {
+    env!("CARGO_PKG_NAME").into()
+}
+
+/// Minutes a password-only login may wait for its second factor
+fn default_mfa_pending_expiration() -> i64 {
+    5
+}
+
+/// Wrong second factors that lock a user out of two-factor login
+fn default_mfa_max_failures() -> i64 {
+    5
+}
+
+/// Minutes a wrong second factor counts towards the lockout
+fn default_mfa_lockout_minutes() -> i64 {
+    15
 }
 
-// Throw the Config struct into a CONFIG lazy_static to avoid multiple processing
-lazy_static! {
-    pub static ref CONFIG: Config = get_config();
+/// Milliseconds the readiness probe waits for each dependency
+fn default_health_timeout_ms() -> u64 {
+    2000
 }
 
-/// Use envy to inject dotenv and env vars into the Config struct
-fn get_config() -> Config {
+/// "text" for human-readable lines, "json" for log pipelines
+fn default_log_format() -> String {
+    "text".into()
+}
+
+/// env_logger filter directives, overridden by RUST_LOG
+fn default_log_filters() -> String {
+    "info".into()
+}
+
+/// "none", "stdout" or "file"
+fn default_trace_exporter() -> String {
+    "none".into()
+}
+
+/// Where the "file" trace exporter appends spans
+fn default_trace_file() -> String {
+    "traces.jsonl".into()
+}
+
+/// "memory" for a per-instance cache, "redis" to share it between instances
+fn default_cache_backend() -> String {
+    "memory".into()
+}
+
+/// Server of the "redis" cache backend
+fn default_cache_url() -> String {
+    "redis://127.0.0.1:6379".into()
+}
+
+/// Entries kept by the "memory" cache backend
+fn default_cache_capacity() -> usize {
+    10_000
+}
+
+/// Seconds before a cached value is loaded again
+fn default_cache_ttl_seconds() -> u64 {
+    60
+}
+
+/// "static" for quotes from `price_file`, "http" to ask the service at `price_url`
+fn default_price_source() -> String {
+    "static".into()
+}
+
+/// Service of the "http" price source
+fn default_price_url() -> String {
+    "http://127.0.0.1:8090".into()
+}
+
+/// Seconds a quote is reused before it is asked for again
+fn default_price_ttl_seconds() -> u64 {
+    30
+}
+
+/// Fiat currency of portfolio valuations when the request names none
+fn default_currency() -> String {
+    "USD".into()
+}
+
+/// Cron schedule of portfolio snapshots, empty to take none
+fn default_snapshot_schedule() -> String {
+    "0 * * * *".into()
+}
+
+/// Cron schedule of the recovery of interrupted transfers, empty to leave it to the CLI
+fn default_transfer_recovery_schedule() -> String {
+    "*/10 * * * *".into()
+}
+
+/// Cron schedule of the removal of expired sessions and MFA attempts, empty to leave it to the TTL indexes
+fn default_token_cleanup_schedule() -> String {
+    "*/15 * * * *".into()
+}
+
+/// Cron schedule of the purge of sessions revoked by a password change or a deleted user
+fn default_session_purge_schedule() -> String {
+    "30 * * * *".into()
+}
+
+/// Days job runs are kept in the run history
+fn default_job_history_days() -> u64 {
+    30
+}
+
+/// Settings from one source, keyed by lowercase field name
+type Layer = BTreeMap<String, String>;
+
+/// Why the configuration could not be loaded
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    File(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::File(error) => write!(f, "{}", error),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Load and validate the configuration from all layers.
+/// `file` and `values` come from the `--config` and `--set key=value` flags.
+pub fn load(file: Option<&str>, values: &[&str]) -> Result<Config, ConfigError> {
     dotenv().ok();
 
-    match envy::from_env::<Config>() {
-        Ok(config) => config,
-        Err(error) => panic!("Configuration Error: {:#?}", error),
+    let file_layer = match file.map(String::from).or_else(|| env::var(CONFIG_FILE_ENV).ok()) {
+        Some(path) => read_file_layer(Path::new(&path))?,
+        None => Layer::new(),
+    };
+    let env_layer = env_layer(env::vars())?;
+    let cli_layer = cli_layer(values)?;
+
+    build(vec![file_layer, env_layer, cli_layer])
+}
+
+/// Merge the layers, later ones winning, then deserialize and validate
+fn build(layers: Vec<Layer>) -> Result<Config, ConfigError> {
+    let merged = layers.into_iter().flatten().collect::<Layer>();
+    let config = envy::from_iter::<_, Config>(merged).map_err(|error| match error {
+        envy::Error::MissingValue(field) => ConfigError::Invalid(vec![format!(
+            "{} is required, set {} or {}{} in the environment or `{}` in the config file",
+            field,
+            field.to_uppercase(),
+            field.to_uppercase(),
+            FILE_SUFFIX.to_uppercase(),
+            field
+        )]),
+        envy::Error::Custom(message) => ConfigError::Invalid(vec![message]),
+    })?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Read a flat TOML or YAML file, chosen by its extension
+fn read_file_layer(path: &Path) -> Result<Layer, ConfigError> {
+    let name = path.display();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ConfigError::File(format!("cannot read config file {}: {}", name, e)))?;
+
+    let values: BTreeMap<String, serde_json::Value> =
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+            _ => Err("expected a .toml, .yaml or .yml extension".into()),
+        }
+        .map_err(|e| ConfigError::File(format!("cannot parse config file {}: {}", name, e)))?;
+
+    let mut layer = Layer::new();
+    let mut problems = Vec::new();
+    for (key, value) in values {
+        let key = key.to_lowercase();
+        if !is_field(&key) {
+            problems.push(format!("unknown setting `{}` in {}", key, name));
+            continue;
+        }
+        match value {
+            serde_json::Value::Null => {}
+            serde_json::Value::String(value) => {
+                layer.insert(key, value);
+            }
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {
+                layer.insert(key, value.to_string());
+            }
+            _ => problems.push(format!("`{}` in {} must be a single value", key, name)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(layer)
+    } else {
+        Err(ConfigError::Invalid(problems))
     }
 }
 
+/// Pick the config fields out of the environment,
+/// reading `NAME_FILE` variables from the file they point to
+fn env_layer(vars: impl Iterator<Item = (String, String)>) -> Result<Layer, ConfigError> {
+    let mut layer = Layer::new();
+    let mut from_files = Layer::new();
+    let mut problems = Vec::new();
+
+    for (name, value) in vars {
+        let key = name.to_lowercase();
+        if is_field(&key) {
+            layer.insert(key, value);
+        } else if let Some(field) = key.strip_suffix(FILE_SUFFIX).filter(|field| is_field(field)) {
+            match fs::read_to_string(&value) {
+                Ok(contents) => {
+                    from_files.insert(field.into(), contents.trim_end_matches(&['\r', '\n'][..]).into());
+                }
+                Err(e) => problems.push(format!("cannot read {} from {}: {}", name, value, e)),
+            }
+        }
+    }
+
+    for (field, value) in from_files {
+        let name = field.to_uppercase();
+        match layer.entry(field) {
+            Entry::Occupied(_) => problems.push(format!(
+                "set either {} or {}{}, not both",
+                name,
+                name,
+                FILE_SUFFIX.to_uppercase()
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(layer)
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+/// Parse `--set key=value` flags
+fn cli_layer(values: &[&str]) -> Result<Layer, ConfigError> {
+    let mut layer = Layer::new();
+    let mut problems = Vec::new();
+
+    for value in values {
+        match value.split_once('=') {
+            Some((key, value)) if is_field(&key.to_lowercase()) => {
+                layer.insert(key.to_lowercase(), value.into());
+            }
+            Some((key, _)) => problems.push(format!("unknown setting `{}` in --set", key)),
+            None => problems.push(format!("--set expects key=value, got `{}`", value)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(layer)
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+impl Config {
+    /// Check the values that deserialize fine but would fail or be unsafe at runtime
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.rsplit(':').next().and_then(|port| port.parse::<u16>().ok()).is_none() {
+            problems.push(format!("server must be host:port, got `{}`", self.server));
+        }
+        if !self.database_url.starts_with("mongodb://") && !self.database_url.starts_with("mongodb+srv://") {
+            problems.push("database_url must start with mongodb:// or mongodb+srv://".into());
+        }
+        if self.jwt_key.len() < MIN_KEY_LENGTH {
+            problems.push(format!(
+                "jwt_key must be at least {} characters, got {}",
+                MIN_KEY_LENGTH,
+                self.jwt_key.len()
+            ));
+        }
+        if self.session_key.len() < MIN_KEY_LENGTH {
+            problems.push(format!(
+                "session_key must be at least {} characters, got {}",
+                MIN_KEY_LENGTH,
+                self.session_key.len()
+            ));
+        }
+        if self.auth_salt.len() < MIN_SALT_LENGTH {
+            problems.push(format!(
+                "auth_salt must be at least {} characters, got {}",
+                MIN_SALT_LENGTH,
+                self.auth_salt.len()
+            ));
+        }
+        for (name, value) in &[
+            ("jwt_expiration", self.jwt_expiration),
+            ("session_timeout", self.session_timeout),
+            ("mfa_pending_expiration", self.mfa_pending_expiration),
+            ("mfa_max_failures", self.mfa_max_failures),
+            ("mfa_lockout_minutes", self.mfa_lockout_minutes),
+        ] {
+            if *value <= 0 {
+                problems.push(format!("{} must be positive, got {}", name, value));
+            }
+        }
+        if self.password_min_length < 6 {
+            problems.push(format!(
+                "password_min_length must be at least 6, got {}",
+                self.password_min_length
+            ));
+        }
+        for (name, value, allowed) in &[
+            ("environment", &self.environment, &["development", "test", "production"][..]),
+            ("log_format", &self.log_format, &["text", "json"][..]),
+            ("trace_exporter", &self.trace_exporter, &["none", "stdout", "file"][..]),
+            ("cache_backend", &self.cache_backend, &["memory", "redis"][..]),
+            ("price_source", &self.price_source, &["static", "http"][..]),
+        ] {
+            if !allowed.contains(&value.as_str()) {
+                problems.push(format!(
+                    "{} must be one of {}, got `{}`",
+                    name,
+                    allowed.join(", "),
+                    value
+                ));
+            }
+        }
+        if self.cache_capacity == 0 {
+            problems.push("cache_capacity must be positive".into());
+        }
+        if self.price_source == "http" && !self.price_url.starts_with("http://") && !self.price_url.starts_with("https://") {
+            problems.push(format!("price_url must start with http:// or https://, got `{}`", self.price_url));
+        }
+        if self.default_currency.len() != 3 || !self.default_currency.chars().all(|c| c.is_ascii_uppercase()) {
+            problems.push(format!(
+                "default_currency must be a three letter code such as USD, got `{}`",
+                self.default_currency
+            ));
+        }
+        for (name, schedule) in &[
+            ("snapshot_schedule", &self.snapshot_schedule),
+            ("transfer_recovery_schedule", &self.transfer_recovery_schedule),
+            ("token_cleanup_schedule", &self.token_cleanup_schedule),
+            ("session_purge_schedule", &self.session_purge_schedule),
+        ] {
+            if schedule.trim().is_empty() {
+                continue;
+            }
+            if let Err(e) = Schedule::parse(schedule) {
+                problems.push(format!("{} is invalid: {}", name, e));
+            }
+        }
+        if self.job_history_days == 0 {
+            problems.push("job_history_days must be positive".into());
+        }
+        if self.environment == "production" && !self.session_secure {
+            problems.push("session_secure must be true in production".into());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+/// Whether a key names a Config field
+fn is_field(key: &str) -> bool {
+    field_names().contains(&key)
+}
+
+/// Names of the Config fields, as seen by its Deserialize impl
+fn field_names() -> &'static [&'static str] {
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> de::Deserializer<'de> for Fields<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("expected a struct"))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = Config::deserialize(Fields(&mut fields));
+    fields
+}
+
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
+    use crate::tests::helpers::tests::TempFile;
+
+    /// Config built from the fixed `required` values alone, without the environment or `.env`
+    pub fn fixed_config() -> Config {
+        build(vec![required()]).expect("Test configuration is invalid")
+    }
 
     #[test]
     fn it_gets_a_config() {
-        let config = get_config();
+        let file = TempFile::new("config.toml", "server = \"0.0.0.0:8080\"\n");
+        let values: Vec<String> = required()
+            .into_iter()
+            .filter(|(key, _)| key != "server")
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        let config = load(Some(file.path()), &values).unwrap();
         assert_ne!(config.server, "".to_string());
     }
 
+    fn layer(values: &[(&str, &str)]) -> Layer {
+        values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn required() -> Layer {
+        layer(&[
+            ("server", "127.0.0.1:3000"),
+            ("database", "rust-mongodb"),
+            ("database_url", "mongodb://localhost:27017"),
+            ("jwt_expiration", "24"),
+            ("jwt_key", "0123456789abcdef0123456789abcdef"),
+            ("auth_salt", "saltsaltsalt"),
+            ("session_key", "fedcba9876543210fedcba9876543210"),
+            ("session_name", "auth"),
+            ("session_secure", "false"),
+            ("session_timeout", "20"),
+        ])
+    }
+
     #[test]
-    fn it_gets_a_config_from_the_lazy_static() {
-        let config = &CONFIG;
-        assert_ne!(config.server, "".to_string());
+    fn it_applies_later_layers_over_earlier_ones() {
+        let config = build(vec![
+            required(),
+            layer(&[("server", "0.0.0.0:8080"), ("log_format", "json")]),
+            layer(&[("server", "0.0.0.0:9090")]),
+        ])
+        .unwrap();
+        assert_eq!(config.server, "0.0.0.0:9090");
+        assert_eq!(config.log_format, "json");
+        assert_eq!(config.password_min_length, 8);
+    }
+
+    #[test]
+    fn it_reads_toml_and_yaml_files() {
+        let toml = TempFile::new("config.toml", "server = \"0.0.0.0:8080\"\njwt_expiration = 12\nsession_secure = true\n");
+        let file = read_file_layer(Path::new(toml.path())).unwrap();
+        assert_eq!(file["server"], "0.0.0.0:8080");
+        assert_eq!(file["jwt_expiration"], "12");
+        assert_eq!(file["session_secure"], "true");
+
+        let yaml = TempFile::new("config.yaml", "SERVER: 0.0.0.0:8080\nsession_timeout: 30\n");
+        let file = read_file_layer(Path::new(yaml.path())).unwrap();
+        assert_eq!(file["server"], "0.0.0.0:8080");
+        assert_eq!(file["session_timeout"], "30");
+    }
+
+    #[test]
+    fn it_rejects_unknown_settings_in_files() {
+        let toml = TempFile::new("typo.toml", "jwt_kye = \"secret\"\n");
+        let error = read_file_layer(Path::new(toml.path())).unwrap_err();
+        assert!(error.to_string().contains("unknown setting `jwt_kye`"));
+    }
+
+    #[test]
+    fn it_reads_secrets_from_file_variables() {
+        let secret = TempFile::new("jwt_key", "from-a-mounted-secret\n");
+        let vars = vec![
+            ("JWT_KEY_FILE".to_string(), secret.path().to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        let env = env_layer(vars.into_iter()).unwrap();
+        assert_eq!(env["jwt_key"], "from-a-mounted-secret");
+        assert!(!env.contains_key("path"));
+    }
+
+    #[test]
+    fn it_rejects_a_variable_set_both_directly_and_from_a_file() {
+        let secret = TempFile::new("session_key", "secret");
+        let vars = vec![
+            ("SESSION_KEY".to_string(), "secret".to_string()),
+            ("SESSION_KEY_FILE".to_string(), secret.path().to_string()),
+        ];
+        let error = env_layer(vars.into_iter()).unwrap_err();
+        assert_eq!(
+            error,
+            ConfigError::Invalid(vec!["set either SESSION_KEY or SESSION_KEY_FILE, not both".into()])
+        );
+    }
+
+    #[test]
+    fn it_parses_cli_overrides() {
+        let cli = cli_layer(&["SERVER=0.0.0.0:80"]).unwrap();
+        assert_eq!(cli["server"], "0.0.0.0:80");
+        assert!(cli_layer(&["server"]).is_err());
+        assert!(cli_layer(&["nope=1"]).is_err());
+    }
+
+    #[test]
+    fn it_explains_missing_and_malformed_values() {
+        let mut values = required();
+        values.remove("jwt_key");
+        let error = build(vec![values]).unwrap_err().to_string();
+        assert!(error.contains("jwt_key is required, set JWT_KEY or JWT_KEY_FILE"));
+
+        let error = build(vec![required(), layer(&[("session_timeout", "soon")])]).unwrap_err();
+        assert!(error.to_string().contains("'soon'"));
+    }
+
+    #[test]
+    fn it_validates_values() {
+        let error = build(vec![
+            required(),
+            layer(&[("jwt_key", "short"), ("environment", "production")]),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigError::Invalid(vec![
+                "jwt_key must be at least 32 characters, got 5".into(),
+                "session_secure must be true in production".into(),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_validates_price_settings() {
+        let error = build(vec![
+            required(),
+            layer(&[("price_source", "http"), ("price_url", "ftp://prices"), ("default_currency", "usd")]),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigError::Invalid(vec![
+                "price_url must start with http:// or https://, got `ftp://prices`".into(),
+                "default_currency must be a three letter code such as USD, got `usd`".into(),
+            ])
+        );
+
+        let config = build(vec![required(), layer(&[("price_source", "http"), ("price_url", "https://prices")])]);
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn it_validates_job_schedules() {
+        let error = build(vec![
+            required(),
+            layer(&[("snapshot_schedule", "every hour"), ("transfer_recovery_schedule", ""), ("job_history_days", "0")]),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            ConfigError::Invalid(vec![
+                "snapshot_schedule is invalid: `every hour` must have 5 fields, got 2".into(),
+                "job_history_days must be positive".into(),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_knows_the_config_fields() {
+        assert!(is_field("jwt_key"));
+        assert!(is_field("trace_file"));
+        assert!(!is_field("path"));
     }
 }
@@ -0,0 +1,50 @@
+//! Application context: configuration and service handles shared by
+//! handlers and middleware, injected with `web::Data<AppContext>`.
+
+use mongodb::Client;
+use std::sync::Arc;
+
+use crate::cache::{self, Cache};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::prices::{self, PriceSource};
+use crate::store::{MongoStore, Store};
+
+pub struct AppContext {
+    pub config: Config,
+    pub db: Arc<dyn Store>,
+    pub cache: Box<dyn Cache>,
+    pub prices: Box<dyn PriceSource>,
+}
+
+impl AppContext {
+    /// Connect to MongoDB, select the configured database and create the cache
+    pub fn new(config: Config) -> Result<Self, ApiError> {
+        let db = Client::with_uri_str(&config.database_url)
+            .map_err(ApiError::DBError)?
+            .database(&config.database);
+        Self::with_store(config, Arc::new(MongoStore::new(db)))
+    }
+
+    /// Context on the given store, such as an in-memory one for tests
+    pub fn with_store(config: Config, db: Arc<dyn Store>) -> Result<Self, ApiError> {
+        let cache = cache::from_config(&config)?;
+        let prices = prices::from_config(&config)?;
+        Ok(AppContext { config, db, cache, prices })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    #[test]
+    fn it_gives_each_test_context_its_own_store() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let other = test_context();
+        assert!(fixtures.ctx().db.find_one("users", doc! { "uid": &user.uid }).unwrap().is_some());
+        assert!(other.db.find_one("users", doc! { "uid": &user.uid }).unwrap().is_none());
+    }
+}
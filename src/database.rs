@@ -1,51 +1,269 @@
-use lazy_static::lazy_static;
-use mongodb::{Client, Collection};
-use crate::config::CONFIG;
+use bson::Bson;
+
 use crate::errors::ApiError;
+use crate::store::{FindOptions, Store};
 
-lazy_static! {
-    pub static ref MONGO: Client = create_mongo_client();
+/// An index the application relies on
+pub struct IndexSpec {
+    pub collection: &'static str,
+    pub name: &'static str,
+    pub keys: bson::Document,
+    pub unique: bool,
+    pub expire_after_seconds: Option<i64>,
 }
 
-fn create_mongo_client() -> Client {
-    Client::with_uri_str(&CONFIG.database_url)
-        .expect("Failed to initialize standalone client.")
+impl IndexSpec {
+    pub fn to_document(&self) -> bson::Document {
+        let mut index = doc! {
+            "key": self.keys.clone(),
+            "name": self.name,
+        };
+        if self.unique {
+            index.insert("unique", true);
+        }
+        if let Some(seconds) = self.expire_after_seconds {
+            index.insert("expireAfterSeconds", seconds);
+        }
+        index
+    }
 }
 
-pub fn collection(coll_name: &str) -> Collection {
-    MONGO.database(&CONFIG.database).collection(coll_name)
+/// Declare every index here; `ensure_indexes` creates the missing ones at startup.
+/// TTL indexes (expire_after_seconds) index a date field holding the expiry time.
+pub fn indexes() -> Vec<IndexSpec> {
+    vec![
+        IndexSpec {
+            collection: "users",
+            name: "uid_unique",
+            keys: doc! { "uid": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "users",
+            name: "email_unique",
+            keys: doc! { "email": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "coins",
+            name: "uid_ticker_unique",
+            keys: doc! { "uid": 1, "ticker": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "coin_transactions",
+            name: "uid_ticker_created_at",
+            keys: doc! { "uid": 1, "ticker": 1, "created_at": -1 },
+            unique: false,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "assets",
+            name: "ticker_unique",
+            keys: doc! { "ticker": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "portfolio_snapshots",
+            name: "uid_taken_at_unique",
+            keys: doc! { "uid": 1, "taken_at": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "job_runs",
+            name: "job_started_at",
+            keys: doc! { "job": 1, "started_at": -1 },
+            unique: false,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "job_runs",
+            name: "expires_at_ttl",
+            keys: doc! { "expires_at": 1 },
+            unique: false,
+            expire_after_seconds: Some(0),
+        },
+        IndexSpec {
+            collection: "sessions",
+            name: "uid",
+            keys: doc! { "uid": 1 },
+            unique: false,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "sessions",
+            name: "expires_at_ttl",
+            keys: doc! { "expires_at": 1 },
+            unique: false,
+            expire_after_seconds: Some(0),
+        },
+        IndexSpec {
+            collection: "mfa_attempts",
+            name: "uid",
+            keys: doc! { "uid": 1 },
+            unique: false,
+            expire_after_seconds: None,
+        },
+        IndexSpec {
+            collection: "mfa_attempts",
+            name: "expires_at_ttl",
+            keys: doc! { "expires_at": 1 },
+            unique: false,
+            expire_after_seconds: Some(0),
+        },
+        IndexSpec {
+            collection: "transfers",
+            name: "from_idempotency_key",
+            keys: doc! { "from": 1, "idempotency_key": 1 },
+            unique: true,
+            expire_after_seconds: None,
+        },
+    ]
 }
 
-/// Insert User with data
-pub fn create(table : &str, data : bson::Document) -> Result<String, ApiError> {
-    let coll = collection(table);
-    let insert_result = coll.insert_one(data, None);
+/// Create the declared indexes.
+pub fn ensure_indexes(db: &dyn Store) -> Result<(), ApiError> {
+    let mut by_collection: Vec<(&str, Vec<IndexSpec>)> = Vec::new();
+    for index in indexes() {
+        match by_collection.iter_mut().find(|(name, _)| *name == index.collection) {
+            Some((_, specs)) => specs.push(index),
+            None => by_collection.push((index.collection, vec![index])),
+        }
+    }
 
-    match insert_result {
-        Ok(result) => Ok(result.inserted_id.to_string()),
-        Err(e) => return Err(e).map_err(ApiError::DBError)
+    for (collection, specs) in by_collection {
+        if let Err(e) = db.create_indexes(collection, &specs) {
+            return Err(duplicate_keys_error(db, collection, &specs)?.unwrap_or(e));
+        }
     }
+    Ok(())
 }
 
-/// Update User with filter and set query.
-pub fn update(table : &str, filter : bson::Document, set : bson::Document) -> Result<String, ApiError> {
-    let coll = collection(table);
-    let update_result = coll.update_one(filter, set, None);
+/// Most duplicate keys listed per index by `duplicate_keys_error`
+const MAX_REPORTED_DUPLICATES: usize = 20;
 
-    match update_result {
-        Ok(_) => Ok("ok".to_string()),
-        Err(e) => return Err(e).map_err(ApiError::DBError)
+/// Name the keys stored more than once in `collection`, for the unique indexes
+/// of `specs` it breaks, so duplicates left from before the index can be fixed
+fn duplicate_keys_error(db: &dyn Store, collection: &str, specs: &[IndexSpec]) -> Result<Option<ApiError>, ApiError> {
+    let documents = db.find(collection, doc! {}, FindOptions::default())?;
+    let mut problems = Vec::new();
+    for spec in specs.iter().filter(|spec| spec.unique) {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for document in &documents {
+            let key = spec
+                .keys
+                .keys()
+                .map(|field| format!("{}={}", field, document.get(field).unwrap_or(&Bson::Null)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match counts.iter_mut().find(|(seen, _)| *seen == key) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((key, 1)),
+            }
+        }
+        let duplicates: Vec<String> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(key, count)| format!("{} ({} records)", key, count))
+            .collect();
+        if duplicates.is_empty() {
+            continue;
+        }
+        let more = duplicates.len().saturating_sub(MAX_REPORTED_DUPLICATES);
+        let mut listed = duplicates.into_iter().take(MAX_REPORTED_DUPLICATES).collect::<Vec<_>>().join("; ");
+        if more > 0 {
+            listed.push_str(&format!("; and {} more", more));
+        }
+        problems.push(format!("{}.{} is broken by {}", collection, spec.name, listed));
+    }
+    if problems.is_empty() {
+        return Ok(None);
     }
+    Ok(Some(ApiError::Conflict(format!(
+        "Cannot create unique indexes, remove or merge the duplicate records first: {}",
+        problems.join(". ")
+    ))))
+}
+
+/// Insert User with data
+pub fn create(db: &dyn Store, table : &str, data : bson::Document) -> Result<String, ApiError> {
+    let inserted_id = db.insert_one(table, data)?;
+    Ok(inserted_id.to_string())
+}
+
+/// Update User with filter and set query.
+pub fn update(db: &dyn Store, table : &str, filter : bson::Document, set : bson::Document) -> Result<String, ApiError> {
+    db.update_one(table, filter, set)?;
+    Ok("ok".to_string())
 }
 
 /// Delete User with filter
-pub fn delete(table : &str, filter : bson::Document) -> Result<String, ApiError> {
-    let coll = collection(table);
-    let delete_result = coll.delete_many(filter, None);
+pub fn delete(db: &dyn Store, table : &str, filter : bson::Document) -> Result<String, ApiError> {
+    db.delete_many(table, filter)?;
+    Ok("ok".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
 
-    match delete_result {
-        Ok(_) => Ok("ok".to_string()),
-        Err(e) => return Err(e).map_err(ApiError::DBError)
+    #[test]
+    fn it_declares_unique_user_indexes() {
+        let unique: Vec<&str> = indexes()
+            .iter()
+            .filter(|index| index.collection == "users" && index.unique)
+            .map(|index| index.name)
+            .collect();
+        assert_eq!(unique, vec!["uid_unique", "email_unique"]);
+    }
+
+    #[test]
+    fn it_lists_duplicate_users_that_break_the_unique_indexes() {
+        let db = MemoryStore::new();
+        db.insert_one("users", doc! { "uid": "first", "email": "a@example.com" }).unwrap();
+        db.insert_one("users", doc! { "uid": "second", "email": "a@example.com" }).unwrap();
+        db.insert_one("users", doc! { "uid": "second", "email": "b@example.com" }).unwrap();
+
+        match ensure_indexes(&db) {
+            Err(ApiError::Conflict(message)) => {
+                assert!(message.contains("users.uid_unique is broken by uid=\"second\" (2 records)"), "{}", message);
+                assert!(message.contains("users.email_unique is broken by email=\"a@example.com\" (2 records)"), "{}", message);
+            }
+            other => panic!("Expected the duplicate keys, got {:?}", other.err()),
+        }
     }
-}
 
+    #[test]
+    fn it_expires_job_runs_at_their_expiry_time() {
+        let ttl = indexes().into_iter().find(|index| index.name == "expires_at_ttl").unwrap();
+        assert_eq!(ttl.collection, "job_runs");
+        assert_eq!(ttl.to_document().get_i64("expireAfterSeconds").unwrap(), 0);
+    }
+
+    #[test]
+    fn it_expires_sessions_and_mfa_attempts_at_their_expiry_time() {
+        for collection in &["sessions", "mfa_attempts"] {
+            let ttl = indexes()
+                .into_iter()
+                .find(|index| index.collection == *collection && index.name == "expires_at_ttl")
+                .unwrap_or_else(|| panic!("{} has no TTL index", collection));
+            assert_eq!(ttl.keys, doc! { "expires_at": 1 });
+            assert_eq!(ttl.to_document().get_i64("expireAfterSeconds").unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn it_surfaces_duplicates_as_conflicts() {
+        let db = MemoryStore::new();
+        ensure_indexes(&db).unwrap();
+        create(&db, "users", doc! { "uid": "bsjung", "email": "a@example.com" }).unwrap();
+        let duplicate = create(&db, "users", doc! { "uid": "bsjung", "email": "b@example.com" });
+        assert!(matches!(duplicate, Err(ApiError::Conflict(_))));
+    }
+}
@@ -3,6 +3,7 @@ use actix_web::{
     http::StatusCode,
     HttpResponse,
 };
+use crate::middleware::request_id::current_request_id;
 use derive_more::Display;
 
 #[derive(Debug, Display)]
@@ -13,8 +14,12 @@ pub enum ApiError {
     CacheError(String),
     CannotDecodeJwtToken(String),
     CannotEncodeJwtToken(String),
+    Conflict(String),
+    Forbidden(String),
     InternalServerError(String),
     NotFound(String),
+    PriceSourceError(String),
+    TooManyRequests(String),
     DBError(mongodb::error::Error),
     #[display(fmt = "")]
     ValidationError(Vec<String>),
@@ -25,6 +30,8 @@ pub enum ApiError {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ErrorResponse {
     errors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 /// Automatically convert ApiErrors to external Response Errors
@@ -34,9 +41,21 @@ impl ResponseError for ApiError {
             ApiError::BadRequest(error) => {
                 HttpResponse::BadRequest().json::<ErrorResponse>(error.into())
             }
+            ApiError::Conflict(message) => {
+                HttpResponse::Conflict().json::<ErrorResponse>(message.into())
+            }
+            ApiError::Forbidden(message) => {
+                HttpResponse::Forbidden().json::<ErrorResponse>(message.into())
+            }
             ApiError::NotFound(message) => {
                 HttpResponse::NotFound().json::<ErrorResponse>(message.into())
             }
+            ApiError::PriceSourceError(message) => {
+                HttpResponse::BadGateway().json::<ErrorResponse>(message.into())
+            }
+            ApiError::TooManyRequests(message) => {
+                HttpResponse::TooManyRequests().json::<ErrorResponse>(message.into())
+            }
             ApiError::ValidationError(errors) => {
                 HttpResponse::UnprocessableEntity().json::<ErrorResponse>(errors.to_vec().into())
             }
@@ -53,6 +72,7 @@ impl From<&String> for ErrorResponse {
     fn from(error: &String) -> Self {
         ErrorResponse {
             errors: vec![error.into()],
+            request_id: current_request_id(),
         }
     }
 }
@@ -60,7 +80,10 @@ impl From<&String> for ErrorResponse {
 /// Utility to make transforming a vector of strings into an ErrorResponse
 impl From<Vec<String>> for ErrorResponse {
     fn from(errors: Vec<String>) -> Self {
-        ErrorResponse { errors }
+        ErrorResponse {
+            errors,
+            request_id: current_request_id(),
+        }
     }
 }
 
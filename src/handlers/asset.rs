@@ -0,0 +1,105 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, HttpResponse, Json, Path};
+use serde::Serialize;
+use validator::Validate;
+
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::handlers::auth::{current_admin, current_claim};
+use crate::models::asset::{self as catalog, Asset, AssetChanges};
+use crate::telemetry::traced;
+use crate::utils::{respond_json, respond_ok};
+use crate::validate::validate;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct AssetRequest {
+    #[validate(length(min = 1, max = 10, message = "ticker must be 1 to 10 characters"))]
+    pub ticker: String,
+
+    #[validate(length(min = 1, message = "name is required"))]
+    pub name: String,
+
+    pub decimals: u32,
+
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AssetPatchRequest {
+    pub name: Option<String>,
+    pub decimals: Option<u32>,
+    pub enabled: Option<bool>,
+}
+
+/// List the asset catalog
+pub async fn get_assets(ctx: Data<AppContext>, id: Identity) -> Result<Json<Vec<Asset>>, ApiError> {
+    traced("handler.get_assets", async move {
+        current_claim(&ctx, &id)?;
+        respond_json(catalog::get_assets(&*ctx.db)?)
+    })
+    .await
+}
+
+/// Get an asset by ticker
+pub async fn get_asset(ctx: Data<AppContext>, id: Identity, ticker: Path<String>) -> Result<Json<Asset>, ApiError> {
+    traced("handler.get_asset", async move {
+        current_claim(&ctx, &id)?;
+        respond_json(catalog::find_asset(&*ctx.db, &ticker)?)
+    })
+    .await
+}
+
+/// Add an asset to the catalog, admins only
+pub async fn create_asset(
+    ctx: Data<AppContext>,
+    id: Identity,
+    params: Json<AssetRequest>,
+) -> Result<Json<Asset>, ApiError> {
+    traced("handler.create_asset", async move {
+        validate(&params)?;
+        current_admin(&ctx, &id)?;
+        let asset = Asset {
+            ticker: params.ticker.clone(),
+            name: params.name.clone(),
+            decimals: params.decimals,
+            enabled: params.enabled,
+        };
+        respond_json(catalog::create_asset(&*ctx.db, &asset)?)
+    })
+    .await
+}
+
+/// Rename, rescale, enable or disable an asset, admins only
+pub async fn patch_asset(
+    ctx: Data<AppContext>,
+    id: Identity,
+    ticker: Path<String>,
+    params: Json<AssetPatchRequest>,
+) -> Result<Json<Asset>, ApiError> {
+    traced("handler.patch_asset", async move {
+        current_admin(&ctx, &id)?;
+        let params = params.into_inner();
+        let changes = AssetChanges {
+            name: params.name,
+            decimals: params.decimals,
+            enabled: params.enabled,
+        };
+        respond_json(catalog::update_asset(&*ctx.db, &ticker, changes)?)
+    })
+    .await
+}
+
+/// Remove an asset nobody holds, admins only
+pub async fn delete_asset(ctx: Data<AppContext>, id: Identity, ticker: Path<String>) -> Result<HttpResponse, ApiError> {
+    traced("handler.delete_asset", async move {
+        current_admin(&ctx, &id)?;
+        catalog::delete_asset(&*ctx.db, &ticker)?;
+        respond_ok()
+    })
+    .await
+}
@@ -1,14 +1,18 @@
 use actix_identity::Identity;
-use actix_web::web::{HttpResponse, Json};
+use actix_web::web::{Data, HttpResponse, Json};
 use serde::Serialize;
 use validator::Validate;
 
-use crate::jwt::{create_jwt, hash, PrivateClaim};
+use crate::context::AppContext;
+use crate::jwt::{create_jwt, decode_jwt, hash, PrivateClaim};
 use crate::errors::ApiError;
+use crate::models::session::{create_session, end_session};
 use crate::models::user::UserResponse;
-use crate::models::user::{find_by_auth};
+use crate::models::user::{find_by_auth, find_totp, forget_cached_user, has_role, update_password};
+use crate::redact::redact_body;
+use crate::telemetry::traced;
 use crate::utils::{respond_json, respond_ok};
-use crate::validate::validate;
+use crate::validate::{validate, validate_password};
 
 #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 pub struct LoginRequest {
@@ -25,43 +29,137 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(
+        min = 6,
+        message = "current_password is required and must be at least 6 characters"
+    ))]
+    pub current_password: String,
+
+    #[validate(length(min = 1, message = "new_password is required"))]
+    pub new_password: String,
+}
+
 /// Login a user
 /// Create and remember their JWT
 pub async fn login(
+    ctx: Data<AppContext>,
     id: Identity,
     params: Json<LoginRequest>,
 ) -> Result<Json<bson::Document>, ApiError> {
-    validate(&params)?;
-
-    // Validate that the id + hashed password matches
-    let hashed = hash(&params.password);
-    let result = find_by_auth(&params.uid, &hashed).await;
-    if result.clone().is_some() {
-      // Create a JWT
-      let user = result.clone().unwrap();
-      let private_claim = PrivateClaim::new(user.uid, user.email.clone());
-      let jwt = create_jwt(private_claim)?;
-      
-      // Remember the token
-      let msg = doc! { "status" : "ok", "data" : jwt.clone().to_string() };
-      id.remember(jwt);
-      respond_json(msg)
-    } else {
-      let msg = doc! { "status" : "fail", "error" : "No UID" };
-      respond_json(msg)
+    traced("handler.login", async move {
+        debug!("login {}", redact_body(&*params));
+        validate(&params)?;
+
+        // Validate that the id + hashed password matches
+        let hashed = hash(&ctx.config, &params.password);
+        let result = find_by_auth(&*ctx.db, &params.uid, &hashed).await?;
+        if result.clone().is_some() {
+          let user = result.clone().unwrap();
+
+          // Users with two-factor authentication must still present a code
+          if find_totp(&*ctx.db, &user.uid)?.enabled {
+            let private_claim = PrivateClaim::new_mfa_pending(&ctx.config, user.uid, user.email);
+            let jwt = create_jwt(&ctx.config, private_claim)?;
+            let msg = doc! { "status" : "mfa_required", "data" : jwt };
+            return respond_json(msg);
+          }
+
+          let jwt = start_session(&ctx, &id, user.uid, user.email)?;
+          let msg = doc! { "status" : "ok", "data" : jwt };
+          respond_json(msg)
+        } else {
+          let msg = doc! { "status" : "fail", "error" : "No UID" };
+          respond_json(msg)
+        }
+    })
+    .await
+}
+
+/// Change the password of the logged-in user
+/// Sessions issued before the change are invalidated; this one gets a fresh JWT
+pub async fn change_password(
+    ctx: Data<AppContext>,
+    id: Identity,
+    params: Json<ChangePasswordRequest>,
+) -> Result<Json<bson::Document>, ApiError> {
+    traced("handler.change_password", async move {
+        validate(&params)?;
+        let private_claim = current_claim(&ctx, &id)?;
+
+        // Verify the current password before replacing it
+        let hashed = hash(&ctx.config, &params.current_password);
+        let user = find_by_auth(&*ctx.db, &private_claim.user_id, &hashed)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized("current_password is incorrect".into()))?;
+
+        if params.current_password == params.new_password {
+            return Err(ApiError::ValidationError(vec![
+                "new_password must differ from current_password".into(),
+            ]));
+        }
+        validate_password(&ctx.config, &params.new_password, &user.uid, &user.email)?;
+        update_password(&*ctx.db, &user.uid, &hash(&ctx.config, &params.new_password))?;
+        forget_cached_user(&*ctx.cache, &user.uid);
+
+        // Replace this session with a new one, outliving the revocation
+        if let Some(ref session_id) = private_claim.session_id {
+            end_session(&*ctx.db, session_id)?;
+        }
+        let jwt = start_session(&ctx, &id, user.uid, user.email)?;
+        let msg = doc! { "status" : "ok", "data" : jwt };
+        respond_json(msg)
+    })
+    .await
+}
+
+/// Record a new session of a user and remember its JWT, returning the JWT
+pub fn start_session(ctx: &AppContext, id: &Identity, uid: String, email: String) -> Result<String, ApiError> {
+    let private_claim = PrivateClaim::new(&ctx.config, uid, email);
+    let session_id = create_session(&*ctx.db, &private_claim.user_id, private_claim.expires_at())?;
+    let jwt = create_jwt(&ctx.config, private_claim.with_session(session_id))?;
+    id.remember(jwt.clone());
+    Ok(jwt)
+}
+
+/// Get the claim of the logged-in user from their session
+pub fn current_claim(ctx: &AppContext, id: &Identity) -> Result<PrivateClaim, ApiError> {
+    let identity = id.identity().unwrap_or_default();
+    decode_jwt(&ctx.config, &identity)
+        .ok()
+        .filter(|private_claim| !private_claim.mfa_pending)
+        .ok_or_else(|| ApiError::Unauthorized("Not logged in".into()))
+}
+
+/// Claim of the logged-in user, refusing users without the "admin" role
+pub fn current_admin(ctx: &AppContext, id: &Identity) -> Result<PrivateClaim, ApiError> {
+    let private_claim = current_claim(ctx, id)?;
+    if !has_role(&*ctx.db, &private_claim.user_id, "admin")? {
+        return Err(ApiError::Forbidden("Admin role required".into()));
     }
+    Ok(private_claim)
 }
 
 /// Logout a user
-/// Forget their user_id
-pub async fn logout(id: Identity) -> Result<HttpResponse, ApiError> {
-    id.forget();
-    respond_ok()
+/// End their session, so a copy of the token is refused too, and forget it
+pub async fn logout(ctx: Data<AppContext>, id: Identity) -> Result<HttpResponse, ApiError> {
+    traced("handler.logout", async move {
+        let session_id = current_claim(&ctx, &id).ok().and_then(|private_claim| private_claim.session_id);
+        if let Some(ref session_id) = session_id {
+            end_session(&*ctx.db, session_id)?;
+        }
+        id.forget();
+        respond_ok()
+    })
+    .await
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
     use actix_identity::Identity;
     use actix_web::{test, FromRequest};
 
@@ -76,17 +174,19 @@ pub mod tests {
     }
 
     async fn login_user() -> Result<Json<bson::Document>, ApiError> {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
         let params = LoginRequest {
-            uid: "bsjung2".into(),
-            password: "123456".into(),
+            uid: user.uid,
+            password: user.password,
         };
         let identity = get_identity().await;
-        login(identity, Json(params)).await
+        login(fixtures.ctx(), identity, Json(params)).await
     }
 
     async fn logout_user() -> Result<HttpResponse, ApiError> {
         let identity = get_identity().await;
-        logout(identity).await
+        logout(test_context(), identity).await
     }
 
     #[actix_rt::test]
@@ -0,0 +1,231 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::HttpRequest;
+use serde::Serialize;
+use validator::Validate;
+
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::handlers::auth::{current_admin, current_claim};
+use crate::models::amount::Amount;
+use crate::models::asset::{enabled_asset, Asset};
+use crate::models::coin::{create_holding, deposit, get_data, withdraw, CoinResponse};
+use crate::models::transaction::{find_transactions, TransactionPage};
+use crate::models::transfer::{transfer, TransferRequest as Transfer, TransferResponse};
+use crate::telemetry::traced;
+use crate::utils::respond_json;
+use crate::validate::validate;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct CoinRequest {
+    /// Name of the asset, checked against the catalog when given
+    #[serde(default)]
+    pub coin: Option<String>,
+
+    #[validate(length(min = 1, max = 10, message = "ticker must be 1 to 10 characters"))]
+    pub ticker: String,
+
+    pub amount: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AmountRequest {
+    pub amount: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DepositRequest {
+    pub amount: String,
+    /// uid of the holder, the admin making the deposit when absent
+    #[serde(default)]
+    pub uid: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransferRequest {
+    /// uid of the recipient
+    pub to: String,
+    pub amount: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceResponse {
+    pub ticker: String,
+    pub amount: Amount,
+}
+
+/// Get the holdings of the logged-in user
+pub async fn get_coins(ctx: Data<AppContext>, id: Identity) -> Result<Json<Vec<Option<CoinResponse>>>, ApiError> {
+    traced("handler.get_coins", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let result = get_data(&*ctx.db, doc! { "uid": &private_claim.user_id })?;
+        respond_json(result)
+    })
+    .await
+}
+
+/// Open a holding for the logged-in user.
+/// Only admins may open one with a balance, anyone else opens it empty.
+pub async fn create_coin(
+    ctx: Data<AppContext>,
+    id: Identity,
+    params: Json<CoinRequest>,
+) -> Result<Json<CoinResponse>, ApiError> {
+    traced("handler.create_coin", async move {
+        validate(&params)?;
+        let private_claim = current_claim(&ctx, &id)?;
+        let asset = enabled_asset(&*ctx.db, &params.ticker)?;
+        if let Some(coin) = params.coin.as_deref().filter(|coin| !names_asset(coin, &asset)) {
+            return Err(ApiError::ValidationError(vec![format!(
+                "coin {:?} is not the name of {} ({})",
+                coin, asset.ticker, asset.name
+            )]));
+        }
+        let amount = Amount::parse(&params.amount, asset.decimals)?;
+        if !amount.is_zero() {
+            current_admin(&ctx, &id)?;
+        }
+        let uid = &private_claim.user_id;
+        let result = create_holding(&*ctx.db, uid, &asset.name, &asset.ticker, amount, uid)?;
+        respond_json(result)
+    })
+    .await
+}
+
+/// Add to a holding, admins only: deposits create coins
+pub async fn deposit_coin(
+    ctx: Data<AppContext>,
+    id: Identity,
+    ticker: Path<String>,
+    params: Json<DepositRequest>,
+) -> Result<Json<BalanceResponse>, ApiError> {
+    traced("handler.deposit_coin", async move {
+        let private_claim = current_admin(&ctx, &id)?;
+        let asset = enabled_asset(&*ctx.db, &ticker)?;
+        let amount = positive_amount(&params.amount, &asset)?;
+        let admin = &private_claim.user_id;
+        let uid = params.uid.as_deref().unwrap_or(admin);
+        let ticker = asset.ticker;
+        let amount = deposit(&*ctx.db, uid, &ticker, amount, admin)?;
+        respond_json(BalanceResponse { ticker, amount })
+    })
+    .await
+}
+
+/// Take from a holding of the logged-in user, refusing to overdraw it
+pub async fn withdraw_coin(
+    ctx: Data<AppContext>,
+    id: Identity,
+    ticker: Path<String>,
+    params: Json<AmountRequest>,
+) -> Result<Json<BalanceResponse>, ApiError> {
+    traced("handler.withdraw_coin", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let asset = enabled_asset(&*ctx.db, &ticker)?;
+        let amount = positive_amount(&params.amount, &asset)?;
+        let uid = &private_claim.user_id;
+        let ticker = asset.ticker;
+        let amount = withdraw(&*ctx.db, uid, &ticker, amount, uid)?;
+        respond_json(BalanceResponse { ticker, amount })
+    })
+    .await
+}
+
+/// Ledger of a holding of the logged-in user, newest first
+pub async fn get_transactions(
+    ctx: Data<AppContext>,
+    id: Identity,
+    ticker: Path<String>,
+    query: Query<PageQuery>,
+) -> Result<Json<TransactionPage>, ApiError> {
+    traced("handler.get_transactions", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let ticker = ticker.to_uppercase();
+        let page = query.page.unwrap_or(1);
+        let per_page = query.per_page.unwrap_or(20);
+        let result = find_transactions(&*ctx.db, &private_claim.user_id, &ticker, page, per_page)?;
+        respond_json(result)
+    })
+    .await
+}
+
+/// Header naming a transfer, so a retried request does not move coins twice
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Send coins from a holding of the logged-in user to another user
+pub async fn transfer_coin(
+    req: HttpRequest,
+    ctx: Data<AppContext>,
+    id: Identity,
+    ticker: Path<String>,
+    params: Json<TransferRequest>,
+) -> Result<Json<TransferResponse>, ApiError> {
+    traced("handler.transfer_coin", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let idempotency_key = req
+            .headers()
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 255)
+            .ok_or_else(|| ApiError::BadRequest(format!("{} header is required", IDEMPOTENCY_KEY_HEADER)))?;
+        let asset = enabled_asset(&*ctx.db, &ticker)?;
+        let amount = positive_amount(&params.amount, &asset)?;
+        let request = Transfer {
+            from: &private_claim.user_id,
+            to: &params.to,
+            ticker: &asset.ticker,
+            amount,
+            idempotency_key,
+        };
+        let result = transfer(&*ctx.db, request)?;
+        respond_json(result)
+    })
+    .await
+}
+
+/// Whether `coin` is the name or ticker of `asset`, ignoring case
+fn names_asset(coin: &str, asset: &Asset) -> bool {
+    coin.eq_ignore_ascii_case(&asset.name) || coin.eq_ignore_ascii_case(&asset.ticker)
+}
+
+fn positive_amount(value: &str, asset: &Asset) -> Result<Amount, ApiError> {
+    let amount = Amount::parse(value, asset.decimals)?;
+    if amount.is_zero() {
+        return Err(ApiError::ValidationError(vec!["amount must be greater than 0".into()]));
+    }
+    Ok(amount)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn bitcoin() -> Asset {
+        Asset {
+            ticker: "BTC".into(),
+            name: "Bitcoin".into(),
+            decimals: 8,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn it_requires_a_positive_amount() {
+        assert_eq!(positive_amount("0.5", &bitcoin()).unwrap().units(), 50_000_000);
+        assert!(positive_amount("0", &bitcoin()).is_err());
+        assert!(positive_amount("-1", &bitcoin()).is_err());
+    }
+
+    #[test]
+    fn it_matches_coin_names_to_assets() {
+        assert!(names_asset("bitcoin", &bitcoin()));
+        assert!(names_asset("btc", &bitcoin()));
+        assert!(!names_asset("Bitcoin Cash", &bitcoin()));
+    }
+}
@@ -1,6 +1,10 @@
+use crate::context::AppContext;
 use crate::errors::ApiError;
+use crate::telemetry::{in_current_trace, traced};
 use crate::utils::respond_json;
-use actix_web::web::Json;
+use actix_web::web::{self, Data, HttpResponse, Json};
+use chrono::{TimeZone, Utc};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct HealthResponse {
@@ -8,6 +12,44 @@ pub struct HealthResponse {
     pub version: String,
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub status: String,
+    pub latency_ms: u128,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_sha: String,
+    pub build_time: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct ReadinessResponse {
+    pub status: String,
+    pub build: BuildInfo,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+impl ReadinessResponse {
+    /// Ready only when every dependency is up
+    fn new(dependencies: Vec<DependencyStatus>) -> Self {
+        let is_ready = dependencies.iter().all(|dependency| dependency.status == "ok");
+        ReadinessResponse {
+            status: if is_ready { "ok" } else { "unavailable" }.into(),
+            build: build_info(),
+            dependencies,
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
 /// Handler to get the liveness of the service
 pub async fn get_health() -> Result<Json<HealthResponse>, ApiError> {
     respond_json(HealthResponse {
@@ -16,13 +58,84 @@ pub async fn get_health() -> Result<Json<HealthResponse>, ApiError> {
     })
 }
 
+/// Handler to get the readiness of the service
+/// Responds 503 when a dependency is down
+pub async fn get_readiness(ctx: Data<AppContext>) -> HttpResponse {
+    let response = traced("handler.get_readiness", async {
+        ReadinessResponse::new(vec![check_mongodb(&ctx).await])
+    })
+    .await;
+    if response.is_ready() {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
+
+/// Ping MongoDB, giving up after the configured timeout
+async fn check_mongodb(ctx: &AppContext) -> DependencyStatus {
+    let started = Instant::now();
+    let db = ctx.db.clone();
+    let ping = web::block(in_current_trace("readiness.mongodb", move || db.ping()));
+    let timeout_ms = ctx.config.health_timeout_ms;
+    let timeout = Duration::from_millis(timeout_ms);
+    let error = match actix_rt::time::timeout(timeout, ping).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(ApiError::from(e).to_string()),
+        Err(_) => Some(format!("timed out after {} ms", timeout_ms)),
+    };
+
+    DependencyStatus {
+        name: "mongodb".into(),
+        status: if error.is_none() { "ok" } else { "down" }.into(),
+        latency_ms: started.elapsed().as_millis(),
+        error,
+    }
+}
+
+fn build_info() -> BuildInfo {
+    let build_timestamp = env!("BUILD_TIMESTAMP").parse().unwrap_or(0);
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").into(),
+        git_sha: env!("GIT_SHA").into(),
+        build_time: Utc
+            .timestamp_opt(build_timestamp, 0)
+            .single()
+            .map(|build_time| build_time.to_rfc3339())
+            .unwrap_or_default(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn dependency(status: &str) -> DependencyStatus {
+        DependencyStatus {
+            name: "mongodb".into(),
+            status: status.into(),
+            latency_ms: 1,
+            error: None,
+        }
+    }
+
     #[actix_rt::test]
     async fn test_get_health() {
         let response = get_health().await.unwrap();
         assert_eq!(response.into_inner().status, "ok".to_string());
     }
+
+    #[test]
+    fn it_is_ready_when_dependencies_are_up() {
+        let response = ReadinessResponse::new(vec![dependency("ok")]);
+        assert!(response.is_ready());
+        assert_eq!(response.build.git_sha, env!("GIT_SHA"));
+    }
+
+    #[test]
+    fn it_is_unavailable_when_a_dependency_is_down() {
+        let response = ReadinessResponse::new(vec![dependency("ok"), dependency("down")]);
+        assert!(!response.is_ready());
+        assert_eq!(response.status, "unavailable");
+    }
 }
@@ -0,0 +1,63 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::handlers::auth::current_admin;
+use crate::jobs::{self, find_runs, JobRun};
+use crate::telemetry::traced;
+use crate::utils::respond_json;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RunsQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobResponse {
+    pub name: String,
+    pub schedule: String,
+    pub next_run_at: Option<String>,
+    pub last_run: Option<JobRun>,
+}
+
+/// List the scheduled jobs with their next and last runs, admins only
+pub async fn get_jobs(ctx: Data<AppContext>, id: Identity) -> Result<Json<Vec<JobResponse>>, ApiError> {
+    traced("handler.get_jobs", async move {
+        current_admin(&ctx, &id)?;
+        let now = Utc::now();
+        let mut result = Vec::new();
+        for job in jobs::jobs(&ctx.config)? {
+            result.push(JobResponse {
+                name: job.name.into(),
+                schedule: job.schedule.to_string(),
+                next_run_at: job
+                    .schedule
+                    .next_after(now)
+                    .map(|next| next.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                last_run: find_runs(&*ctx.db, job.name, 1)?.pop(),
+            });
+        }
+        respond_json(result)
+    })
+    .await
+}
+
+/// Run history of a job, newest first, admins only
+pub async fn get_job_runs(
+    ctx: Data<AppContext>,
+    id: Identity,
+    name: Path<String>,
+    query: Query<RunsQuery>,
+) -> Result<Json<Vec<JobRun>>, ApiError> {
+    traced("handler.get_job_runs", async move {
+        current_admin(&ctx, &id)?;
+        if !jobs::jobs(&ctx.config)?.iter().any(|job| job.name == name.as_str()) {
+            return Err(ApiError::NotFound(format!("No job {}", name)));
+        }
+        respond_json(find_runs(&*ctx.db, &name, query.limit.unwrap_or(20))?)
+    })
+    .await
+}
@@ -0,0 +1,9 @@
+use crate::metrics::render;
+use actix_web::web::HttpResponse;
+
+/// Handler to expose metrics in the Prometheus text format
+pub async fn get_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render())
+}
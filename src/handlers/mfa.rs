@@ -0,0 +1,133 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json};
+use serde::Serialize;
+use validator::Validate;
+
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::handlers::auth::{current_claim, start_session};
+use crate::jwt::{decode_jwt, hash};
+use crate::models::user::{
+    accept_totp_step, clear_mfa_failures, consume_recovery_code, enable_totp, find_totp, is_mfa_locked,
+    record_mfa_failure, set_pending_totp_secret,
+};
+use crate::telemetry::traced;
+use crate::totp;
+use crate::utils::respond_json;
+use crate::validate::validate;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct ConfirmTotpRequest {
+    #[validate(length(min = 6, max = 6, message = "code must be 6 digits"))]
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct LoginMfaRequest {
+    #[validate(length(min = 1, message = "token is required"))]
+    pub token: String,
+
+    #[validate(length(min = 1, message = "code is required"))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct EnrollTotpResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct ConfirmTotpResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Start two-factor enrollment for the logged-in user
+/// The secret is pending until confirmed with a valid code
+pub async fn enroll_totp(
+    ctx: Data<AppContext>,
+    id: Identity,
+) -> Result<Json<EnrollTotpResponse>, ApiError> {
+    traced("handler.enroll_totp", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        if find_totp(&*ctx.db, &private_claim.user_id)?.enabled {
+            return Err(ApiError::BadRequest("Two-factor authentication is already enabled".into()));
+        }
+
+        let secret = totp::generate_secret();
+        set_pending_totp_secret(&*ctx.db, &private_claim.user_id, &secret)?;
+        respond_json(EnrollTotpResponse {
+            otpauth_uri: totp::otpauth_uri(&ctx.config.totp_issuer, &private_claim.user_id, &secret),
+            secret,
+        })
+    })
+    .await
+}
+
+/// Confirm two-factor enrollment with a first valid code
+/// Recovery codes are returned once and only stored hashed
+pub async fn confirm_totp(
+    ctx: Data<AppContext>,
+    id: Identity,
+    params: Json<ConfirmTotpRequest>,
+) -> Result<Json<ConfirmTotpResponse>, ApiError> {
+    traced("handler.confirm_totp", async move {
+        validate(&params)?;
+        let private_claim = current_claim(&ctx, &id)?;
+
+        let secret = find_totp(&*ctx.db, &private_claim.user_id)?
+            .pending_secret
+            .ok_or_else(|| ApiError::BadRequest("No two-factor enrollment in progress".into()))?;
+        let step = totp::verify(&secret, &params.code).ok_or_else(|| ApiError::Unauthorized("code is invalid".into()))?;
+
+        let recovery_codes = totp::generate_recovery_codes();
+        let hashed = recovery_codes.iter().map(|code| hash(&ctx.config, code)).collect();
+        enable_totp(&*ctx.db, &private_claim.user_id, &secret, step, hashed)?;
+        respond_json(ConfirmTotpResponse { recovery_codes })
+    })
+    .await
+}
+
+/// Second step of a two-factor login
+/// Exchange the "mfa pending" token and a TOTP or recovery code for a session.
+/// A TOTP code is accepted once, and too many wrong codes lock the user out for a while.
+pub async fn login_mfa(
+    ctx: Data<AppContext>,
+    id: Identity,
+    params: Json<LoginMfaRequest>,
+) -> Result<Json<bson::Document>, ApiError> {
+    traced("handler.login_mfa", async move {
+        validate(&params)?;
+
+        let pending_claim = decode_jwt(&ctx.config, &params.token)
+            .ok()
+            .filter(|private_claim| private_claim.mfa_pending)
+            .ok_or_else(|| ApiError::Unauthorized("token is invalid or expired".into()))?;
+
+        let db = &*ctx.db;
+        let uid = &pending_claim.user_id;
+        if is_mfa_locked(db, uid, ctx.config.mfa_max_failures)? {
+            return Err(ApiError::TooManyRequests("Too many invalid codes, try again later".into()));
+        }
+
+        let settings = find_totp(db, uid)?;
+        let step = match settings.secret {
+            Some(ref secret) if settings.enabled => totp::verify(secret, &params.code),
+            _ => None,
+        };
+        let is_valid_code = match step {
+            Some(step) => accept_totp_step(db, uid, step)?,
+            None => consume_recovery_code(db, uid, &hash(&ctx.config, &params.code))?,
+        };
+        if !is_valid_code {
+            record_mfa_failure(db, uid, ctx.config.mfa_lockout_minutes)?;
+            return Err(ApiError::Unauthorized("code is invalid".into()));
+        }
+        clear_mfa_failures(db, uid)?;
+
+        let jwt = start_session(&ctx, &id, pending_claim.user_id, pending_claim.email)?;
+        let msg = doc! { "status" : "ok", "data" : jwt };
+        respond_json(msg)
+    })
+    .await
+}
@@ -1,3 +1,9 @@
+pub mod asset;
 pub mod auth;
+pub mod coin;
 pub mod health;
+pub mod job;
+pub mod metrics;
+pub mod mfa;
+pub mod portfolio;
 pub mod user;
@@ -0,0 +1,94 @@
+use actix_identity::Identity;
+use actix_web::web::{self, Data, Json, Query};
+use chrono::{DateTime, Duration as Span, Utc};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::handlers::auth::current_claim;
+use crate::models::coin::get_data;
+use crate::models::portfolio::{valuate, Portfolio};
+use crate::models::snapshot::{find_history, HistoryPoint, Interval};
+use crate::prices::{cached_quote, cached_quotes, currency};
+use crate::telemetry::{in_current_trace, traced};
+use crate::utils::respond_json;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PortfolioQuery {
+    /// Fiat currency code, `default_currency` when absent
+    pub currency: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HistoryQuery {
+    /// RFC 3339 start of the range, 30 days before `to` when absent
+    pub from: Option<String>,
+    /// RFC 3339 end of the range, now when absent
+    pub to: Option<String>,
+    /// "hour", "day" (default) or "week"
+    pub interval: Option<String>,
+}
+
+/// Value of the holdings of the logged-in user in a fiat currency.
+/// Quotes may come from a remote service, so valuation runs on the blocking pool.
+pub async fn get_portfolio(
+    ctx: Data<AppContext>,
+    id: Identity,
+    query: Query<PortfolioQuery>,
+) -> Result<Json<Portfolio>, ApiError> {
+    traced("handler.get_portfolio", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let currency = currency(query.currency.as_deref().unwrap_or(&ctx.config.default_currency))?;
+        let valuation = in_current_trace("portfolio.valuate", move || value_holdings(&ctx, &private_claim.user_id, &currency));
+        let result = web::block(valuation).await?;
+        respond_json(result)
+    })
+    .await
+}
+
+fn value_holdings(ctx: &AppContext, uid: &str, currency: &str) -> Result<Portfolio, ApiError> {
+    let ttl = Duration::from_secs(ctx.config.price_ttl_seconds);
+    let tickers: Vec<String> = get_data(&*ctx.db, doc! { "uid": uid })?
+        .into_iter()
+        .flatten()
+        .map(|holding| holding.ticker)
+        .collect();
+    let quotes = RefCell::new(cached_quotes(&*ctx.prices, &*ctx.cache, ttl, &tickers, currency));
+    let quote = |ticker: &str| match quotes.borrow_mut().remove(ticker) {
+        Some(quote) => quote,
+        // Opened since the holdings were read
+        None => cached_quote(&*ctx.prices, &*ctx.cache, ttl, ticker, currency),
+    };
+    valuate(&*ctx.db, uid, currency, quote)
+}
+
+/// Snapshots of the holdings of the logged-in user, one per interval of the range
+pub async fn get_portfolio_history(
+    ctx: Data<AppContext>,
+    id: Identity,
+    query: Query<HistoryQuery>,
+) -> Result<Json<Vec<HistoryPoint>>, ApiError> {
+    traced("handler.get_portfolio_history", async move {
+        let private_claim = current_claim(&ctx, &id)?;
+        let to = match query.to.as_deref() {
+            Some(to) => parse_time("to", to)?,
+            None => Utc::now(),
+        };
+        let from = match query.from.as_deref() {
+            Some(from) => parse_time("from", from)?,
+            None => to - Span::days(30),
+        };
+        let interval = Interval::parse(query.interval.as_deref().unwrap_or("day"))?;
+        let result = find_history(&*ctx.db, &private_claim.user_id, from, to, interval)?;
+        respond_json(result)
+    })
+    .await
+}
+
+fn parse_time(name: &str, value: &str) -> Result<DateTime<Utc>, ApiError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| ApiError::ValidationError(vec![format!("{} must be an RFC 3339 time, got {:?}", name, value)]))
+}
@@ -1,17 +1,25 @@
-use actix_web::web::{Json, Path};
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path};
 use rayon::prelude::*;
 use serde::Serialize;
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 use crate::bson;
+use crate::config::Config;
+use crate::context::AppContext;
 use crate::database;
-use crate::validate::validate;
+use crate::handlers::auth::current_claim;
+use crate::jwt::hash;
+use crate::validate::{validate, validate_password, validate_password_identity};
 use crate::errors::ApiError;
 use chrono::prelude::*;
+use crate::redact::redact_body;
+use crate::telemetry::traced;
 use crate::utils::{respond_json, respond_ok};
 use crate::models::user::*;
 
 #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[validate(schema(function = "validate_user_password"))]
 pub struct UserRequest {
     #[validate(length(
         min = 6,
@@ -22,92 +30,212 @@ pub struct UserRequest {
     #[validate(email(message = "email must be a valid email"))]
     pub email: String,
 
-    #[validate(length(
-        min = 6,
-        message = "password is required and must be at least 6 characters"
-    ))]
+    #[validate(length(min = 1, message = "password is required"))]
     pub password: String,
     pub name: String,
     pub phone: String,
 }
 
+/// Partial update of a user, only provided fields are validated and set
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UserPatchRequest {
+    #[validate(length(
+        min = 6,
+        message = "UserID must be at least 6 characters"
+    ))]
+    pub uid: Option<String>,
+
+    #[validate(email(message = "email must be a valid email"))]
+    pub email: Option<String>,
+
+    pub password: Option<String>,
+    pub name: Option<String>,
+    pub phone: Option<String>,
+}
+
+/// The password must not be derived from the user's own identifiers
+fn validate_user_password(user: &UserRequest) -> Result<(), ValidationError> {
+    validate_password_identity(&user.password, &user.uid, &user.email)
+}
+
 /// Get all users
-pub async fn get_users() -> Result<Json<Vec<Option<UserResponse>>>, ApiError> {
-    println!("all users");
+pub async fn get_users(ctx: Data<AppContext>) -> Result<Json<Vec<Option<UserResponse>>>, ApiError> {
+    traced("handler.get_users", async move {
+        debug!("get all users");
 
-    // get all users
-    let query = bson::Document::new();
-    let result = get_data(query).unwrap();
-    respond_json(result)
+        // get all users
+        let query = bson::Document::new();
+        let result = get_data(&*ctx.db, query).unwrap();
+        respond_json(result)
+    })
+    .await
 }
 
 /// Get a user
-pub async fn get_user( uid: Path<String> ) -> Result<Json<Vec<Option<UserResponse>>>, ApiError> {
-    let user_id = uid.split('/').last().unwrap();
-    println!("get {}", user_id);
-
-    // get user
-    let query = doc! { "uid" => user_id };
-    let result = get_data(query).unwrap();
-    respond_json(result)
+pub async fn get_user( ctx: Data<AppContext>, uid: Path<String> ) -> Result<Json<Vec<Option<UserResponse>>>, ApiError> {
+    traced("handler.get_user", async move {
+        let user_id = uid.split('/').last().unwrap();
+        debug!("get user {}", user_id);
+
+        // get user
+        let result = find_cached(&*ctx.db, &*ctx.cache, user_id)?;
+        respond_json(result)
+    })
+    .await
 }
 
 /// Create a user
 pub async fn create_user(
+    ctx: Data<AppContext>,
     params: Json<UserRequest>,
 ) -> Result<Json<String>, ApiError> {
-    validate(&params)?;
-
-    // insert user
-    let query = doc! {
-        "uid": params.uid.to_string(),
-        "email": params.email.to_string(),
-        "password": params.password.to_string(),
-        "name": params.name.to_string(),
-        "phone": params.phone.to_string(),
-        "created_at": Utc::now(),
-        "updated_at": Utc::now()
-    };
-    let result = database::create("users", query).unwrap();
-    respond_json(result)
+    traced("handler.create_user", async move {
+        debug!("create user {}", redact_body(&*params));
+        validate(&params)?;
+        validate_password(&ctx.config, &params.password, &params.uid, &params.email)?;
+
+        // insert user
+        let query = doc! {
+            "uid": params.uid.to_string(),
+            "email": params.email.to_string(),
+            "password": hash(&ctx.config, &params.password),
+            "name": params.name.to_string(),
+            "phone": params.phone.to_string(),
+            "roles": ["user"],
+            "created_at": Utc::now(),
+            "updated_at": Utc::now()
+        };
+        let result = database::create(&*ctx.db, "users", query)?;
+        forget_cached_user(&*ctx.cache, &params.uid);
+        respond_json(result)
+    })
+    .await
 }
 
 /// Update a user
 pub async fn update_user(
+    ctx: Data<AppContext>,
     uid: Path<String>,
     params: Json<UserRequest>,
 ) -> Result<Json<String>, ApiError> {
-    validate(&params)?;
-
-    // update user
-    let user_id = uid.split('/').last().unwrap();
-    println!("update {}", user_id);
-
-    let filter = doc! { "uid" : user_id };
-    let query = doc! { "$set" : {
-        "uid": params.uid.to_string(),
-        "email": params.email.to_string(),
-        "password": params.password.to_string(),
-        "name": params.name.to_string(),
-        "phone": params.phone.to_string(),
-        "updated_at": Utc::now()
-       }
-    };
-    let result = database::update("users", filter, query).unwrap();
-    respond_json(result)
+    traced("handler.update_user", async move {
+        validate(&params)?;
+        validate_password(&ctx.config, &params.password, &params.uid, &params.email)?;
+
+        // update user
+        let user_id = uid.split('/').last().unwrap();
+        debug!("update user {}", user_id);
+
+        let now = Utc::now();
+        let filter = doc! { "uid" : user_id };
+        let query = doc! { "$set" : {
+            "uid": params.uid.to_string(),
+            "email": params.email.to_string(),
+            "password": hash(&ctx.config, &params.password),
+            "password_changed_at_ms": now.timestamp_millis(),
+            "name": params.name.to_string(),
+            "phone": params.phone.to_string(),
+            "updated_at": now
+           }
+        };
+        let result = database::update(&*ctx.db, "users", filter, query)?;
+        forget_cached_user(&*ctx.cache, user_id);
+        forget_cached_user(&*ctx.cache, &params.uid);
+        respond_json(result)
+    })
+    .await
+}
+
+/// Partially update a user
+/// Users may patch only themselves and admins anyone. Only admins may set a
+/// password here, users change theirs with the current one at /auth/change-password.
+pub async fn patch_user(
+    ctx: Data<AppContext>,
+    id: Identity,
+    uid: Path<String>,
+    params: Json<UserPatchRequest>,
+) -> Result<Json<String>, ApiError> {
+    traced("handler.patch_user", async move {
+        validate(&params)?;
+
+        let user_id = uid.rsplit('/').next().unwrap();
+        let private_claim = current_claim(&ctx, &id)?;
+        let is_admin = has_role(&*ctx.db, &private_claim.user_id, "admin")?;
+        if private_claim.user_id != user_id && !is_admin {
+            return Err(ApiError::Forbidden("Users may only patch themselves".into()));
+        }
+        if params.password.is_some() && !is_admin {
+            return Err(ApiError::Forbidden(
+                "Change your password at /api/v1/auth/change-password".into(),
+            ));
+        }
+
+        let stored = find_user(&*ctx.db, user_id)?;
+        if let Some(ref password) = params.password {
+            // Fields left out keep their stored value, which the password must not contain either
+            let uid = params.uid.as_deref().unwrap_or(&stored.uid);
+            let email = params.email.as_deref().unwrap_or(&stored.email);
+            validate_password(&ctx.config, password, uid, email)?;
+        }
+
+        let set = patch_document(&ctx.config, &params, Utc::now());
+        if set.len() == 1 {
+            return Err(ApiError::ValidationError(vec![
+                "at least one field must be provided".into(),
+            ]));
+        }
+
+        let filter = doc! { "uid" : user_id };
+        let query = doc! { "$set" : set };
+        let result = database::update(&*ctx.db, "users", filter, query)?;
+        forget_cached_user(&*ctx.cache, user_id);
+        if let Some(ref uid) = params.uid {
+            forget_cached_user(&*ctx.cache, uid);
+        }
+        respond_json(result)
+    })
+    .await
+}
+
+/// Build the $set document of a partial update from the provided fields
+fn patch_document(config: &Config, params: &UserPatchRequest, now: DateTime<Utc>) -> bson::Document {
+    let mut set = bson::Document::new();
+    if let Some(ref uid) = params.uid {
+        set.insert("uid", uid.to_string());
+    }
+    if let Some(ref email) = params.email {
+        set.insert("email", email.to_string());
+    }
+    if let Some(ref password) = params.password {
+        set.insert("password", hash(config, password));
+        set.insert("password_changed_at_ms", now.timestamp_millis());
+    }
+    if let Some(ref name) = params.name {
+        set.insert("name", name.to_string());
+    }
+    if let Some(ref phone) = params.phone {
+        set.insert("phone", phone.to_string());
+    }
+    set.insert("updated_at", now);
+    set
 }
 
 /// Delete a user
 pub async fn delete_user(
+    ctx: Data<AppContext>,
     uid: Path<String>,
 ) -> Result<Json<String>, ApiError> {
-    // delete user
-    let user_id = uid.split('/').last().unwrap();
-    println!("delete {}", user_id);
+    traced("handler.delete_user", async move {
+        // delete user
+        let user_id = uid.split('/').last().unwrap();
+        debug!("delete user {}", user_id);
 
-    let query = doc! { "uid" => user_id };
-    let result = database::delete("users", query).unwrap();
-    respond_json(result)
+        let query = doc! { "uid" => user_id };
+        let result = database::delete(&*ctx.db, "users", query).unwrap();
+        forget_cached_user(&*ctx.cache, user_id);
+        respond_json(result)
+    })
+    .await
 }
 
 
@@ -116,11 +244,15 @@ pub mod tests {
     use super::*;
     use actix_web::{test, web, App, HttpResponse};
     use actix_web::http::{header, StatusCode};
-    use crate::jwt::{create_jwt, hash, PrivateClaim};
+    use crate::jwt::{create_jwt, PrivateClaim};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::{test_config, test_context};
 
     #[actix_rt::test]
     async fn it_get_users() {
-        let resp = get_users().await;
+        let fixtures = Fixtures::new(test_context());
+        fixtures.user().create();
+        let resp = get_users(fixtures.ctx()).await;
         assert_eq!(resp.is_ok(), true);
         let result = resp.unwrap().into_inner();
         for item in result {
@@ -130,47 +262,71 @@ pub mod tests {
 
     #[actix_rt::test]
     async fn it_get_user() {
-        let uid: Path<String> = String::from("/api/v1/user/bsjung").into();
-        let resp = get_user(uid).await;
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let uid: Path<String> = format!("/api/v1/user/{}", user.uid).into();
+        let resp = get_user(fixtures.ctx(), uid).await;
         assert_eq!(resp.is_ok(), true);
     }
 
     #[actix_rt::test]
-    #[ignore]
     async fn it_create_user() {
-        let hashed = hash("123456");
         let params = UserRequest {
-            uid: "bsjung2".into(),
-            email: "bsjung@gmail.com".into(),
-            password: hashed.into(),
+            uid: "bsjung3".into(),
+            email: "bsjung3@gmail.com".into(),
+            password: "Tr0ub4dor&3".into(),
             name: "Benjaming".into(),
             phone: "010-xxxx-xxxx".into(),
         };
-        let resp = create_user(Json(params.clone())).await;
+        let resp = create_user(test_context(), Json(params.clone())).await;
         assert_eq!(resp.is_ok(), true);
     }
 
     #[actix_rt::test]
-    #[ignore]
     async fn it_update_user() {
-        let uid: Path<String> = String::from("/api/v1/user/bsjung").into();
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let uid: Path<String> = format!("/api/v1/user/{}", user.uid).into();
         println!("update id");
         let params = UserRequest {
-            uid: "bsjung2".into(),
-            email: "bsjung@gmail.com".into(),
-            password: "123456".into(),
+            uid: user.uid.clone(),
+            email: user.email.clone(),
+            password: "Tr0ub4dor&3".into(),
             name: "Benjaming".into(),
             phone: "010-xxxx-xxxx".into(),
         };
-        let resp = update_user(uid, Json(params.clone())).await;
+        let resp = update_user(fixtures.ctx(), uid, Json(params.clone())).await;
         assert_eq!(resp.is_ok(), true);
     }
 
+    #[test]
+    fn it_sets_only_provided_fields() {
+        let params = UserPatchRequest {
+            phone: Some("010-yyyy-yyyy".into()),
+            ..UserPatchRequest::default()
+        };
+        let set = patch_document(&test_config(), &params, Utc::now());
+        assert_eq!(set.get_str("phone").unwrap(), "010-yyyy-yyyy");
+        assert!(set.contains_key("updated_at"));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn it_validates_only_provided_fields() {
+        let params = UserPatchRequest {
+            email: Some("not-an-email".into()),
+            ..UserPatchRequest::default()
+        };
+        assert!(params.validate().is_err());
+        assert!(UserPatchRequest::default().validate().is_ok());
+    }
+
     #[actix_rt::test]
-    #[ignore]
     async fn it_delete_user() {
-        let uid: Path<String> = String::from("/api/v1/user/bsjung2").into();
-        let resp = delete_user(uid).await;
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let uid: Path<String> = format!("/api/v1/user/{}", user.uid).into();
+        let resp = delete_user(fixtures.ctx(), uid).await;
         assert_eq!(resp.is_ok(), true);
     }
 }
@@ -0,0 +1,323 @@
+//! Periodic background work of the server.
+//!
+//! Jobs are registered in `jobs()` with a cron-like `Schedule` from the
+//! configuration and fired by the `Scheduler` actor. Before running, an
+//! instance takes the lease of the job in `job_locks` and renews it while the
+//! job runs, so with several instances sharing a database each scheduled run
+//! happens once. Runs are recorded in `job_runs`, which a TTL index empties
+//! after `job_history_days`.
+
+mod schedule;
+mod scheduler;
+
+pub use self::schedule::Schedule;
+pub use self::scheduler::Scheduler;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::Serialize;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration as StdDuration;
+
+use crate::config::Config;
+use crate::context::AppContext;
+use crate::errors::ApiError;
+use crate::lease::Lease;
+use crate::models::session::{delete_expired_sessions, purge_stale_sessions};
+use crate::models::snapshot::{snapshot_time, take_snapshots};
+use crate::models::transfer::{recover_transfers, stale_cutoff};
+use crate::models::user::delete_expired_mfa_failures;
+use crate::prices::cached_quote;
+use crate::store::{FindOptions, Store};
+
+const LOCKS: &str = "job_locks";
+const RUNS: &str = "job_runs";
+
+/// Most runs `find_runs` returns
+const MAX_RUNS: i64 = 100;
+
+pub const SUCCEEDED: &str = "succeeded";
+pub const FAILED: &str = "failed";
+
+/// Work of a job for the run due at the given time, returning a summary for the history
+pub type JobFn = fn(&AppContext, DateTime<Utc>) -> Result<String, ApiError>;
+
+#[derive(Clone)]
+pub struct Job {
+    pub name: &'static str,
+    pub schedule: Schedule,
+    pub run: JobFn,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobRun {
+    pub job: String,
+    /// Instance that ran the job
+    pub owner: String,
+    pub scheduled_at: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub status: String,
+    /// Summary of the run, or why it failed
+    pub detail: String,
+}
+
+/// Every scheduled job; jobs configured with an empty schedule are left out
+pub fn jobs(config: &Config) -> Result<Vec<Job>, ApiError> {
+    let registered: [(&'static str, &str, JobFn); 4] = [
+        ("portfolio_snapshots", &config.snapshot_schedule, snapshot_job),
+        ("transfer_recovery", &config.transfer_recovery_schedule, recovery_job),
+        ("token_cleanup", &config.token_cleanup_schedule, token_cleanup_job),
+        ("session_purge", &config.session_purge_schedule, session_purge_job),
+    ];
+    registered
+        .iter()
+        .filter(|(_, schedule, _)| !schedule.trim().is_empty())
+        .map(|(name, schedule, run)| {
+            Ok(Job {
+                name,
+                schedule: Schedule::parse(schedule).map_err(ApiError::InternalServerError)?,
+                run: *run,
+            })
+        })
+        .collect()
+}
+
+/// Name of this instance in leases and the run history
+pub fn instance_name() -> String {
+    format!("{}-{:x}", std::process::id(), rand::random::<u32>())
+}
+
+/// Run `job` for its run due at `scheduled_at`, unless another instance holds
+/// its lease or already took that run. Failures and panics of the job are
+/// recorded in the history like successes; the recorded run is returned.
+pub fn run_job(ctx: &AppContext, job: &Job, owner: &str, scheduled_at: DateTime<Utc>) -> Result<Option<JobRun>, ApiError> {
+    let db = &*ctx.db;
+    let lease = Lease::new(db, LOCKS, job.name, owner);
+    if !acquire_lease(&lease, scheduled_at)? {
+        return Ok(None);
+    }
+    let started_at = Utc::now();
+    let outcome = lease.hold(|_| panic::catch_unwind(AssertUnwindSafe(|| (job.run)(ctx, scheduled_at))));
+    let finished_at = Utc::now();
+
+    let (status, detail) = match outcome {
+        Ok(Ok(summary)) => (SUCCEEDED, summary),
+        Ok(Err(ApiError::ValidationError(errors))) => (FAILED, errors.join(", ")),
+        Ok(Err(e)) => (FAILED, e.to_string()),
+        Err(payload) => (FAILED, format!("panicked: {}", panic_message(&*payload))),
+    };
+    let expires_at = finished_at + Duration::days(ctx.config.job_history_days as i64);
+    let run = doc! {
+        "job": job.name,
+        "owner": owner,
+        "scheduled_at": scheduled_at,
+        "started_at": started_at,
+        "finished_at": finished_at,
+        "status": status,
+        "detail": detail,
+        "expires_at": expires_at,
+    };
+    let recorded = db.insert_one(RUNS, run.clone());
+    // The lease only stops other instances from taking this run; should releasing it
+    // fail, it lapses once the heartbeat stops
+    if let Err(e) = lease.release() {
+        warn!("Could not release the lease of job {}: {}", job.name, e);
+    }
+    recorded?;
+    doc_to_model(&run).map(Some)
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
+/// The last `limit` runs of `job`, newest first
+pub fn find_runs(db: &dyn Store, job: &str, limit: i64) -> Result<Vec<JobRun>, ApiError> {
+    let options = FindOptions {
+        sort: Some(doc! { "started_at": -1 }),
+        limit: Some(limit.clamp(1, MAX_RUNS)),
+        ..FindOptions::default()
+    };
+    db.find(RUNS, doc! { "job": job }, options)?.iter().map(doc_to_model).collect()
+}
+
+/// Snapshot every portfolio as of the minute holding `at`, valued in `default_currency`
+pub fn snapshot_portfolios(ctx: &AppContext, at: DateTime<Utc>) -> Result<usize, ApiError> {
+    let currency = &ctx.config.default_currency;
+    let ttl = StdDuration::from_secs(ctx.config.price_ttl_seconds);
+    let quote = |ticker: &str| cached_quote(&*ctx.prices, &*ctx.cache, ttl, ticker, currency);
+    take_snapshots(&*ctx.db, currency, quote, snapshot_time(at, 1))
+}
+
+fn snapshot_job(ctx: &AppContext, scheduled_at: DateTime<Utc>) -> Result<String, ApiError> {
+    let written = snapshot_portfolios(ctx, scheduled_at)?;
+    Ok(format!("Took {} portfolio snapshot(s)", written))
+}
+
+fn recovery_job(ctx: &AppContext, _: DateTime<Utc>) -> Result<String, ApiError> {
+    let recovered = recover_transfers(&*ctx.db, stale_cutoff())?;
+    Ok(format!("Recovered {} interrupted transfer(s)", recovered))
+}
+
+fn token_cleanup_job(ctx: &AppContext, scheduled_at: DateTime<Utc>) -> Result<String, ApiError> {
+    let sessions = delete_expired_sessions(&*ctx.db, scheduled_at)?;
+    let attempts = delete_expired_mfa_failures(&*ctx.db, scheduled_at)?;
+    Ok(format!("Deleted {} expired session(s) and {} expired MFA attempt(s)", sessions, attempts))
+}
+
+fn session_purge_job(ctx: &AppContext, _: DateTime<Utc>) -> Result<String, ApiError> {
+    let purged = purge_stale_sessions(&*ctx.db)?;
+    Ok(format!("Purged {} revoked session(s)", purged))
+}
+
+/// Take the lease of a job for the run due at `scheduled_at`.
+/// False when another instance holds it or already took that run.
+fn acquire_lease(lease: &Lease, scheduled_at: DateTime<Utc>) -> Result<bool, ApiError> {
+    lease.acquire(doc! { "scheduled_at": { "$lt": scheduled_at } }, doc! { "scheduled_at": scheduled_at })
+}
+
+fn doc_to_model(doc: &bson::Document) -> Result<JobRun, ApiError> {
+    let invalid = |field: &str| ApiError::InternalServerError(format!("Invalid {} in a job run", field));
+    let text = |field: &str| doc.get_str(field).map(String::from).map_err(|_| invalid(field));
+    let time = |field: &str| {
+        doc.get_utc_datetime(field)
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .map_err(|_| invalid(field))
+    };
+    Ok(JobRun {
+        job: text("job")?,
+        owner: text("owner")?,
+        scheduled_at: time("scheduled_at")?,
+        started_at: time("started_at")?,
+        finished_at: time("finished_at")?,
+        status: text("status")?,
+        detail: text("detail")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn job(run: JobFn) -> Job {
+        Job {
+            name: "test",
+            schedule: Schedule::parse("* * * * *").unwrap(),
+            run,
+        }
+    }
+
+    #[test]
+    fn it_snapshots_in_the_default_currency() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0.5").create();
+        let ctx = fixtures.ctx();
+
+        let now = Utc::now();
+        assert_eq!(snapshot_portfolios(&ctx, now).unwrap(), 1);
+        assert_eq!(snapshot_portfolios(&ctx, now).unwrap(), 0);
+        let snapshot = ctx.db.find_one("portfolio_snapshots", doc! { "uid": &user.uid }).unwrap().unwrap();
+        assert_eq!(snapshot.get_str("currency").unwrap(), "USD");
+        assert_eq!(snapshot.get_i64("total").unwrap(), 3_200_000);
+    }
+
+    #[test]
+    fn it_registers_jobs_with_a_schedule() {
+        let mut config = test_context().config.clone();
+        config.transfer_recovery_schedule = "".into();
+        let names: Vec<&str> = jobs(&config).unwrap().iter().map(|job| job.name).collect();
+        assert_eq!(names, ["portfolio_snapshots", "token_cleanup", "session_purge"]);
+    }
+
+    #[test]
+    fn it_runs_each_scheduled_run_once() {
+        let ctx = test_context();
+        let job = job(|_, _| Ok("done".into()));
+        let first = at("2026-10-15T13:00:00Z");
+
+        let run = run_job(&ctx, &job, "a", first).unwrap().unwrap();
+        assert_eq!(run.status, SUCCEEDED);
+        assert_eq!(run.scheduled_at, "2026-10-15T13:00:00Z");
+        // Another instance firing for the same run finds it taken
+        assert!(run_job(&ctx, &job, "b", first).unwrap().is_none());
+        let run = run_job(&ctx, &job, "b", first + Duration::hours(1)).unwrap().unwrap();
+        assert_eq!(run.owner, "b");
+
+        let runs = find_runs(&*ctx.db, "test", 10).unwrap();
+        let owners: Vec<&str> = runs.iter().map(|run| run.owner.as_str()).collect();
+        assert_eq!(owners, ["b", "a"]);
+    }
+
+    #[test]
+    fn it_waits_for_a_lease_held_by_another_instance() {
+        let ctx = test_context();
+        let job = job(|_, _| Ok("done".into()));
+        let first = at("2026-10-15T13:00:00Z");
+        acquire_lease(&Lease::new(&*ctx.db, LOCKS, "test", "a"), first).unwrap();
+
+        assert!(run_job(&ctx, &job, "b", first + Duration::hours(1)).unwrap().is_none());
+        // A lease left by a crashed instance is taken over once it lapses
+        let lapsed = doc! { "$set": { "locked_until": Utc::now() - Duration::minutes(1) } };
+        ctx.db.update_one(LOCKS, doc! { "_id": "test" }, lapsed).unwrap();
+        assert!(run_job(&ctx, &job, "b", first + Duration::hours(1)).unwrap().is_some());
+    }
+
+    #[test]
+    fn it_records_failed_runs() {
+        let ctx = test_context();
+        let job = job(|_, _| Err(ApiError::InternalServerError("boom".into())));
+
+        let run = run_job(&ctx, &job, "a", Utc::now()).unwrap().unwrap();
+        assert_eq!(run.status, FAILED);
+        assert_eq!(run.detail, "boom");
+        let record = ctx.db.find_one(RUNS, doc! { "job": "test" }).unwrap().unwrap();
+        assert!(record.get_utc_datetime("expires_at").unwrap() > &Utc::now());
+    }
+
+    #[test]
+    fn it_records_panicking_runs_and_releases_their_lease() {
+        let ctx = test_context();
+        let job = job(|_, _| panic!("boom"));
+        let first = at("2026-10-15T13:00:00Z");
+
+        let run = run_job(&ctx, &job, "a", first).unwrap().unwrap();
+        assert_eq!(run.status, FAILED);
+        assert_eq!(run.detail, "panicked: boom");
+        let lease = ctx.db.find_one(LOCKS, doc! { "_id": "test" }).unwrap().unwrap();
+        assert!(lease.get_utc_datetime("locked_until").unwrap() <= &Utc::now());
+    }
+
+    thread_local! {
+        /// Store of the running test, for jobs to break
+        static STORE: RefCell<Option<Arc<MemoryStore>>> = const { RefCell::new(None) };
+    }
+
+    #[test]
+    fn it_records_the_run_when_the_lease_cannot_be_released() {
+        let store = Arc::new(MemoryStore::new());
+        STORE.with(|cell| cell.replace(Some(store.clone())));
+        let ctx = AppContext::with_store(test_context().config.clone(), store.clone()).unwrap();
+        let job = job(|_, _| {
+            STORE.with(|cell| cell.borrow().as_ref().unwrap().fail_writes_to(LOCKS));
+            Ok("done".into())
+        });
+
+        let run = run_job(&ctx, &job, "a", Utc::now()).unwrap().unwrap();
+        assert_eq!(run.status, SUCCEEDED);
+        assert_eq!(find_runs(&*store, "test", 10).unwrap().len(), 1);
+    }
+}
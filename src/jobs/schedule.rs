@@ -0,0 +1,182 @@
+//! Cron-like schedules of five fields, evaluated in UTC:
+//!
+//! ```text
+//! minute hour day-of-month month day-of-week
+//! */15   *    *            *     1-5
+//! ```
+//!
+//! Fields take `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and comma
+//! separated lists of those. Day of week counts from 0 (or 7) for Sunday. As in
+//! cron, when both day fields are restricted either of them matching is enough.
+//! `@hourly`, `@daily` and `@weekly` are shorthands.
+
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// Years searched for the next match before a schedule is deemed never to fire
+const SEARCH_YEARS: i64 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("`{}` must have 5 fields, got {}", expression, fields.len()));
+        }
+        let mut weekdays = field(fields[4], 0, 7)?;
+        // 7 is another name for Sunday
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = Schedule {
+            expression: expression.trim().to_string(),
+            minutes: field(fields[0], 0, 59)?,
+            hours: field(fields[1], 0, 23)?,
+            days: field(fields[2], 1, 31)?,
+            months: field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        };
+        if schedule.next_after(Utc.timestamp(0, 0)).is_none() {
+            return Err(format!("`{}` never fires", expression));
+        }
+        Ok(schedule)
+    }
+
+    /// First time the schedule fires strictly after `after`, to the minute
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut at = after.date().and_hms(after.hour(), after.minute(), 0) + Duration::minutes(1);
+        let limit = at + Duration::days(366 * SEARCH_YEARS);
+        while at < limit {
+            if !has(self.months, at.month()) {
+                let (year, month) = if at.month() == 12 { (at.year() + 1, 1) } else { (at.year(), at.month() + 1) };
+                at = Utc.ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.matches_day(at) {
+                at = (at.date() + Duration::days(1)).and_hms(0, 0, 0);
+            } else if !has(self.hours, at.hour()) {
+                at = at.date().and_hms(at.hour(), 0, 0) + Duration::hours(1);
+            } else if !has(self.minutes, at.minute()) {
+                at = at + Duration::minutes(1);
+            } else {
+                return Some(at);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, at: DateTime<Utc>) -> bool {
+        let day = has(self.days, at.day());
+        let weekday = has(self.weekdays, at.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.expression)
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & 1 << value != 0
+}
+
+/// Bit set of the values a field matches
+fn field(spec: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0;
+    for part in spec.split(',') {
+        let invalid = || format!("`{}` is not a valid field, values run from {} to {}", spec, min, max);
+        let (range, step) = match part.find('/') {
+            Some(slash) => {
+                let step: u32 = part[slash + 1..].parse().map_err(|_| invalid())?;
+                (&part[..slash], step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.find('-') {
+                Some(dash) => (
+                    range[..dash].parse().map_err(|_| invalid())?,
+                    range[dash + 1..].parse().map_err(|_| invalid())?,
+                ),
+                // `a/n` runs from a to the end of the field
+                None if part.contains('/') => (range.parse().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> DateTime<Utc> {
+        Schedule::parse(expression).unwrap().next_after(at(after)).unwrap()
+    }
+
+    #[test]
+    fn it_finds_the_next_run() {
+        assert_eq!(next("*/15 * * * *", "2026-10-15T13:45:10Z"), at("2026-10-15T14:00:00Z"));
+        assert_eq!(next("*/15 * * * *", "2026-10-15T14:00:00Z"), at("2026-10-15T14:15:00Z"));
+        assert_eq!(next("@hourly", "2026-10-15T13:45:10Z"), at("2026-10-15T14:00:00Z"));
+        assert_eq!(next("30 2 * * *", "2026-10-15T13:45:10Z"), at("2026-10-16T02:30:00Z"));
+        assert_eq!(next("0 9 * * 1-5", "2026-10-16T10:00:00Z"), at("2026-10-19T09:00:00Z"));
+        assert_eq!(next("0 0 1 */3 *", "2026-10-15T00:00:00Z"), at("2027-01-01T00:00:00Z"));
+        assert_eq!(next("0 0 29 2 *", "2026-03-01T00:00:00Z"), at("2028-02-29T00:00:00Z"));
+        // Either day field matching is enough when both are restricted
+        assert_eq!(next("0 0 20 * 7", "2026-10-15T00:00:00Z"), at("2026-10-18T00:00:00Z"));
+        assert_eq!(next("0 12 5,20 * *", "2026-10-15T00:00:00Z"), at("2026-10-20T12:00:00Z"));
+    }
+
+    #[test]
+    fn it_rejects_invalid_schedules() {
+        for expression in &["", "* * * *", "60 * * * *", "* 24 * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *", "0 0 31 2 *"] {
+            assert!(Schedule::parse(expression).is_err(), "{} was accepted", expression);
+        }
+        assert_eq!(Schedule::parse(" @daily ").unwrap().to_string(), "@daily");
+    }
+}
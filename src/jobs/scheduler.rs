@@ -0,0 +1,65 @@
+//! Actor firing the registered jobs on their schedules.
+
+use actix::prelude::*;
+use actix_web::web::{self, Data};
+use chrono::{DateTime, Utc};
+
+use super::{instance_name, run_job, Job};
+use crate::context::AppContext;
+use crate::errors::ApiError;
+
+pub struct Scheduler {
+    ctx: Data<AppContext>,
+    jobs: Vec<Job>,
+    owner: String,
+}
+
+impl Scheduler {
+    pub fn new(ctx: Data<AppContext>, jobs: Vec<Job>) -> Self {
+        Scheduler {
+            ctx,
+            jobs,
+            owner: instance_name(),
+        }
+    }
+
+    /// Fire job `index` at its first run after `after`, then schedule the one following
+    fn schedule(&self, index: usize, after: DateTime<Utc>, ctx: &mut Context<Self>) {
+        let next = match self.jobs[index].schedule.next_after(after) {
+            Some(next) => next,
+            None => return,
+        };
+        let delay = (next - Utc::now()).to_std().unwrap_or_default();
+        ctx.run_later(delay, move |scheduler, ctx| {
+            scheduler.run(index, next);
+            scheduler.schedule(index, next, ctx);
+        });
+    }
+
+    /// Run job `index` on the blocking pool, leaving the actor free to fire other jobs
+    fn run(&self, index: usize, scheduled_at: DateTime<Utc>) {
+        let app = self.ctx.clone();
+        let job = self.jobs[index].clone();
+        let owner = self.owner.clone();
+        actix_rt::spawn(async move {
+            let name = job.name;
+            match web::block(move || run_job(&app, &job, &owner, scheduled_at)).await {
+                Ok(Some(run)) => info!("Job {} {}: {}", name, run.status, run.detail),
+                Ok(None) => debug!("Job {} run of {} taken by another instance", name, scheduled_at),
+                Err(e) => error!("Job {} could not run: {}", name, ApiError::from(e)),
+            }
+        });
+    }
+}
+
+impl Actor for Scheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let now = Utc::now();
+        for index in 0..self.jobs.len() {
+            info!("Scheduled job {} at `{}`", self.jobs[index].name, self.jobs[index].schedule);
+            self.schedule(index, now, ctx);
+        }
+    }
+}
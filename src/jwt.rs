@@ -1,8 +1,12 @@
-use crate::config::CONFIG;
+use crate::config::Config;
 use crate::errors::ApiError;
-use actix_identity::{CookieIdentityPolicy, IdentityService};
+use actix_identity::{CookieIdentityPolicy, IdentityPolicy, IdentityService};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderMap};
+use actix_web::Error;
 use argon2rs::argon2i_simple;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::future::{ok, Ready};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -10,21 +14,71 @@ pub struct PrivateClaim {
     pub user_id: String,
     pub email: String,
     exp: i64,
+    /// Absent from tokens issued before sessions could be revoked
+    #[serde(default)]
+    iat: i64,
+    /// `iat` in milliseconds, so a password change revokes the tokens issued earlier in the same second
+    #[serde(default)]
+    iat_ms: Option<i64>,
+    #[serde(default)]
+    pub mfa_pending: bool,
+    /// Session the token belongs to, absent from tokens issued before sessions were recorded
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 impl PrivateClaim {
-    pub fn new(user_id: String, email: String) -> Self {
+    pub fn new(config: &Config, user_id: String, email: String) -> Self {
+        let now = Utc::now();
         Self {
             user_id,
             email,
-            exp: (Utc::now() + Duration::hours(CONFIG.jwt_expiration)).timestamp(),
+            exp: (now + Duration::hours(config.jwt_expiration)).timestamp(),
+            iat: now.timestamp(),
+            iat_ms: Some(now.timestamp_millis()),
+            mfa_pending: false,
+            session_id: None,
         }
     }
+
+    /// Short-lived claim proving the password step of a two-factor login.
+    /// It only grants exchanging a one-time code for a session.
+    pub fn new_mfa_pending(config: &Config, user_id: String, email: String) -> Self {
+        let now = Utc::now();
+        Self {
+            user_id,
+            email,
+            exp: (now + Duration::minutes(config.mfa_pending_expiration)).timestamp(),
+            iat: now.timestamp(),
+            iat_ms: Some(now.timestamp_millis()),
+            mfa_pending: true,
+            session_id: None,
+        }
+    }
+
+    /// Claim of the token of session `id`
+    pub fn with_session(self, id: String) -> Self {
+        Self {
+            session_id: Some(id),
+            ..self
+        }
+    }
+
+    /// When the token expires
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        Utc.timestamp(self.exp, 0)
+    }
+
+    /// Unix timestamp in milliseconds of when the token was issued,
+    /// the start of its second for tokens carrying only `iat`
+    pub fn issued_at_ms(&self) -> i64 {
+        self.iat_ms.unwrap_or(self.iat * 1000)
+    }
 }
 
 /// Create a json web token (JWT)
-pub fn create_jwt(private_claim: PrivateClaim) -> Result<String, ApiError> {
-    let encoding_key = EncodingKey::from_secret(&CONFIG.jwt_key.as_ref());
+pub fn create_jwt(config: &Config, private_claim: PrivateClaim) -> Result<String, ApiError> {
+    let encoding_key = EncodingKey::from_secret(&config.jwt_key.as_ref());
     encode(
         &Header::default(),
         &private_claim,
@@ -34,8 +88,8 @@ pub fn create_jwt(private_claim: PrivateClaim) -> Result<String, ApiError> {
 }
 
 /// Decode a json web token (JWT)
-pub fn decode_jwt(token: &str) -> Result<PrivateClaim, ApiError> {
-    let decoding_key = DecodingKey::from_secret(&CONFIG.jwt_key.as_ref());
+pub fn decode_jwt(config: &Config, token: &str) -> Result<PrivateClaim, ApiError> {
+    let decoding_key = DecodingKey::from_secret(&config.jwt_key.as_ref());
     decode::<PrivateClaim>(token, &decoding_key, &Validation::default())
         .map(|data| data.claims)
         .map_err(|e| ApiError::CannotDecodeJwtToken(e.to_string()))
@@ -44,57 +98,114 @@ pub fn decode_jwt(token: &str) -> Result<PrivateClaim, ApiError> {
 /// Encrypt a password
 ///
 /// Uses the argon2i algorithm.
-/// auth_salt is environment-configured.
-pub fn hash(password: &str) -> String {
-    argon2i_simple(&password, &CONFIG.auth_salt)
+/// auth_salt comes from the config.
+pub fn hash(config: &Config, password: &str) -> String {
+    argon2i_simple(&password, &config.auth_salt)
         .iter()
         .map(|b| format!("{:02x}", b))
         .collect()
 }
 
 /// Gets the identidy service for injection into an Actix app
-pub fn get_identity_service() -> IdentityService<CookieIdentityPolicy> {
-    IdentityService::new(
-        CookieIdentityPolicy::new(&CONFIG.session_key.as_ref())
-            .name(&CONFIG.session_name)
-            .max_age_time(chrono::Duration::minutes(CONFIG.session_timeout))
-            .secure(CONFIG.session_secure),
-    )
+pub fn get_identity_service(config: &Config) -> IdentityService<BearerOrCookiePolicy> {
+    IdentityService::new(BearerOrCookiePolicy(
+        CookieIdentityPolicy::new(&config.session_key.as_ref())
+            .name(&config.session_name)
+            .max_age_time(chrono::Duration::minutes(config.session_timeout))
+            .secure(config.session_secure),
+    ))
+}
+
+/// Reads the JWT from an `Authorization: Bearer` header, else from the session cookie.
+/// Logging in still sets the cookie, API clients send the JWT from the response body.
+pub struct BearerOrCookiePolicy(CookieIdentityPolicy);
+
+impl IdentityPolicy for BearerOrCookiePolicy {
+    type Future = Ready<Result<Option<String>, Error>>;
+    type ResponseFuture = Ready<Result<(), Error>>;
+
+    fn from_request(&self, request: &mut ServiceRequest) -> Self::Future {
+        match bearer_token(request.headers()) {
+            Some(token) => ok(Some(token.into())),
+            None => self.0.from_request(request),
+        }
+    }
+
+    fn to_response<B>(
+        &self,
+        identity: Option<String>,
+        changed: bool,
+        response: &mut ServiceResponse<B>,
+    ) -> Self::ResponseFuture {
+        self.0.to_response(identity, changed, response)
+    }
+}
+
+/// Token of an `Authorization: Bearer <token>` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token)
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::tests::helpers::tests::test_config;
     static UID: &str = "bsjung";
     static EMAIL: &str = "bsjung@gmail.com";
 
     #[test]
     fn it_hashes_a_password() {
         let password = "password";
-        let hashed = hash(password);
+        let hashed = hash(&test_config(), password);
         assert_ne!(password, hashed);
     }
 
     #[test]
     fn it_matches_2_hashed_passwords() {
         let password = "password";
-        let hashed = hash(password);
-        let hashed_again = hash(password);
+        let hashed = hash(&test_config(), password);
+        let hashed_again = hash(&test_config(), password);
         assert_eq!(hashed, hashed_again);
     }
 
     #[test]
     fn it_creates_a_jwt() {
-        let private_claim = PrivateClaim::new(UID.into(), EMAIL.into());
-        let jwt = create_jwt(private_claim);
+        let private_claim = PrivateClaim::new(&test_config(), UID.into(), EMAIL.into());
+        let jwt = create_jwt(&test_config(), private_claim);
         assert!(jwt.is_ok());
     }
 
     #[test]
     fn it_decodes_a_jwt() {
-        let private_claim = PrivateClaim::new(UID.into(), EMAIL.into());
-        let jwt = create_jwt(private_claim.clone()).unwrap();
-        let decoded = decode_jwt(&jwt).unwrap();
+        let private_claim = PrivateClaim::new(&test_config(), UID.into(), EMAIL.into());
+        let jwt = create_jwt(&test_config(), private_claim.clone()).unwrap();
+        let decoded = decode_jwt(&test_config(), &jwt).unwrap();
         assert_eq!(private_claim, decoded);
     }
+
+    #[test]
+    fn it_records_when_a_jwt_was_issued() {
+        let before = Utc::now().timestamp_millis();
+        let private_claim = PrivateClaim::new(&test_config(), UID.into(), EMAIL.into());
+        assert!(private_claim.issued_at_ms() >= before);
+        assert!(private_claim.issued_at_ms() <= Utc::now().timestamp_millis());
+    }
+
+    #[test]
+    fn it_decodes_a_jwt_issued_before_issue_times_were_recorded() {
+        let exp = (Utc::now() + Duration::hours(1)).timestamp();
+        let claims = serde_json::json!({ "user_id": UID, "email": EMAIL, "exp": exp });
+        let key = EncodingKey::from_secret(test_config().jwt_key.as_ref());
+        let jwt = encode(&Header::default(), &claims, &key).unwrap();
+
+        let decoded = decode_jwt(&test_config(), &jwt).unwrap();
+        assert_eq!(decoded.user_id, UID);
+        assert_eq!(decoded.issued_at_ms(), 0);
+    }
 }
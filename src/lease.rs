@@ -0,0 +1,133 @@
+//! Leases keeping instances that share a database from doing the same work at once.
+//!
+//! A lease is a document held by its `owner` until `locked_until`. The owner
+//! renews it every `HEARTBEAT` while working; a lease left by a crashed
+//! instance lapses after `LEASE_SECONDS` and may be taken over.
+
+use bson::Document;
+use chrono::{Duration, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use crate::errors::ApiError;
+use crate::store::Store;
+
+/// A lease not renewed for this long is considered abandoned by a crashed instance
+pub const LEASE_SECONDS: i64 = 120;
+
+/// How often the owner of a lease renews it
+const HEARTBEAT: StdDuration = StdDuration::from_secs(30);
+
+/// Lease `id` in `collection`, as seen by `owner`
+pub struct Lease<'a> {
+    db: &'a dyn Store,
+    collection: &'a str,
+    id: &'a str,
+    owner: &'a str,
+}
+
+impl<'a> Lease<'a> {
+    pub fn new(db: &'a dyn Store, collection: &'a str, id: &'a str, owner: &'a str) -> Self {
+        Lease { db, collection, id, owner }
+    }
+
+    /// Take the lease, setting `fields` on it. A lease held by another owner is
+    /// only taken over once it lapsed and also matches `filter`.
+    /// False when the lease is not free.
+    pub fn acquire(&self, filter: Document, fields: Document) -> Result<bool, ApiError> {
+        let now = Utc::now();
+        let mut lease = fields;
+        lease.insert("owner", self.owner);
+        lease.insert("locked_until", now + Duration::seconds(LEASE_SECONDS));
+        let mut new = lease.clone();
+        new.insert("_id", self.id);
+
+        match self.db.insert_one(self.collection, new) {
+            Ok(_) => Ok(true),
+            Err(ApiError::Conflict(_)) => {
+                let mut lapsed = filter;
+                lapsed.insert("_id", self.id);
+                lapsed.insert("locked_until", doc! { "$lt": now });
+                Ok(self.db.find_one_and_update(self.collection, lapsed, doc! { "$set": lease })?.is_some())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Extend the lease, false if `owner` no longer holds it
+    pub fn renew(&self) -> Result<bool, ApiError> {
+        let update = doc! { "$set": { "locked_until": Utc::now() + Duration::seconds(LEASE_SECONDS) } };
+        Ok(self.db.update_one(self.collection, self.held(), update)?.matched_count > 0)
+    }
+
+    /// Let the lease lapse now. Its document stays for the `filter` of later takeovers.
+    pub fn release(&self) -> Result<(), ApiError> {
+        self.db.update_one(self.collection, self.held(), doc! { "$set": { "locked_until": Utc::now() } })?;
+        Ok(())
+    }
+
+    /// Run `f` while renewing the lease every `HEARTBEAT`. `f` is passed a flag
+    /// set once another instance took the lease over, after which it should stop.
+    pub fn hold<T>(&self, f: impl FnOnce(&AtomicBool) -> T) -> T {
+        let lost = AtomicBool::new(false);
+        let (done, stopped) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            let lost = &lost;
+            scope.spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT) {
+                    match self.renew() {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            warn!("Lease {} in {} was taken over by another instance", self.id, self.collection);
+                            lost.store(true, Ordering::SeqCst);
+                            break;
+                        }
+                        Err(e) => warn!("Could not renew lease {} in {}: {}", self.id, self.collection, e),
+                    }
+                }
+            });
+            let result = f(lost);
+            drop(done);
+            result
+        })
+    }
+
+    fn held(&self) -> Document {
+        doc! { "_id": self.id, "owner": self.owner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn it_takes_over_a_lapsed_lease_only() {
+        let db = MemoryStore::new();
+        assert!(Lease::new(&db, "locks", "job", "a").acquire(doc! {}, doc! {}).unwrap());
+        assert!(!Lease::new(&db, "locks", "job", "b").acquire(doc! {}, doc! {}).unwrap());
+
+        Lease::new(&db, "locks", "job", "a").release().unwrap();
+        assert!(Lease::new(&db, "locks", "job", "b").acquire(doc! {}, doc! {}).unwrap());
+        let lease = db.find_one("locks", doc! { "_id": "job" }).unwrap().unwrap();
+        assert_eq!(lease.get_str("owner").unwrap(), "b");
+    }
+
+    #[test]
+    fn it_renews_the_lease_of_its_owner_only() {
+        let db = MemoryStore::new();
+        let lease = Lease::new(&db, "locks", "job", "a");
+        lease.acquire(doc! {}, doc! {}).unwrap();
+        let lapsed = doc! { "$set": { "locked_until": Utc::now() } };
+        db.update_one("locks", doc! { "_id": "job" }, lapsed).unwrap();
+
+        assert!(lease.renew().unwrap());
+        assert!(!Lease::new(&db, "locks", "job", "b").renew().unwrap());
+        let stored = db.find_one("locks", doc! { "_id": "job" }).unwrap().unwrap();
+        assert!(stored.get_utc_datetime("locked_until").unwrap() > &(Utc::now() + Duration::seconds(LEASE_SECONDS - 5)));
+        assert!(lease.hold(|lost| !lost.load(Ordering::SeqCst)));
+    }
+}
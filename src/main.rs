@@ -1,43 +1,90 @@
 #[macro_use]
 extern crate bson;
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate validator_derive;
 
+mod cache;
+mod cli;
+mod context;
 mod jwt;
-mod state;
+mod telemetry;
 mod routes;
 mod config;
 mod errors;
 mod utils;
 mod validate;
+mod metrics;
 mod middleware;
+mod redact;
+mod totp;
 
 mod database;
+mod store;
+mod prices;
+mod migrations;
 mod models;
 mod handlers;
+mod jobs;
+mod lease;
 mod tests;
 
-use actix_web::{App, HttpServer};
-use crate::config::CONFIG;
-use crate::state::new_state;
+use actix::Actor;
+use actix_web::{web::Data, App, HttpServer};
+use crate::context::AppContext;
+use crate::jwt::get_identity_service;
+use crate::middleware::metrics::Metrics;
+use crate::middleware::request_id::RequestId;
+use crate::middleware::tracing::Tracing;
 use crate::routes::routes;
 use actix_cors::Cors;
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()>{
-    utils::init_logger();
-    let data = new_state::<String>();
+    let matches = cli::app().get_matches();
+    let overrides: Vec<&str> = matches.values_of("set").map(Iterator::collect).unwrap_or_default();
+    let config = match config::load(matches.value_of("config"), &overrides) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Configuration Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+    utils::init_logger(&config);
+
+    let ctx = AppContext::new(config).map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    // Administrative subcommands run and exit, anything else serves HTTP
+    if let (name, Some(sub_matches)) = matches.subcommand() {
+        if name != "serve" {
+            return cli::run(&ctx, name, sub_matches).map_err(|e| std::io::Error::other(e.to_string()));
+        }
+    }
+
+    telemetry::init(&ctx.config)?;
+    if let Err(error) = database::ensure_indexes(&*ctx.db) {
+        eprintln!("Database Error: {}", error);
+        std::process::exit(1);
+    }
 
-    let binding_address = &CONFIG.server;
+    let binding_address = ctx.config.server.clone();
+    let ctx = Data::new(ctx);
+    let scheduled = jobs::jobs(&ctx.config).map_err(|e| std::io::Error::other(e.to_string()))?;
+    jobs::Scheduler::new(ctx.clone(), scheduled).start();
     HttpServer::new(move|| {
         App::new()
             .wrap(Cors::new().supports_credentials().finish())
-            .app_data(data.clone())
+            .wrap(Metrics)
+            .wrap(Tracing)
+            .wrap(RequestId)
+            .wrap(get_identity_service(&ctx.config))
+            .app_data(ctx.clone())
             .configure(routes)
     })
-    .bind(binding_address)
+    .bind(&binding_address)
     .expect(&format!("Can not bind to {}", binding_address) )
     .run()
     .await
@@ -0,0 +1,78 @@
+//! Prometheus metrics for HTTP requests and MongoDB operations.
+
+use lazy_static::lazy_static;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use crate::telemetry::in_span;
+use std::time::Instant;
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+    pub static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "Number of HTTP requests"),
+        &["method", "route", "status"],
+    ));
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency in seconds"),
+        &["method", "route"],
+    ));
+    pub static ref MONGODB_OPERATION_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("mongodb_operation_duration_seconds", "MongoDB operation latency in seconds"),
+        &["collection", "operation"],
+    ));
+}
+
+fn register<T>(metric: prometheus::Result<T>) -> T
+where
+    T: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+/// Record a finished HTTP request
+pub fn observe_request(method: &str, route: &str, status: u16, started: Instant) {
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Time a MongoDB operation, in a tracing span of its own
+pub fn time_db<T>(collection: &str, operation: &str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let span_name = format!("mongodb.{}", operation);
+    let attributes = [("db.collection", collection), ("db.operation", operation)];
+    let result = in_span(&span_name, &attributes, f);
+    MONGODB_OPERATION_DURATION
+        .with_label_values(&[collection, operation])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Render all metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Could not encode metrics");
+    String::from_utf8(buffer).expect("Metrics are valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_recorded_metrics() {
+        observe_request("GET", "/api/v1/user/{id}", 200, Instant::now());
+        time_db("users", "find", || ());
+        let rendered = render();
+        assert!(rendered.contains("http_requests_total{method=\"GET\",route=\"/api/v1/user/{id}\",status=\"200\"}"));
+        assert!(rendered.contains("mongodb_operation_duration_seconds_count{collection=\"users\",operation=\"find\"}"));
+    }
+}
@@ -1,5 +1,9 @@
+use crate::context::AppContext;
 use crate::jwt::{decode_jwt, PrivateClaim};
 use crate::errors::ApiError;
+use crate::middleware::request_id::set_current_user_id;
+use crate::models::session::is_session_active;
+use crate::models::user::is_session_revoked;
 use actix_identity::RequestIdentity;
 use actix_service::{Service, Transform};
 use actix_web::{
@@ -10,6 +14,9 @@ use futures::{Future, future::{ok, Ready}};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// Routes reachable without a session
+const PUBLIC_PATHS: [&str; 2] = ["/api/v1/auth/login", "/api/v1/auth/login/mfa"];
+
 pub struct Auth;
 
 impl<S, B> Transform<S> for Auth
@@ -47,10 +54,24 @@ where
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let ctx = req
+            .app_data::<AppContext>()
+            .expect("AppContext must be registered with App::app_data");
         let identity = RequestIdentity::get_identity(&req).unwrap_or("".into());
-        let private_claim: Result<PrivateClaim, ApiError> = decode_jwt(&identity);
-        let is_logged_in = private_claim.is_ok();
-        let unauthorized = !is_logged_in && req.path() != "/api/v1/auth/login";
+        let private_claim: Result<PrivateClaim, ApiError> = decode_jwt(&ctx.config, &identity);
+        let is_logged_in = match private_claim {
+            Ok(claim) if claim.mfa_pending => false,
+            Ok(claim) => {
+                let is_valid = is_session_valid(&ctx, &claim).unwrap_or(false);
+                if is_valid {
+                    // Tag logs and the access log with who is calling
+                    set_current_user_id(&claim.user_id);
+                }
+                is_valid
+            }
+            Err(_) => false,
+        };
+        let unauthorized = !is_logged_in && !PUBLIC_PATHS.contains(&req.path());
 
         if unauthorized {
             return Box::pin(async move {    
@@ -66,3 +87,14 @@ where
         })
     }
 }
+
+/// Whether the session of a token was neither revoked by a password change nor ended by logging out
+fn is_session_valid(ctx: &AppContext, claim: &PrivateClaim) -> Result<bool, ApiError> {
+    if is_session_revoked(&*ctx.db, &*ctx.cache, &claim.user_id, claim.issued_at_ms())? {
+        return Ok(false);
+    }
+    match claim.session_id {
+        Some(ref session_id) => is_session_active(&*ctx.db, session_id),
+        None => Ok(true),
+    }
+}
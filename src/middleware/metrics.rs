@@ -0,0 +1,174 @@
+use crate::metrics::observe_request;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    Error, HttpRequest,
+};
+use futures::{Future, future::{ok, Ready}};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Label for requests that match no route, to bound label cardinality
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service })
+    }
+}
+pub struct MetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let route = route_pattern(res.request());
+            observe_request(&method, &route, res.status().as_u16(), started);
+            Ok(res)
+        })
+    }
+}
+
+/// Rebuild the route pattern of a request, e.g. `/api/v1/user/{id}`,
+/// by putting the matched parameters back in place of their values.
+/// Requests that did not reach a resource, such as unknown paths or those a
+/// scope middleware rejected before routing, share one label instead of their path.
+pub fn route_pattern(req: &HttpRequest) -> String {
+    // Routing consumes the path segment by segment, a resource consumes the rest
+    if !req.match_info().unprocessed().is_empty() {
+        return UNMATCHED_ROUTE.into();
+    }
+    // Parameter values come from the whole decoded path, `req.path()` may still be percent-encoded
+    let params: Vec<(&str, &str)> = req.match_info().iter().collect();
+    pattern_from_params(req.match_info().get_ref().path(), &params).unwrap_or_else(|| UNMATCHED_ROUTE.into())
+}
+
+/// Parameters are matched against path segments from the end,
+/// since dynamic segments follow the static prefix.
+/// None if a parameter is not found, rather than labelling with the raw path.
+fn pattern_from_params(path: &str, params: &[(&str, &str)]) -> Option<String> {
+    let mut segments: Vec<String> = path.split('/').map(String::from).collect();
+    let mut remaining = params.to_vec();
+    for segment in segments.iter_mut().rev() {
+        if let Some(position) = remaining.iter().rposition(|(_, value)| value == segment) {
+            let (name, _) = remaining.remove(position);
+            *segment = format!("{{{}}}", name);
+        }
+    }
+    if remaining.is_empty() {
+        Some(segments.join("/"))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwt::get_identity_service;
+    use crate::metrics::render;
+    use crate::routes::routes;
+    use crate::tests::helpers::tests::test_context;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn it_records_requests_by_route_pattern() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Metrics)
+                .route("/metrics-test/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/metrics-test/42").to_request();
+        test::call_service(&mut app, request).await;
+
+        // Ids sent percent-encoded share the label of the route
+        let request = test::TestRequest::get().uri("/metrics-test/%61bc").to_request();
+        test::call_service(&mut app, request).await;
+
+        let rendered = render();
+        assert!(rendered.contains("route=\"/metrics-test/{id}\",status=\"200\""));
+        assert!(!rendered.contains("/metrics-test/42"));
+        assert!(!rendered.contains("%61bc"));
+    }
+
+    #[actix_rt::test]
+    async fn it_labels_requests_that_reach_no_resource_as_unmatched() {
+        let ctx = test_context();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(ctx.clone())
+                .wrap(Metrics)
+                .wrap(get_identity_service(&ctx.config))
+                .configure(routes),
+        )
+        .await;
+        // Rejected by the Auth middleware of the api scope, before routing within it
+        let request = test::TestRequest::get().uri("/api/v1/coin/SECRET/transactions").to_request();
+        assert_eq!(test::call_service(&mut app, request).await.status(), StatusCode::UNAUTHORIZED);
+        let request = test::TestRequest::get().uri("/no-such-route/12345").to_request();
+        assert_eq!(test::call_service(&mut app, request).await.status(), StatusCode::NOT_FOUND);
+
+        let rendered = render();
+        assert!(rendered.contains("route=\"unmatched\",status=\"401\""));
+        assert!(rendered.contains("route=\"unmatched\",status=\"404\""));
+        assert!(!rendered.contains("SECRET"));
+        assert!(!rendered.contains("12345"));
+    }
+
+    #[test]
+    fn it_replaces_params_with_their_names() {
+        let pattern = pattern_from_params("/api/v1/user/bsjung", &[("id", "bsjung")]);
+        assert_eq!(pattern.as_deref(), Some("/api/v1/user/{id}"));
+    }
+
+    #[test]
+    fn it_only_replaces_the_trailing_occurrence() {
+        let pattern = pattern_from_params("/api/v1/user/user", &[("id", "user")]);
+        assert_eq!(pattern.as_deref(), Some("/api/v1/user/{id}"));
+    }
+
+    #[test]
+    fn it_keeps_static_paths() {
+        assert_eq!(pattern_from_params("/health", &[]).as_deref(), Some("/health"));
+    }
+
+    #[test]
+    fn it_gives_up_on_params_it_cannot_place() {
+        assert_eq!(pattern_from_params("/api/v1/user/%61bc", &[("id", "abc")]), None);
+    }
+}
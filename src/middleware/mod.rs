@@ -1 +1,4 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod metrics;
+pub mod request_id;
+pub mod tracing;
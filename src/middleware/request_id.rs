@@ -0,0 +1,214 @@
+use crate::middleware::metrics::route_pattern;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::{Future, future::{ok, Ready}};
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest incoming request id that is accepted as is
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// What log lines emitted while handling a request are tagged with
+struct RequestContext {
+    request_id: String,
+    user_id: RefCell<Option<String>>,
+}
+
+thread_local! {
+    static CURRENT_REQUEST: RefCell<Option<Rc<RequestContext>>> = const { RefCell::new(None) };
+}
+
+/// Id of the request being handled on this thread, if any.
+/// Set while the request's middleware and handler futures are polled.
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|context| context.request_id.clone())
+    })
+}
+
+/// Id of the authenticated user of the request being handled, if any
+pub fn current_user_id() -> Option<String> {
+    CURRENT_REQUEST.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .and_then(|context| context.user_id.borrow().clone())
+    })
+}
+
+/// Record the authenticated user of the request being handled
+pub fn set_current_user_id(user_id: &str) {
+    CURRENT_REQUEST.with(|current| {
+        if let Some(context) = current.borrow().as_ref() {
+            context.user_id.replace(Some(user_id.into()));
+        }
+    })
+}
+
+/// Run `f` with `context` as the current request
+fn with_context<T>(context: &Rc<RequestContext>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_REQUEST.with(|current| current.replace(Some(context.clone())));
+    let result = f();
+    CURRENT_REQUEST.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Keep an incoming id if it is reasonable, otherwise generate a new one
+fn request_id_from_header(header: Option<&HeaderValue>) -> String {
+    header
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+        .filter(|value| value.chars().all(|c| c.is_ascii_graphic()))
+        .map(String::from)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Assign or accept an X-Request-Id, echo it in the response,
+/// and write one access-log line per request
+pub struct RequestId;
+
+impl<S, B> Transform<S> for RequestId
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddleware { service })
+    }
+}
+pub struct RequestIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestIdMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let request_id = request_id_from_header(req.headers().get(REQUEST_ID_HEADER));
+        let method = req.method().to_string();
+
+        let context = Rc::new(RequestContext {
+            request_id: request_id.clone(),
+            user_id: RefCell::new(None),
+        });
+        let service = &mut self.service;
+        let fut = with_context(&context, || service.call(req));
+
+        Box::pin(RequestIdFuture {
+            context,
+            inner: Box::pin(async move {
+                let mut res = fut.await?;
+
+                let user_id = current_user_id().unwrap_or_else(|| "-".into());
+                info!(
+                    target: "access",
+                    "method={} route={} status={} latency_ms={} user_id={}",
+                    method,
+                    route_pattern(res.request()),
+                    res.status().as_u16(),
+                    started.elapsed().as_millis(),
+                    user_id
+                );
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                Ok(res)
+            }),
+        })
+    }
+}
+
+/// Makes the request current every time the wrapped future is polled
+struct RequestIdFuture<F> {
+    context: Rc<RequestContext>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for RequestIdFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        with_context(&this.context, || inner.as_mut().poll(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ApiError;
+    use actix_web::{test, web, App};
+
+    async fn fail() -> Result<String, ApiError> {
+        Err(ApiError::NotFound("nothing here".into()))
+    }
+
+    #[test]
+    fn it_accepts_a_reasonable_incoming_id() {
+        let header = HeaderValue::from_static("abc-123");
+        assert_eq!(request_id_from_header(Some(&header)), "abc-123");
+    }
+
+    #[test]
+    fn it_replaces_an_unreasonable_incoming_id() {
+        let header = HeaderValue::from_str(&"x".repeat(MAX_REQUEST_ID_LENGTH + 1)).unwrap();
+        assert_eq!(request_id_from_header(Some(&header)).len(), 32);
+        assert_eq!(request_id_from_header(None).len(), 32);
+    }
+
+    #[actix_rt::test]
+    async fn it_echoes_the_request_id_in_responses_and_errors() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestId)
+                .route("/request-id-test", web::get().to(fail)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/request-id-test")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+
+        assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        let body = test::read_body(response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "abc-123");
+        assert_eq!(current_request_id(), None);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::middleware::metrics::route_pattern;
+use crate::telemetry::{InSpan, Span, TraceContext, TRACEPARENT_HEADER};
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::{Future, future::{ok, Ready}};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Open a span per request, continuing the caller's trace from `traceparent`
+/// and returning this server's span context in the response
+pub struct Tracing;
+
+impl<S, B> Transform<S> for Tracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracingMiddleware { service })
+    }
+}
+pub struct TracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for TracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let parent = req
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::from_traceparent);
+        let method = req.method().to_string();
+        let span = Span::start(&format!("HTTP {}", method), parent.as_ref());
+        span.set_attribute("http.method", &method);
+        span.set_attribute("http.target", req.path());
+
+        let service = &mut self.service;
+        let fut = crate::telemetry::with_span(&span, || service.call(req));
+        let request_span = span.clone();
+
+        Box::pin(InSpan::new(span, async move {
+            let result = fut.await;
+            match result {
+                Ok(mut res) => {
+                    let route = route_pattern(res.request());
+                    request_span.set_name(&format!("{} {}", method, route));
+                    request_span.set_attribute("http.route", route);
+                    request_span.set_attribute("http.status_code", res.status().as_u16());
+                    request_span.finish();
+
+                    let traceparent = request_span.context().to_traceparent();
+                    if let Ok(value) = HeaderValue::from_str(&traceparent) {
+                        res.headers_mut()
+                            .insert(HeaderName::from_static(TRACEPARENT_HEADER), value);
+                    }
+                    Ok(res)
+                }
+                Err(e) => {
+                    request_span.set_attribute("error", &e);
+                    request_span.finish();
+                    Err(e)
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn it_continues_the_incoming_trace() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Tracing)
+                .route("/tracing-test", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/tracing-test")
+            .header(
+                TRACEPARENT_HEADER,
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+
+        let traceparent = response.headers().get(TRACEPARENT_HEADER).unwrap();
+        let context = TraceContext::from_traceparent(traceparent.to_str().unwrap()).unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+    }
+}
@@ -0,0 +1,39 @@
+//! Give every existing user the default "user" role.
+
+use crate::errors::ApiError;
+use crate::store::Store;
+
+pub fn up(db: &dyn Store) -> Result<(), ApiError> {
+    let filter = doc! { "roles" : { "$exists" : false } };
+    let update = doc! { "$set" : { "roles" : ["user"] } };
+    db.update_many("users", filter, update)?;
+    Ok(())
+}
+
+/// Only the default roles `up` gave are taken back, admins keep theirs
+pub fn down(db: &dyn Store) -> Result<(), ApiError> {
+    let filter = doc! { "roles" : ["user"] };
+    let update = doc! { "$unset" : { "roles" : "" } };
+    db.update_many("users", filter, update)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn it_keeps_roles_assigned_since_on_rollback() {
+        let db = MemoryStore::new();
+        db.insert_one("users", doc! { "uid": "plain" }).unwrap();
+        db.insert_one("users", doc! { "uid": "admin", "roles": ["admin", "user"] }).unwrap();
+
+        up(&db).unwrap();
+        down(&db).unwrap();
+        let plain = db.find_one("users", doc! { "uid": "plain" }).unwrap().unwrap();
+        assert!(!plain.contains_key("roles"));
+        let admin = db.find_one("users", doc! { "uid": "admin" }).unwrap().unwrap();
+        assert_eq!(admin.get_array("roles").unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,71 @@
+//! Store coin amounts as integers of the smallest unit of their ticker
+//! instead of decimal strings.
+
+use bson::Bson;
+
+use crate::errors::ApiError;
+use crate::models::amount::{precision, Amount};
+use crate::store::{FindOptions, Store};
+
+pub fn up(db: &dyn Store) -> Result<(), ApiError> {
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let (id, ticker) = key(&holding)?;
+        if let Some(Bson::String(amount)) = holding.get("amount") {
+            let units = Amount::parse(amount.trim(), precision(&ticker))
+                .map_err(|_| ApiError::InternalServerError(format!("Coin {} has an invalid amount {:?}", id, amount)))?
+                .units();
+            db.update_one("coins", doc! { "_id": id }, doc! { "$set": { "amount": units } })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn down(db: &dyn Store) -> Result<(), ApiError> {
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let (id, ticker) = key(&holding)?;
+        if let Ok(units) = holding.get_i64("amount") {
+            let amount = Amount::from_units(units, precision(&ticker)).to_string();
+            db.update_one("coins", doc! { "_id": id }, doc! { "$set": { "amount": amount } })?;
+        }
+    }
+    Ok(())
+}
+
+fn key(holding: &bson::Document) -> Result<(Bson, String), ApiError> {
+    let id = holding.get("_id").cloned().unwrap_or(Bson::Null);
+    let ticker = holding
+        .get_str("ticker")
+        .map_err(|_| ApiError::InternalServerError(format!("Coin {} has no ticker", id)))?;
+    Ok((id, ticker.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::tests::test_context;
+
+    #[test]
+    fn it_converts_amounts_both_ways() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": "1.5" }).unwrap();
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "ETH", "amount": 7_i64 }).unwrap();
+
+        up(db).unwrap();
+        let btc = db.find_one("coins", doc! { "ticker": "BTC" }).unwrap().unwrap();
+        assert_eq!(btc.get_i64("amount").unwrap(), 150_000_000);
+        let eth = db.find_one("coins", doc! { "ticker": "ETH" }).unwrap().unwrap();
+        assert_eq!(eth.get_i64("amount").unwrap(), 7);
+
+        down(db).unwrap();
+        let btc = db.find_one("coins", doc! { "ticker": "BTC" }).unwrap().unwrap();
+        assert_eq!(btc.get_str("amount").unwrap(), "1.50000000");
+    }
+
+    #[test]
+    fn it_stops_on_an_invalid_amount() {
+        let ctx = test_context();
+        ctx.db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": "lots" }).unwrap();
+        assert!(up(&*ctx.db).is_err());
+    }
+}
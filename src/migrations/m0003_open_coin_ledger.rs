@@ -0,0 +1,65 @@
+//! Record the balance of holdings that predate the transaction ledger
+//! as an "open" entry, so reconciliation starts from their current balance.
+
+use chrono::Utc;
+
+use crate::errors::ApiError;
+use crate::models::amount::{precision, Amount};
+use crate::models::transaction::{record, Entry, Kind};
+use crate::store::{FindOptions, Store};
+
+const ACTOR: &str = "migration";
+
+pub fn up(db: &dyn Store) -> Result<(), ApiError> {
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let (uid, ticker, units) = match (holding.get_str("uid"), holding.get_str("ticker"), holding.get_i64("amount")) {
+            (Ok(uid), Ok(ticker), Ok(units)) => (uid, ticker, units),
+            _ => continue,
+        };
+        if db.find_one("coin_transactions", doc! { "uid": uid, "ticker": ticker })?.is_some() {
+            continue;
+        }
+        let precision = precision(ticker);
+        let amount = Amount::from_units(units, precision);
+        let before = Amount::from_units(0, precision);
+        let entry = Entry {
+            uid,
+            ticker,
+            kind: Kind::Open,
+            amount,
+            before,
+            after: amount,
+            actor: ACTOR,
+            reference: None,
+        };
+        record(db, entry, Utc::now())?;
+    }
+    Ok(())
+}
+
+pub fn down(db: &dyn Store) -> Result<(), ApiError> {
+    db.delete_many("coin_transactions", doc! { "actor": ACTOR })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::reconcile;
+    use crate::tests::helpers::tests::test_context;
+
+    #[test]
+    fn it_opens_the_ledger_of_existing_holdings() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": 5_i64 }).unwrap();
+        assert_eq!(reconcile(db).unwrap().len(), 1);
+
+        up(db).unwrap();
+        up(db).unwrap();
+        assert!(reconcile(db).unwrap().is_empty());
+
+        down(db).unwrap();
+        assert_eq!(reconcile(db).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Fill the asset catalog with the default assets and every ticker already held,
+//! and give holdings the catalog name of their asset.
+//!
+//! Tickers stored in another case, such as "btc", are rewritten in canonical
+//! form on holdings, their ledger and transfers; a holding that then duplicates
+//! one of its owner is merged into it. Assets this migration adds are marked
+//! so `down` removes them alone; rewritten tickers are kept.
+
+use std::collections::HashSet;
+
+use super::merge_duplicate_holdings;
+use crate::errors::ApiError;
+use crate::models::amount::precision;
+use crate::models::asset::{create_asset, find_asset, get_assets, normalize_ticker, register_defaults, Asset};
+use crate::store::{FindOptions, Store};
+
+/// Marks the assets this migration added to the catalog
+const SEEDED_BY: &str = "seeded_by_migration";
+const VERSION: i32 = 4;
+
+pub fn up(db: &dyn Store) -> Result<(), ApiError> {
+    let existing: HashSet<String> = get_assets(db)?.into_iter().map(|asset| asset.ticker).collect();
+    register_defaults(db)?;
+    let renamed = normalize_tickers(db)?;
+    let merged = merge_duplicate_holdings(db)?;
+    if renamed + merged > 0 {
+        info!("Rewrote {} holding ticker(s), merged {} duplicate holding(s)", renamed, merged);
+    }
+
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let (ticker, coin) = match (holding.get_str("ticker"), holding.get_str("coin")) {
+            (Ok(ticker), Ok(coin)) => (ticker, coin),
+            _ => continue,
+        };
+        if normalize_ticker(ticker).ok().as_deref() != Some(ticker) {
+            continue;
+        }
+        if let Err(ApiError::NotFound(_)) = find_asset(db, ticker) {
+            // Amounts were stored with the built-in precision
+            let asset = Asset {
+                ticker: ticker.into(),
+                name: coin.into(),
+                decimals: precision(ticker),
+                enabled: true,
+            };
+            create_asset(db, &asset)?;
+        }
+    }
+    for asset in get_assets(db)? {
+        db.update_many("coins", doc! { "ticker": &asset.ticker }, doc! { "$set": { "coin": &asset.name } })?;
+        if !existing.contains(&asset.ticker) {
+            db.update_one("assets", doc! { "ticker": &asset.ticker }, doc! { "$set": { SEEDED_BY: VERSION } })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn down(db: &dyn Store) -> Result<(), ApiError> {
+    db.delete_many("assets", doc! { SEEDED_BY: VERSION })?;
+    Ok(())
+}
+
+/// Rewrite the tickers of holdings, their ledger and transfers in canonical form.
+/// A holding whose owner already holds the canonical ticker is folded into it.
+fn normalize_tickers(db: &dyn Store) -> Result<usize, ApiError> {
+    let mut renamed = 0;
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let (uid, ticker, units) = match (holding.get_str("uid"), holding.get_str("ticker"), holding.get_i64("amount")) {
+            (Ok(uid), Ok(ticker), Ok(units)) => (uid, ticker, units),
+            _ => continue,
+        };
+        let canonical = match normalize_ticker(ticker.trim()) {
+            Ok(canonical) if canonical == ticker => continue,
+            Ok(canonical) => canonical,
+            Err(_) => {
+                warn!("Holding of {} left out of the asset catalog, {:?} is not a valid ticker", uid, ticker);
+                continue;
+            }
+        };
+
+        let id = holding.get("_id").cloned().unwrap_or(bson::Bson::Null);
+        let rename = doc! { "$set": { "ticker": &canonical } };
+        match db.update_one("coins", doc! { "_id": id.clone() }, rename.clone()) {
+            Ok(_) => {}
+            // The unique index refuses a second holding of the ticker, fold this one into it
+            Err(ApiError::Conflict(_)) => {
+                let add = doc! { "$inc": { "amount": units } };
+                db.update_one("coins", doc! { "uid": uid, "ticker": &canonical }, add)?;
+                db.delete_one("coins", doc! { "_id": id })?;
+            }
+            Err(e) => return Err(e),
+        }
+        db.update_many("coin_transactions", doc! { "uid": uid, "ticker": ticker }, rename.clone())?;
+        db.update_many("transfers", doc! { "from": uid, "ticker": ticker }, rename.clone())?;
+        db.update_many("transfers", doc! { "to": uid, "ticker": ticker }, rename)?;
+        renamed += 1;
+    }
+    Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ensure_indexes;
+    use crate::models::asset::decimals;
+    use crate::models::transaction::reconcile;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn it_catalogs_held_assets() {
+        let db = MemoryStore::new();
+        ensure_indexes(&db).unwrap();
+        let sol = Asset {
+            ticker: "SOL".into(),
+            name: "Solana".into(),
+            decimals: 9,
+            enabled: true,
+        };
+        create_asset(&db, &sol).unwrap();
+        db.insert_one("coins", doc! { "uid": "a", "coin": "bitcoin", "ticker": "BTC", "amount": 5_i64 }).unwrap();
+        db.insert_one("coins", doc! { "uid": "a", "coin": "Dogecoin", "ticker": "DOGE", "amount": 5_i64 }).unwrap();
+
+        up(&db).unwrap();
+        up(&db).unwrap();
+        let tickers: Vec<String> = get_assets(&db).unwrap().into_iter().map(|asset| asset.ticker).collect();
+        assert_eq!(tickers, ["BTC", "DOGE", "ETH", "SOL", "USDC", "USDT"]);
+        assert_eq!(decimals(&db, "DOGE").unwrap(), 8);
+        let holding = db.find_one("coins", doc! { "ticker": "BTC" }).unwrap().unwrap();
+        assert_eq!(holding.get_str("coin").unwrap(), "Bitcoin");
+
+        // Only what the migration added is removed
+        down(&db).unwrap();
+        let tickers: Vec<String> = get_assets(&db).unwrap().into_iter().map(|asset| asset.ticker).collect();
+        assert_eq!(tickers, ["SOL"]);
+    }
+
+    #[test]
+    fn it_normalizes_tickers_and_merges_the_holdings_they_duplicate() {
+        let db = MemoryStore::new();
+        ensure_indexes(&db).unwrap();
+        let holdings = [("a", "BTC", 5_i64), ("a", "btc", 7), ("b", "eth", 1), ("c", "b t c", 1)];
+        for (uid, ticker, amount) in &holdings {
+            db.insert_one("coins", doc! { "uid": *uid, "coin": "coin", "ticker": *ticker, "amount": *amount }).unwrap();
+            let entry = doc! { "uid": *uid, "ticker": *ticker, "kind": "open", "amount": *amount };
+            db.insert_one("coin_transactions", entry).unwrap();
+        }
+
+        up(&db).unwrap();
+        let btc = db.find("coins", doc! { "uid": "a" }, FindOptions::default()).unwrap();
+        assert_eq!(btc.len(), 1);
+        assert_eq!(btc[0].get_str("ticker").unwrap(), "BTC");
+        assert_eq!(btc[0].get_i64("amount").unwrap(), 12);
+        assert!(reconcile(&db).unwrap().is_empty());
+        let eth = db.find_one("coins", doc! { "uid": "b" }).unwrap().unwrap();
+        assert_eq!(eth.get_str("ticker").unwrap(), "ETH");
+        assert_eq!(eth.get_str("coin").unwrap(), "Ethereum");
+        // Invalid tickers are left for an operator to fix
+        assert!(db.find_one("coins", doc! { "uid": "c", "ticker": "b t c" }).unwrap().is_some());
+    }
+}
@@ -0,0 +1,65 @@
+//! Merge duplicate holdings of a ticker left by concurrent creations, then make
+//! `uid` and `ticker` unique. Run it before starting a server on a database
+//! holding duplicates: creating the unique index at startup fails on them.
+
+use super::merge_duplicate_holdings;
+use crate::database::indexes;
+use crate::errors::ApiError;
+use crate::store::Store;
+
+pub fn up(db: &dyn Store) -> Result<(), ApiError> {
+    let merged = merge_duplicate_holdings(db)?;
+    if merged > 0 {
+        info!("Merged {} duplicate holding(s)", merged);
+    }
+    let holdings: Vec<_> = indexes().into_iter().filter(|index| index.collection == "coins").collect();
+    db.create_indexes("coins", &holdings)
+}
+
+/// Irreversible: merged holdings cannot be told apart again, so rolling back
+/// fails rather than reporting the version as rolled back
+pub fn down(_: &dyn Store) -> Result<(), ApiError> {
+    Err(ApiError::BadRequest(
+        "Migration 5 (unique_holdings) cannot be rolled back, merged holdings cannot be split again".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::reconcile;
+    use crate::store::MemoryStore;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn it_merges_duplicate_holdings() {
+        // Without the unique index, as on a database predating it
+        let db = MemoryStore::new();
+        let earlier = Utc::now() - Duration::hours(1);
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": 5_i64, "created_at": earlier }).unwrap();
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": 7_i64, "created_at": Utc::now() }).unwrap();
+        db.insert_one("coins", doc! { "uid": "b", "ticker": "BTC", "amount": 1_i64, "created_at": Utc::now() }).unwrap();
+        for (uid, amount) in &[("a", 5_i64), ("a", 7), ("b", 1)] {
+            let entry = doc! { "uid": *uid, "ticker": "BTC", "kind": "open", "amount": *amount };
+            db.insert_one("coin_transactions", entry).unwrap();
+        }
+
+        up(&db).unwrap();
+        up(&db).unwrap();
+        let merged = db.find_one("coins", doc! { "uid": "a" }).unwrap().unwrap();
+        assert_eq!(merged.get_i64("amount").unwrap(), 12);
+        assert_eq!(merged.get_utc_datetime("created_at").unwrap().timestamp(), earlier.timestamp());
+        assert!(reconcile(&db).unwrap().is_empty());
+        let duplicate = db.insert_one("coins", doc! { "uid": "b", "ticker": "BTC", "amount": 1_i64 });
+        assert!(matches!(duplicate, Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn it_refuses_to_roll_back() {
+        let db = MemoryStore::new();
+        crate::migrations::run(&db).unwrap();
+        assert!(crate::migrations::rollback(&db, crate::migrations::migrations().len()).is_err());
+        let applied = crate::migrations::status(&db).unwrap();
+        assert!(applied.iter().find(|migration| migration.version == 5).unwrap().applied_at.is_some());
+    }
+}
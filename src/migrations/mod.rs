@@ -0,0 +1,281 @@
+//! Versioned schema migrations.
+//!
+//! Migrations are registered in `migrations()` in ascending version order.
+//! Applied versions are recorded in the `migrations` collection, and a lock
+//! document in `migration_locks`, renewed while migrations run, keeps
+//! instances from running them concurrently.
+
+mod m0001_add_user_roles;
+mod m0002_coin_amount_units;
+mod m0003_open_coin_ledger;
+mod m0004_asset_catalog;
+mod m0005_unique_holdings;
+
+use bson::Bson;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::errors::ApiError;
+use crate::lease::{Lease, LEASE_SECONDS};
+use crate::store::{FindOptions, Store};
+
+const MIGRATIONS: &str = "migrations";
+const LOCKS: &str = "migration_locks";
+const LOCK_ID: &str = "migrations";
+
+pub type MigrationFn = fn(&dyn Store) -> Result<(), ApiError>;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: MigrationFn,
+    pub down: MigrationFn,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+/// Every migration, in the order they must be applied
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            name: "add_user_roles",
+            up: m0001_add_user_roles::up,
+            down: m0001_add_user_roles::down,
+        },
+        Migration {
+            version: 2,
+            name: "coin_amount_units",
+            up: m0002_coin_amount_units::up,
+            down: m0002_coin_amount_units::down,
+        },
+        Migration {
+            version: 3,
+            name: "open_coin_ledger",
+            up: m0003_open_coin_ledger::up,
+            down: m0003_open_coin_ledger::down,
+        },
+        Migration {
+            version: 4,
+            name: "asset_catalog",
+            up: m0004_asset_catalog::up,
+            down: m0004_asset_catalog::down,
+        },
+        Migration {
+            version: 5,
+            name: "unique_holdings",
+            up: m0005_unique_holdings::up,
+            down: m0005_unique_holdings::down,
+        },
+    ]
+}
+
+/// Apply all pending migrations, returning the versions applied
+pub fn run(db: &dyn Store) -> Result<Vec<i64>, ApiError> {
+    with_lock(db, |lost| {
+        let applied = applied_versions(db)?;
+        let mut versions = Vec::new();
+        for migration in pending(migrations(), &applied) {
+            check_lock(lost, migration.version)?;
+            (migration.up)(db)?;
+            let record = doc! {
+                "version": migration.version,
+                "name": migration.name,
+                "applied_at": Utc::now(),
+            };
+            db.insert_one(MIGRATIONS, record)?;
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    })
+}
+
+/// Roll back the last `steps` applied migrations, returning the versions rolled back
+pub fn rollback(db: &dyn Store, steps: usize) -> Result<Vec<i64>, ApiError> {
+    with_lock(db, |lost| {
+        let applied = applied_versions(db)?;
+        let mut versions = Vec::new();
+        for migration in migrations().into_iter().rev() {
+            if versions.len() == steps {
+                break;
+            }
+            if !applied.contains(&migration.version) {
+                continue;
+            }
+            check_lock(lost, migration.version)?;
+            (migration.down)(db)?;
+            db.delete_one(MIGRATIONS, doc! { "version": migration.version })?;
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    })
+}
+
+/// List every registered migration and when it was applied
+pub fn status(db: &dyn Store) -> Result<Vec<MigrationStatus>, ApiError> {
+    let options = FindOptions {
+        sort: Some(doc! { "version": 1 }),
+        ..Default::default()
+    };
+    let records = db.find(MIGRATIONS, doc! {}, options)?;
+
+    Ok(migrations()
+        .into_iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            name: migration.name,
+            applied_at: records
+                .iter()
+                .find(|record| record.get_i64("version").ok() == Some(migration.version))
+                .and_then(|record| record.get_utc_datetime("applied_at").ok())
+                .cloned(),
+        })
+        .collect())
+}
+
+fn applied_versions(db: &dyn Store) -> Result<Vec<i64>, ApiError> {
+    db.find(MIGRATIONS, doc! {}, FindOptions::default())?
+        .iter()
+        .map(|record| {
+            record
+                .get_i64("version")
+                .map_err(|e| ApiError::InternalServerError(e.to_string()))
+        })
+        .collect()
+}
+
+/// Registered migrations that have not been applied yet, in order
+fn pending(migrations: Vec<Migration>, applied: &[i64]) -> Vec<Migration> {
+    migrations
+        .into_iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect()
+}
+
+/// Fold holdings sharing a uid and ticker into the oldest of them, adding up
+/// their balances. Their ledger entries already share the uid and ticker, so
+/// the merged balance still reconciles. Returns how many holdings were folded away.
+fn merge_duplicate_holdings(db: &dyn Store) -> Result<usize, ApiError> {
+    let options = FindOptions {
+        sort: Some(doc! { "created_at": 1, "_id": 1 }),
+        ..Default::default()
+    };
+    // Oldest holding of each uid and ticker, with the balance it will hold
+    let mut kept: HashMap<(String, String), (Bson, i64)> = HashMap::new();
+    let mut merged = 0;
+    for holding in db.find("coins", doc! {}, options)? {
+        let (uid, ticker, units) = match (holding.get_str("uid"), holding.get_str("ticker"), holding.get_i64("amount")) {
+            (Ok(uid), Ok(ticker), Ok(units)) => (uid.to_string(), ticker.to_string(), units),
+            _ => continue,
+        };
+        let id = holding.get("_id").cloned().unwrap_or(Bson::Null);
+        match kept.get_mut(&(uid.clone(), ticker.clone())) {
+            None => {
+                kept.insert((uid, ticker), (id, units));
+            }
+            Some((kept_id, total)) => {
+                *total = total.checked_add(units).ok_or_else(|| {
+                    ApiError::InternalServerError(format!("Merged {} holding of {} is too large", ticker, uid))
+                })?;
+                let update = doc! { "$set": { "amount": *total, "updated_at": Utc::now() } };
+                db.update_one("coins", doc! { "_id": kept_id.clone() }, update)?;
+                db.delete_one("coins", doc! { "_id": id })?;
+                merged += 1;
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Run `f` while holding the migration lock, see `Lease::hold`.
+/// The result of `f` is returned even if the lock cannot be released, which then lapses.
+fn with_lock<T>(
+    db: &dyn Store,
+    f: impl FnOnce(&AtomicBool) -> Result<T, ApiError>,
+) -> Result<T, ApiError> {
+    let owner = format!("{}-{:x}", std::process::id(), rand::random::<u64>());
+    let lock = Lease::new(db, LOCKS, LOCK_ID, &owner);
+    if !lock.acquire(doc! {}, doc! {})? {
+        return Err(ApiError::Conflict("Migrations are already running on another instance".into()));
+    }
+    let result = lock.hold(f);
+    if let Err(e) = lock.release() {
+        error!("Could not release the migration lock, it lapses in {} seconds: {}", LEASE_SECONDS, e);
+    }
+    result
+}
+
+/// Fail before migration `version` once the lock was lost, so two instances
+/// never go on applying migrations side by side
+fn check_lock(lost: &AtomicBool, version: i64) -> Result<(), ApiError> {
+    if lost.load(Ordering::SeqCst) {
+        return Err(ApiError::Conflict(format!(
+            "The migration lock was taken over by another instance, stopped before migration {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn it_registers_migrations_in_ascending_order() {
+        let versions: Vec<i64> = migrations().iter().map(|m| m.version).collect();
+        let mut sorted = versions.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(versions, sorted);
+    }
+
+    #[test]
+    fn it_skips_applied_migrations() {
+        let remaining: Vec<i64> = pending(migrations(), &[1]).iter().map(|m| m.version).collect();
+        assert!(!remaining.contains(&1));
+
+        let all: Vec<i64> = migrations().iter().map(|m| m.version).collect();
+        let remaining: Vec<i64> = pending(migrations(), &[]).iter().map(|m| m.version).collect();
+        assert_eq!(remaining, all);
+    }
+
+    #[test]
+    fn it_returns_the_migration_error_when_the_lock_cannot_be_released() {
+        let db = MemoryStore::new();
+        let result: Result<(), ApiError> = with_lock(&db, |_| {
+            db.fail_writes_to(LOCKS);
+            Err(ApiError::BadRequest("migration failed".into()))
+        });
+        match result {
+            Err(ApiError::BadRequest(message)) => assert_eq!(message, "migration failed"),
+            other => panic!("Expected the migration error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_does_not_migrate_while_another_instance_holds_the_lock() {
+        let db = MemoryStore::new();
+        let other = Lease::new(&db, LOCKS, LOCK_ID, "a");
+        other.acquire(doc! {}, doc! {}).unwrap();
+
+        assert!(matches!(with_lock(&db, |_| Ok(1)), Err(ApiError::Conflict(_))));
+        other.release().unwrap();
+        assert_eq!(with_lock(&db, |_| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn it_stops_migrating_once_the_lock_is_lost() {
+        let lost = AtomicBool::new(false);
+        assert!(check_lock(&lost, 1).is_ok());
+        lost.store(true, Ordering::SeqCst);
+        assert!(matches!(check_lock(&lost, 2), Err(ApiError::Conflict(_))));
+    }
+}
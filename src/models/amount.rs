@@ -0,0 +1,162 @@
+//! Exact coin amounts.
+//!
+//! Amounts are stored as 64-bit integers counting the smallest unit of their
+//! ticker (satoshis for BTC), so sums and balance checks never round.
+//! The API reads and writes them as decimal strings.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+use crate::errors::ApiError;
+
+/// Decimal places of tickers missing from `PRECISIONS`
+pub const DEFAULT_PRECISION: u32 = 8;
+
+/// Decimal places kept for each ticker.
+/// ETH is kept in gwei: wei would overflow 64 bits above 9.2 ETH.
+const PRECISIONS: [(&str, u32); 4] = [("BTC", 8), ("ETH", 9), ("USDT", 6), ("USDC", 6)];
+
+/// Decimal places kept for `ticker`
+pub fn precision(ticker: &str) -> u32 {
+    PRECISIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(ticker))
+        .map_or(DEFAULT_PRECISION, |(_, precision)| *precision)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount {
+    units: i64,
+    precision: u32,
+}
+
+impl Amount {
+    pub fn from_units(units: i64, precision: u32) -> Self {
+        Amount { units, precision }
+    }
+
+    /// Parse a non-negative decimal string such as "1.5", refusing more
+    /// decimal places than `precision`
+    pub fn parse(value: &str, precision: u32) -> Result<Self, ApiError> {
+        let invalid = |reason: &str| ApiError::ValidationError(vec![format!("amount {:?} {}", value, reason)]);
+
+        let (whole, fraction) = match value.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (value, ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) || value.ends_with('.') {
+            return Err(invalid("must be a non-negative decimal number"));
+        }
+        if fraction.len() > precision as usize {
+            return Err(invalid(&format!("has more than {} decimal places", precision)));
+        }
+
+        let scale = 10i64.pow(precision);
+        let fraction = format!("{:0<width$}", fraction, width = precision as usize);
+        let units = whole
+            .parse::<i64>()
+            .ok()
+            .and_then(|whole| whole.checked_mul(scale))
+            .and_then(|units| units.checked_add(fraction.parse::<i64>().unwrap_or(0)))
+            .ok_or_else(|| invalid("is too large"))?;
+        Ok(Amount { units, precision })
+    }
+
+    /// Count of the smallest unit, as stored
+    pub fn units(&self) -> i64 {
+        self.units
+    }
+
+    /// Decimal places of the amount
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    pub fn checked_add(&self, other: Amount) -> Option<Amount> {
+        self.units
+            .checked_add(other.units)
+            .map(|units| Amount::from_units(units, self.precision))
+    }
+
+    pub fn checked_sub(&self, other: Amount) -> Option<Amount> {
+        self.units
+            .checked_sub(other.units)
+            .map(|units| Amount::from_units(units, self.precision))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.units < 0 { "-" } else { "" };
+        let units = self.units.unsigned_abs();
+        if self.precision == 0 {
+            return write!(f, "{}{}", sign, units);
+        }
+        let scale = 10u64.pow(self.precision);
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / scale,
+            units % scale,
+            width = self.precision as usize
+        )
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_decimal_strings() {
+        assert_eq!(Amount::parse("1.5", 8).unwrap().units(), 150_000_000);
+        assert_eq!(Amount::parse("20", 9).unwrap().units(), 20_000_000_000);
+        assert_eq!(Amount::parse("0.00000001", 8).unwrap().units(), 1);
+        assert_eq!(Amount::parse("0", 8).unwrap().units(), 0);
+    }
+
+    #[test]
+    fn it_rejects_invalid_amounts() {
+        for value in &["", "-1", "1.", ".5", "1,5", "abc", "1e3", " 1"] {
+            assert!(Amount::parse(value, 8).is_err(), "{:?} should be rejected", value);
+        }
+        assert!(Amount::parse("0.000000001", 8).is_err());
+        assert!(Amount::parse("100000000000", 8).is_err());
+    }
+
+    #[test]
+    fn it_formats_with_the_ticker_precision() {
+        assert_eq!(Amount::from_units(150_000_000, 8).to_string(), "1.50000000");
+        assert_eq!(Amount::from_units(-5, 2).to_string(), "-0.05");
+        assert_eq!(Amount::from_units(7, 0).to_string(), "7");
+        assert_eq!(serde_json::to_string(&Amount::from_units(1, 6)).unwrap(), "\"0.000001\"");
+    }
+
+    #[test]
+    fn it_adds_and_subtracts_exactly() {
+        let a = Amount::parse("0.1", 8).unwrap();
+        let b = Amount::parse("0.2", 8).unwrap();
+        assert_eq!(a.checked_add(b).unwrap(), Amount::parse("0.3", 8).unwrap());
+        assert_eq!(b.checked_sub(a).unwrap(), a);
+        assert!(Amount::from_units(i64::MAX, 8).checked_add(a).is_none());
+    }
+
+    #[test]
+    fn it_knows_ticker_precisions() {
+        assert_eq!(precision("BTC"), 8);
+        assert_eq!(precision("eth"), 9);
+        assert_eq!(precision("DOGE"), DEFAULT_PRECISION);
+    }
+}
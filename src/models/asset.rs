@@ -0,0 +1,285 @@
+//! Catalog of the assets holdings may be opened in.
+//!
+//! Every coin write names its asset by ticker and is refused unless the asset
+//! is in the catalog and enabled. The catalog also fixes the name stored with
+//! holdings and the decimal places their amounts are kept in.
+
+use chrono::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::errors::ApiError;
+use crate::models::amount::precision;
+use crate::store::{FindOptions, Store};
+
+const ASSETS: &str = "assets";
+
+/// Most decimal places of an asset; more would leave 64-bit amounts too small
+pub const MAX_DECIMALS: u32 = 12;
+
+/// Assets registered by `register_defaults`, with the decimals of `amount::precision`
+const DEFAULT_ASSETS: [(&str, &str); 4] = [
+    ("BTC", "Bitcoin"),
+    ("ETH", "Ethereum"),
+    ("USDT", "Tether"),
+    ("USDC", "USD Coin"),
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Asset {
+    pub ticker: String,
+    pub name: String,
+    pub decimals: u32,
+    pub enabled: bool,
+}
+
+/// Changes to an asset, `None` leaving a field as it is
+#[derive(Clone, Debug, Default)]
+pub struct AssetChanges {
+    pub name: Option<String>,
+    pub decimals: Option<u32>,
+    pub enabled: Option<bool>,
+}
+
+/// Uppercase a ticker, refusing anything but 1 to 10 letters and digits
+pub fn normalize_ticker(ticker: &str) -> Result<String, ApiError> {
+    if ticker.is_empty() || ticker.len() > 10 || !ticker.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ApiError::ValidationError(vec![format!(
+            "ticker {:?} must be 1 to 10 letters or digits",
+            ticker
+        )]));
+    }
+    Ok(ticker.to_uppercase())
+}
+
+/// Every asset of the catalog, by ticker
+pub fn get_assets(db: &dyn Store) -> Result<Vec<Asset>, ApiError> {
+    let options = FindOptions {
+        sort: Some(doc! { "ticker": 1 }),
+        ..FindOptions::default()
+    };
+    db.find(ASSETS, doc! {}, options)?.iter().map(doc_to_model).collect()
+}
+
+pub fn find_asset(db: &dyn Store, ticker: &str) -> Result<Asset, ApiError> {
+    db.find_one(ASSETS, doc! { "ticker": ticker.to_uppercase() })?
+        .map(|asset| doc_to_model(&asset))
+        .unwrap_or_else(|| Err(ApiError::NotFound(format!("No asset {}", ticker))))
+}
+
+/// The asset a coin write names, refusing unknown and disabled ones
+pub fn enabled_asset(db: &dyn Store, ticker: &str) -> Result<Asset, ApiError> {
+    let unsupported = || ApiError::ValidationError(vec![format!("{} is not a supported asset", ticker)]);
+    match find_asset(db, ticker) {
+        Ok(asset) if asset.enabled => Ok(asset),
+        Ok(_) | Err(ApiError::NotFound(_)) => Err(unsupported()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decimal places amounts of `ticker` are kept in.
+/// Tickers missing from the catalog keep the built-in precision they were stored with.
+pub fn decimals(db: &dyn Store, ticker: &str) -> Result<u32, ApiError> {
+    match find_asset(db, ticker) {
+        Ok(asset) => Ok(asset.decimals),
+        Err(ApiError::NotFound(_)) => Ok(precision(ticker)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decimal places of every cataloged asset, read at once to convert the
+/// amounts of many holdings without a catalog lookup each
+pub struct Decimals(HashMap<String, u32>);
+
+impl Decimals {
+    pub fn load(db: &dyn Store) -> Result<Self, ApiError> {
+        let assets = get_assets(db)?;
+        Ok(Decimals(assets.into_iter().map(|asset| (asset.ticker, asset.decimals)).collect()))
+    }
+
+    /// Like `decimals`, from the catalog as it was loaded
+    pub fn of(&self, ticker: &str) -> u32 {
+        self.0.get(&ticker.to_uppercase()).copied().unwrap_or_else(|| precision(ticker))
+    }
+}
+
+pub fn create_asset(db: &dyn Store, asset: &Asset) -> Result<Asset, ApiError> {
+    let ticker = normalize_ticker(&asset.ticker)?;
+    validate_asset(&asset.name, asset.decimals)?;
+    let document = doc! {
+        "ticker": &ticker,
+        "name": &asset.name,
+        "decimals": asset.decimals as i32,
+        "enabled": asset.enabled,
+        "created_at": Utc::now(),
+        "updated_at": Utc::now(),
+    };
+    match db.insert_one(ASSETS, document) {
+        Err(ApiError::Conflict(_)) => Err(ApiError::Conflict(format!("Asset {} already exists", ticker))),
+        result => result.and_then(|_| find_asset(db, &ticker)),
+    }
+}
+
+/// Apply `changes` to an asset. Renaming also renames the holdings of the asset,
+/// decimals cannot change once the asset is held.
+pub fn update_asset(db: &dyn Store, ticker: &str, changes: AssetChanges) -> Result<Asset, ApiError> {
+    let asset = find_asset(db, ticker)?;
+    let name = changes.name.unwrap_or_else(|| asset.name.clone());
+    let decimals = changes.decimals.unwrap_or(asset.decimals);
+    validate_asset(&name, decimals)?;
+    if decimals != asset.decimals && is_held(db, &asset.ticker)? {
+        return Err(ApiError::Conflict(format!(
+            "{} is held, its decimals cannot change",
+            asset.ticker
+        )));
+    }
+
+    let update = doc! { "$set": {
+        "name": &name,
+        "decimals": decimals as i32,
+        "enabled": changes.enabled.unwrap_or(asset.enabled),
+        "updated_at": Utc::now(),
+    } };
+    db.update_one(ASSETS, doc! { "ticker": &asset.ticker }, update)?;
+    if name != asset.name {
+        db.update_many("coins", doc! { "ticker": &asset.ticker }, doc! { "$set": { "coin": &name } })?;
+    }
+    find_asset(db, &asset.ticker)
+}
+
+/// Remove an asset nobody holds; held assets can only be disabled
+pub fn delete_asset(db: &dyn Store, ticker: &str) -> Result<(), ApiError> {
+    let asset = find_asset(db, ticker)?;
+    if is_held(db, &asset.ticker)? {
+        return Err(ApiError::Conflict(format!("{} is held, disable it instead", asset.ticker)));
+    }
+    db.delete_one(ASSETS, doc! { "ticker": &asset.ticker })?;
+    Ok(())
+}
+
+/// Add the built-in assets missing from the catalog, returning how many were added
+pub fn register_defaults(db: &dyn Store) -> Result<usize, ApiError> {
+    let mut added = 0;
+    for (ticker, name) in DEFAULT_ASSETS.iter() {
+        if db.find_one(ASSETS, doc! { "ticker": *ticker })?.is_some() {
+            continue;
+        }
+        let asset = Asset {
+            ticker: ticker.to_string(),
+            name: name.to_string(),
+            decimals: precision(ticker),
+            enabled: true,
+        };
+        create_asset(db, &asset)?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+fn validate_asset(name: &str, decimals: u32) -> Result<(), ApiError> {
+    let mut errors = Vec::new();
+    if name.trim().is_empty() {
+        errors.push("name is required".to_string());
+    }
+    if decimals > MAX_DECIMALS {
+        errors.push(format!("decimals must be at most {}", MAX_DECIMALS));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::ValidationError(errors))
+    }
+}
+
+fn is_held(db: &dyn Store, ticker: &str) -> Result<bool, ApiError> {
+    Ok(db.find_one("coins", doc! { "ticker": ticker })?.is_some())
+}
+
+fn doc_to_model(doc: &bson::Document) -> Result<Asset, ApiError> {
+    let invalid = |field: &str| ApiError::InternalServerError(format!("Invalid {} in an asset", field));
+    Ok(Asset {
+        ticker: doc.get_str("ticker").map_err(|_| invalid("ticker"))?.into(),
+        name: doc.get_str("name").map_err(|_| invalid("name"))?.into(),
+        decimals: doc.get_i32("decimals").map_err(|_| invalid("decimals"))? as u32,
+        enabled: doc.get_bool("enabled").map_err(|_| invalid("enabled"))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    fn asset(ticker: &str, decimals: u32) -> Asset {
+        Asset {
+            ticker: ticker.into(),
+            name: format!("{} coin", ticker),
+            decimals,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn it_registers_the_default_assets_once() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        assert_eq!(register_defaults(db).unwrap(), 0);
+        db.delete_one(ASSETS, doc! { "ticker": "USDC" }).unwrap();
+        assert_eq!(register_defaults(db).unwrap(), 1);
+        assert_eq!(find_asset(db, "eth").unwrap().decimals, 9);
+    }
+
+    #[test]
+    fn it_loads_the_decimals_of_the_catalog() {
+        let ctx = test_context();
+        create_asset(&*ctx.db, &asset("SOL", 9)).unwrap();
+        let decimals = Decimals::load(&*ctx.db).unwrap();
+        assert_eq!(decimals.of("SOL"), 9);
+        assert_eq!(decimals.of("usdc"), 6);
+        assert_eq!(decimals.of("DOGE"), precision("DOGE"));
+    }
+
+    #[test]
+    fn it_refuses_duplicate_and_invalid_assets() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        assert_eq!(create_asset(db, &asset("sol", 9)).unwrap().ticker, "SOL");
+        assert!(matches!(create_asset(db, &asset("SOL", 9)), Err(ApiError::Conflict(_))));
+        assert!(matches!(create_asset(db, &asset("S-L", 9)), Err(ApiError::ValidationError(_))));
+        assert!(matches!(create_asset(db, &asset("ADA", 19)), Err(ApiError::ValidationError(_))));
+    }
+
+    #[test]
+    fn it_only_accepts_writes_to_enabled_assets() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        let disabled = AssetChanges { enabled: Some(false), ..AssetChanges::default() };
+        update_asset(db, "BTC", disabled).unwrap();
+
+        assert!(matches!(enabled_asset(db, "BTC"), Err(ApiError::ValidationError(_))));
+        assert!(matches!(enabled_asset(db, "DOGE"), Err(ApiError::ValidationError(_))));
+        assert_eq!(enabled_asset(db, "eth").unwrap().name, "Ethereum");
+        assert_eq!(decimals(db, "BTC").unwrap(), 8);
+        assert_eq!(decimals(db, "DOGE").unwrap(), 8);
+    }
+
+    #[test]
+    fn it_protects_held_assets() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).coin("bitcoin", "BTC").create();
+        let db = &*fixtures.ctx().db;
+
+        let renamed = AssetChanges { name: Some("Bitcoin Core".into()), ..AssetChanges::default() };
+        update_asset(db, "BTC", renamed).unwrap();
+        let holding = db.find_one("coins", doc! { "uid": &user.uid }).unwrap().unwrap();
+        assert_eq!(holding.get_str("coin").unwrap(), "Bitcoin Core");
+
+        let rescaled = AssetChanges { decimals: Some(2), ..AssetChanges::default() };
+        assert!(matches!(update_asset(db, "BTC", rescaled), Err(ApiError::Conflict(_))));
+        assert!(matches!(delete_asset(db, "BTC"), Err(ApiError::Conflict(_))));
+        delete_asset(db, "USDT").unwrap();
+        assert!(matches!(find_asset(db, "USDT"), Err(ApiError::NotFound(_))));
+    }
+}
@@ -1,87 +1,267 @@
 use serde::Serialize;
 use chrono::prelude::*;
 
-use crate::database;
 use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::models::asset::Decimals;
+use crate::models::transaction::{record, Entry, Kind};
+use crate::store::{FindOptions, Store};
 
 #[derive(Clone, Debug)]
 pub struct Coin {
     pub uid: String,
     pub coin: String,
     pub ticker: String,
-    pub amount: String,
+    /// In the smallest unit of the ticker, see `models::amount`
+    pub amount: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CoinResponse {
     pub uid: String,
     pub coin: String,
     pub ticker: String,
-    pub amount: String,
+    pub amount: Amount,
 }
 
-/// Helper function : doc --> model
-fn doc_to_model(doc: &bson::Document) -> CoinResponse {
+/// Helper function : doc --> model, amounts having `precision` decimals
+fn doc_to_model(doc: &bson::Document, precision: u32) -> CoinResponse {
     CoinResponse {
         uid: String::from(doc.get_str("uid").unwrap()),
         coin: String::from(doc.get_str("coin").unwrap()),
         ticker: String::from(doc.get_str("ticker").unwrap()),
-        amount: String::from(doc.get_str("amount").unwrap()),
+        amount: Amount::from_units(doc.get_i64("amount").unwrap(), precision),
         //created_at: doc.get_utc_datetime("created_at").unwrap().clone(),
         //updated_at: doc.get_utc_datetime("updated_at").unwrap().clone(),
     }
 }
 
 /// Get Coin with query
-pub fn get_data(query : bson::Document ) -> Result<Vec<Option<CoinResponse>>, ApiError> {
-    let coll = database::collection("coins");
-    let cursor = coll.find(query, None).unwrap();
-
-    let result = cursor.into_iter()
-        .map(|document| {
-            match document {
-                Ok(doc) => Ok(Some(doc_to_model(&doc))),
-                Err(e) => return Err(e).map_err(ApiError::DBError)
-            }
-        })
-        .collect::<Result<Vec<Option<CoinResponse>>, ApiError>>();
-
-    result.into()
+pub fn get_data(db: &dyn Store, query : bson::Document ) -> Result<Vec<Option<CoinResponse>>, ApiError> {
+    let documents = db.find("coins", query, FindOptions::default())?;
+    let decimals = Decimals::load(db)?;
+    Ok(documents
+        .iter()
+        .map(|doc| Some(doc_to_model(doc, decimals.of(doc.get_str("ticker").unwrap_or_default()))))
+        .collect())
+}
+
+/// Open a holding of `ticker` for `uid`, refusing a second one
+pub fn create_holding(db: &dyn Store, uid: &str, coin: &str, ticker: &str, amount: Amount, actor: &str) -> Result<CoinResponse, ApiError> {
+    let holding = doc! {
+        "uid": uid,
+        "coin": coin,
+        "ticker": ticker,
+        "amount": amount.units(),
+        "created_at": Utc::now(),
+        "updated_at": Utc::now(),
+    };
+    // The unique index on uid and ticker refuses a second holding, even from a concurrent request
+    let id = match db.insert_one("coins", holding.clone()) {
+        Err(ApiError::Conflict(_)) => return Err(ApiError::Conflict(format!("{} already holds {}", uid, ticker))),
+        result => result?,
+    };
+    let zero = Amount::from_units(0, amount.precision());
+    let cause = Cause { kind: Kind::Open, actor, reference: None };
+    if let Err(e) = record(db, entry(uid, ticker, amount, zero, amount, cause), Utc::now()) {
+        // A holding missing from the ledger would show as drift, take it back
+        if let Err(undo) = db.delete_one("coins", doc! { "_id": id }) {
+            error!("Could not remove the unrecorded {} holding of {}: {}", ticker, uid, undo);
+        }
+        return Err(e);
+    }
+    Ok(doc_to_model(&holding, amount.precision()))
+}
+
+/// What caused a balance change, as recorded in the ledger
+#[derive(Clone, Copy, Debug)]
+pub struct Cause<'a> {
+    pub kind: Kind,
+    pub actor: &'a str,
+    /// Transfer the change is part of
+    pub reference: Option<&'a str>,
+}
+
+/// Add `amount` to a holding, returning the new balance
+pub fn deposit(db: &dyn Store, uid: &str, ticker: &str, amount: Amount, actor: &str) -> Result<Amount, ApiError> {
+    credit(db, uid, ticker, amount, Cause { kind: Kind::Deposit, actor, reference: None })
+}
+
+/// Take `amount` from a holding, refusing to go below zero.
+/// Returns the new balance.
+pub fn withdraw(db: &dyn Store, uid: &str, ticker: &str, amount: Amount, actor: &str) -> Result<Amount, ApiError> {
+    debit(db, uid, ticker, amount, Cause { kind: Kind::Withdraw, actor, reference: None })
+}
+
+/// Add `amount` to a holding and record why, returning the new balance
+pub fn credit(db: &dyn Store, uid: &str, ticker: &str, amount: Amount, cause: Cause) -> Result<Amount, ApiError> {
+    // The bound in the filter keeps the increment from overflowing
+    let filter = doc! { "uid": uid, "ticker": ticker, "amount": { "$lte": i64::MAX - amount.units() } };
+    let update = doc! { "$inc": { "amount": amount.units() }, "$set": { "updated_at": Utc::now() } };
+    let before = match db.find_one_and_update("coins", filter, update)? {
+        Some(before) => balance(&before, ticker, amount)?,
+        None => return Err(missing_or(db, uid, ticker, "Balance would exceed the largest storable amount")?),
+    };
+    let after = before
+        .checked_add(amount)
+        .ok_or_else(|| ApiError::InternalServerError("Balance overflowed".into()))?;
+    if let Err(e) = record(db, entry(uid, ticker, amount, before, after, cause), Utc::now()) {
+        let filter = doc! { "uid": uid, "ticker": ticker, "amount": { "$gte": amount.units() } };
+        return Err(compensate(db, filter, -amount.units(), e));
+    }
+    Ok(after)
+}
+
+/// Take `amount` from a holding, refusing to go below zero, and record why.
+/// Returns the new balance.
+pub fn debit(db: &dyn Store, uid: &str, ticker: &str, amount: Amount, cause: Cause) -> Result<Amount, ApiError> {
+    // Matching only balances that cover the amount makes the check and the decrement atomic
+    let filter = doc! { "uid": uid, "ticker": ticker, "amount": { "$gte": amount.units() } };
+    let update = doc! { "$inc": { "amount": -amount.units() }, "$set": { "updated_at": Utc::now() } };
+    let before = match db.find_one_and_update("coins", filter, update)? {
+        Some(before) => balance(&before, ticker, amount)?,
+        None => return Err(missing_or(db, uid, ticker, "Insufficient balance")?),
+    };
+    let after = before
+        .checked_sub(amount)
+        .ok_or_else(|| ApiError::InternalServerError("Balance overflowed".into()))?;
+    if let Err(e) = record(db, entry(uid, ticker, amount, before, after, cause), Utc::now()) {
+        let filter = doc! { "uid": uid, "ticker": ticker, "amount": { "$lte": i64::MAX - amount.units() } };
+        return Err(compensate(db, filter, amount.units(), e));
+    }
+    Ok(after)
+}
+
+/// Undo a balance change the ledger could not record, so a failed call changes
+/// nothing and can be retried. Returns the error of the ledger write.
+fn compensate(db: &dyn Store, filter: bson::Document, units: i64, error: ApiError) -> ApiError {
+    let update = doc! { "$inc": { "amount": units }, "$set": { "updated_at": Utc::now() } };
+    match db.update_one("coins", filter.clone(), update) {
+        Ok(result) if result.matched_count == 1 => {}
+        Ok(_) => error!("Could not undo an unrecorded balance change of {}: the balance moved", filter),
+        Err(undo) => error!("Could not undo an unrecorded balance change of {}: {}", filter, undo),
+    }
+    error
+}
+
+fn entry<'a>(uid: &'a str, ticker: &'a str, amount: Amount, before: Amount, after: Amount, cause: Cause<'a>) -> Entry<'a> {
+    Entry {
+        uid,
+        ticker,
+        kind: cause.kind,
+        amount,
+        before,
+        after,
+        actor: cause.actor,
+        reference: cause.reference,
+    }
+}
+
+/// Balance of a holding, in the precision of `amount`
+fn balance(holding: &bson::Document, ticker: &str, amount: Amount) -> Result<Amount, ApiError> {
+    let units = holding
+        .get_i64("amount")
+        .map_err(|e| ApiError::InternalServerError(format!("Invalid {} balance: {}", ticker, e)))?;
+    Ok(Amount::from_units(units, amount.precision()))
+}
+
+/// Explain why a conditional balance update matched nothing
+fn missing_or(db: &dyn Store, uid: &str, ticker: &str, reason: &str) -> Result<ApiError, ApiError> {
+    match db.find_one("coins", doc! { "uid": uid, "ticker": ticker })? {
+        Some(_) => Ok(ApiError::BadRequest(reason.into())),
+        None => Ok(ApiError::NotFound(format!("{} holds no {}", uid, ticker))),
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::database;
+    use crate::models::asset::decimals;
+    use crate::store::MemoryStore;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
 
     #[test]
     pub fn test_get_data() {
-       let query = doc! { "uid" => "bsjung"};
-       let results = get_data(query);
-       /*
-       for item in results {
-         println! ("{:#?}", item);
-       }
-       */
+       let fixtures = Fixtures::new(test_context());
+       let user = fixtures.user().create();
+       fixtures.coin(&user.uid).create();
+       fixtures.coin(&user.uid).coin("Ethereum", "ETH").amount("20").create();
+       let query = doc! { "uid" => &user.uid };
+       let results = get_data(&*fixtures.ctx().db, query).unwrap();
+       assert_eq!(results.len(), 2);
     }
 
     #[test]
-    #[ignore]
     pub fn test_create_data() {
       println!("Creating Coin");
       let data = doc! {
             "uid" => "bsjung",
             "coin" => "Bitcoin",
             "ticker" => "BTC",
-            "amount" => "100",
+            "amount" => 100_i64,
             "created_at" => Utc::now(),
             "updated_at" => Utc::now(),
            };
-      let result = database::create("coins", data);
+      let result = database::create(&*test_context().db, "coins", data);
     
-      if result.is_err()  {
-          println!("Could not insert  new coin!")
-      }
+      assert!(result.is_ok(), "Could not insert a new coin");
+    }
+
+    #[test]
+    fn it_deposits_and_withdraws() {
+       let fixtures = Fixtures::new(test_context());
+       let user = fixtures.user().create();
+       fixtures.coin(&user.uid).amount("1.5").create();
+       let db = &*fixtures.ctx().db;
+       let amount = |value| Amount::parse(value, decimals(db, "BTC").unwrap()).unwrap();
+
+       assert_eq!(deposit(db, &user.uid, "BTC", amount("0.25"), &user.uid).unwrap(), amount("1.75"));
+       assert_eq!(withdraw(db, &user.uid, "BTC", amount("1.75"), &user.uid).unwrap(), amount("0"));
+       let stored = db.find_one("coins", doc! { "uid": &user.uid }).unwrap().unwrap();
+       assert_eq!(stored.get_i64("amount").unwrap(), 0);
+    }
+
+    #[test]
+    fn it_refuses_to_go_negative() {
+       let fixtures = Fixtures::new(test_context());
+       let user = fixtures.user().create();
+       fixtures.coin(&user.uid).amount("1").create();
+       let db = &*fixtures.ctx().db;
+
+       let result = withdraw(db, &user.uid, "BTC", Amount::parse("1.00000001", 8).unwrap(), &user.uid);
+       assert!(matches!(result, Err(ApiError::BadRequest(_))));
+       let result = withdraw(db, &user.uid, "ETH", Amount::parse("1", 9).unwrap(), &user.uid);
+       assert!(matches!(result, Err(ApiError::NotFound(_))));
+       let stored = db.find_one("coins", doc! { "uid": &user.uid }).unwrap().unwrap();
+       assert_eq!(stored.get_i64("amount").unwrap(), 100_000_000);
+    }
+
+    #[test]
+    fn it_leaves_balances_alone_when_the_ledger_fails() {
+       let db = MemoryStore::new();
+       database::ensure_indexes(&db).unwrap();
+       let btc = |value| Amount::parse(value, 8).unwrap();
+       create_holding(&db, "bsjung", "Bitcoin", "BTC", btc("1"), "bsjung").unwrap();
+       db.fail_writes_to("coin_transactions");
+
+       assert!(deposit(&db, "bsjung", "BTC", btc("0.5"), "bsjung").is_err());
+       assert!(withdraw(&db, "bsjung", "BTC", btc("0.5"), "bsjung").is_err());
+       assert!(create_holding(&db, "bsjung", "Ethereum", "ETH", Amount::parse("1", 9).unwrap(), "bsjung").is_err());
+       let stored = db.find_one("coins", doc! { "uid": "bsjung", "ticker": "BTC" }).unwrap().unwrap();
+       assert_eq!(stored.get_i64("amount").unwrap(), 100_000_000);
+       assert!(db.find_one("coins", doc! { "ticker": "ETH" }).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_refuses_a_second_holding_of_a_ticker() {
+       let fixtures = Fixtures::new(test_context());
+       let user = fixtures.user().create();
+       fixtures.coin(&user.uid).create();
+       let result = create_holding(&*fixtures.ctx().db, &user.uid, "Bitcoin", "BTC", Amount::from_units(1, 8), &user.uid);
+       assert!(matches!(result, Err(ApiError::Conflict(_))));
     }
 }
@@ -1,2 +1,9 @@
 pub mod user;
+pub mod amount;
+pub mod asset;
 pub mod coin;
+pub mod transaction;
+pub mod transfer;
+pub mod portfolio;
+pub mod session;
+pub mod snapshot;
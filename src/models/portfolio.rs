@@ -0,0 +1,112 @@
+//! Value of a user's holdings in a fiat currency.
+
+use serde::Serialize;
+
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::models::coin::get_data;
+use crate::prices::{currency_precision, value};
+use crate::store::Store;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HoldingValue {
+    pub coin: String,
+    pub ticker: String,
+    pub amount: Amount,
+    /// None when no quote is available
+    pub price: Option<Amount>,
+    pub value: Option<Amount>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Portfolio {
+    pub currency: String,
+    pub holdings: Vec<HoldingValue>,
+    /// Sum of the holdings with a quote
+    pub total: Amount,
+    /// Tickers left out of `total` for want of a quote
+    pub unpriced: Vec<String>,
+}
+
+/// Value every holding of `uid` in `currency`, pricing with `quote(ticker)`.
+/// Holdings without a quote are reported as unpriced, other quote errors fail.
+pub fn valuate<F>(db: &dyn Store, uid: &str, currency: &str, quote: F) -> Result<Portfolio, ApiError>
+where
+    F: Fn(&str) -> Result<Amount, ApiError>,
+{
+    let mut holdings = Vec::new();
+    let mut unpriced = Vec::new();
+    let mut total = Amount::from_units(0, currency_precision(currency));
+    for coin in get_data(db, doc! { "uid": uid })?.into_iter().flatten() {
+        let price = match quote(&coin.ticker) {
+            Ok(price) => Some(price),
+            Err(ApiError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let holding_value = match price {
+            Some(price) => {
+                let holding_value = value(coin.amount, price, currency)?;
+                total = total
+                    .checked_add(holding_value)
+                    .ok_or_else(|| ApiError::InternalServerError("Portfolio value is too large".into()))?;
+                Some(holding_value)
+            }
+            None => {
+                unpriced.push(coin.ticker.clone());
+                None
+            }
+        };
+        holdings.push(HoldingValue {
+            coin: coin.coin,
+            ticker: coin.ticker,
+            amount: coin.amount,
+            price,
+            value: holding_value,
+        });
+    }
+    holdings.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+    Ok(Portfolio {
+        currency: currency.into(),
+        holdings,
+        total,
+        unpriced,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prices::{PriceSource, StaticPrices};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    #[test]
+    fn it_values_priced_holdings_and_lists_the_rest() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0.5").create();
+        fixtures.coin(&user.uid).coin("Ethereum", "ETH").amount("2").create();
+        fixtures.coin(&user.uid).coin("Dogecoin", "DOGE").amount("100").create();
+        let prices = StaticPrices::new(&[("BTC", "USD", "64000.01"), ("ETH", "USD", "3100.5")]).unwrap();
+
+        let portfolio = valuate(&*fixtures.ctx().db, &user.uid, "USD", |ticker| prices.quote(ticker, "USD")).unwrap();
+        let tickers: Vec<&str> = portfolio.holdings.iter().map(|holding| holding.ticker.as_str()).collect();
+        assert_eq!(tickers, ["BTC", "DOGE", "ETH"]);
+        assert_eq!(portfolio.holdings[0].value.unwrap().to_string(), "32000.01");
+        assert_eq!(portfolio.holdings[1].price, None);
+        assert_eq!(portfolio.holdings[2].value.unwrap().to_string(), "6201.00");
+        assert_eq!(portfolio.total.to_string(), "38201.01");
+        assert_eq!(portfolio.unpriced, ["DOGE"]);
+    }
+
+    #[test]
+    fn it_fails_when_the_price_source_does() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).create();
+        let down = |_: &str| Err(ApiError::PriceSourceError("down".into()));
+
+        let result = valuate(&*fixtures.ctx().db, &user.uid, "USD", down);
+        assert!(matches!(result, Err(ApiError::PriceSourceError(_))));
+    }
+}
@@ -0,0 +1,127 @@
+//! Login sessions.
+//!
+//! Each login records a session in `sessions` and names it in the JWT, so a
+//! token stops working once its session ends by logging out, while the token
+//! itself cannot be recalled. Sessions expire with their token, removed by a
+//! TTL index on `expires_at`.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::errors::ApiError;
+use crate::models::user::password_changed_at;
+use crate::store::{FindOptions, Store};
+
+pub const SESSIONS: &str = "sessions";
+
+/// Record a session of User `uid` lasting until `expires_at`, returning its id
+pub fn create_session(db: &dyn Store, uid: &str, expires_at: DateTime<Utc>) -> Result<String, ApiError> {
+    let id = format!("{:032x}", rand::random::<u128>());
+    let session = doc! {
+        "_id": &id,
+        "uid": uid,
+        "created_at": Utc::now(),
+        "expires_at": expires_at,
+    };
+    db.insert_one(SESSIONS, session)?;
+    Ok(id)
+}
+
+/// Whether session `id` exists and has not expired
+pub fn is_session_active(db: &dyn Store, id: &str) -> Result<bool, ApiError> {
+    let session = db.find_one(SESSIONS, doc! { "_id": id, "expires_at": { "$gt": Utc::now() } })?;
+    Ok(session.is_some())
+}
+
+/// End session `id`, its token is refused from now on
+pub fn end_session(db: &dyn Store, id: &str) -> Result<(), ApiError> {
+    db.delete_one(SESSIONS, doc! { "_id": id })?;
+    Ok(())
+}
+
+/// Delete the sessions expired at `now`, ahead of the TTL index
+pub fn delete_expired_sessions(db: &dyn Store, now: DateTime<Utc>) -> Result<i64, ApiError> {
+    db.delete_many(SESSIONS, doc! { "expires_at": { "$lte": now } })
+}
+
+/// Delete the sessions of deleted users and those started before a password
+/// change, which `is_session_revoked` refuses but would keep until they expire
+pub fn purge_stale_sessions(db: &dyn Store) -> Result<i64, ApiError> {
+    let sessions = db.find(SESSIONS, doc! {}, FindOptions::default())?;
+    // Per user, the time in milliseconds before which its sessions are stale
+    let mut cutoffs: HashMap<String, i64> = HashMap::new();
+    let mut purged = 0;
+    for session in &sessions {
+        let (id, uid) = match (session.get_str("_id"), session.get_str("uid")) {
+            (Ok(id), Ok(uid)) => (id, uid),
+            _ => continue,
+        };
+        let cutoff = match cutoffs.get(uid) {
+            Some(cutoff) => *cutoff,
+            None => {
+                let cutoff = match password_changed_at(db, uid) {
+                    Ok(changed_at_ms) => changed_at_ms.unwrap_or(i64::MIN),
+                    Err(ApiError::NotFound(_)) => i64::MAX,
+                    Err(e) => return Err(e),
+                };
+                cutoffs.insert(uid.to_string(), cutoff);
+                cutoff
+            }
+        };
+        let created_at_ms = session.get_utc_datetime("created_at").map_or(i64::MIN, |created| created.timestamp_millis());
+        if created_at_ms < cutoff {
+            purged += db.delete_one(SESSIONS, doc! { "_id": id })?;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use chrono::Duration;
+
+    #[test]
+    fn it_ends_sessions() {
+        let db = MemoryStore::new();
+        let id = create_session(&db, "bsjung", Utc::now() + Duration::hours(1)).unwrap();
+        assert!(is_session_active(&db, &id).unwrap());
+
+        end_session(&db, &id).unwrap();
+        assert!(!is_session_active(&db, &id).unwrap());
+    }
+
+    #[test]
+    fn it_purges_sessions_of_deleted_users_and_changed_passwords() {
+        let db = MemoryStore::new();
+        let later = Utc::now() + Duration::hours(1);
+        db.insert_one("users", doc! { "uid": "kept" }).unwrap();
+        db.insert_one("users", doc! { "uid": "changed", "password_changed_at_ms": later.timestamp_millis() }).unwrap();
+        let kept = create_session(&db, "kept", later).unwrap();
+        let changed = create_session(&db, "changed", later).unwrap();
+        let deleted = create_session(&db, "deleted", later).unwrap();
+
+        assert_eq!(purge_stale_sessions(&db).unwrap(), 2);
+        assert!(is_session_active(&db, &kept).unwrap());
+        assert!(!is_session_active(&db, &changed).unwrap());
+        assert!(!is_session_active(&db, &deleted).unwrap());
+    }
+
+    #[test]
+    fn it_deletes_expired_sessions() {
+        let db = MemoryStore::new();
+        create_session(&db, "bsjung", Utc::now() - Duration::seconds(1)).unwrap();
+        let active = create_session(&db, "bsjung", Utc::now() + Duration::hours(1)).unwrap();
+
+        assert_eq!(delete_expired_sessions(&db, Utc::now()).unwrap(), 1);
+        assert!(is_session_active(&db, &active).unwrap());
+    }
+
+    #[test]
+    fn it_treats_expired_sessions_as_ended() {
+        let db = MemoryStore::new();
+        let id = create_session(&db, "bsjung", Utc::now() - Duration::seconds(1)).unwrap();
+        assert!(!is_session_active(&db, &id).unwrap());
+    }
+}
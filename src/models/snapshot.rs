@@ -0,0 +1,286 @@
+//! Snapshots of portfolios over time.
+//!
+//! A scheduled job writes one document per user and tick to
+//! `portfolio_snapshots`, holding the balances and their value in the default
+//! currency. History is read back by bucketing the snapshots of a time range
+//! into hours, days or weeks and keeping the last snapshot of each bucket.
+
+use chrono::{DateTime, Datelike, Duration, SecondsFormat, TimeZone, Timelike, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::models::asset::Decimals;
+use crate::models::portfolio::{valuate, Portfolio};
+use crate::prices::currency_precision;
+use crate::store::{FindOptions, Store};
+
+const SNAPSHOTS: &str = "portfolio_snapshots";
+
+/// Longest range `find_history` reads
+pub const MAX_HISTORY_DAYS: i64 = 366;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    Hour,
+    Day,
+    Week,
+}
+
+impl Interval {
+    pub fn parse(interval: &str) -> Result<Self, ApiError> {
+        match interval {
+            "hour" => Ok(Interval::Hour),
+            "day" => Ok(Interval::Day),
+            "week" => Ok(Interval::Week),
+            _ => Err(ApiError::ValidationError(vec![format!(
+                "interval {:?} must be one of hour, day, week",
+                interval
+            )])),
+        }
+    }
+
+    /// Start of the bucket holding `at`; weeks start on Monday, all in UTC
+    pub fn bucket(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let hour = snapshot_time(at, 60);
+        match self {
+            Interval::Hour => hour,
+            Interval::Day => hour - Duration::hours(i64::from(at.hour())),
+            Interval::Week => {
+                let day = hour - Duration::hours(i64::from(at.hour()));
+                day - Duration::days(i64::from(at.weekday().num_days_from_monday()))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotHolding {
+    pub ticker: String,
+    pub amount: Amount,
+    /// None when no quote was available
+    pub value: Option<Amount>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HistoryPoint {
+    /// Start of the bucket
+    pub at: String,
+    /// When the snapshot standing for the bucket was taken
+    pub taken_at: String,
+    pub currency: String,
+    pub holdings: Vec<SnapshotHolding>,
+    pub total: Amount,
+}
+
+/// Snapshot the holdings of every user as of `taken_at`, valued in `currency`
+/// with `quote`. Balances are still recorded when the price source fails.
+/// Returns how many snapshots were written; users already snapshotted at
+/// `taken_at`, by this or another instance, are skipped.
+pub fn take_snapshots<F>(db: &dyn Store, currency: &str, quote: F, taken_at: DateTime<Utc>) -> Result<usize, ApiError>
+where
+    F: Fn(&str) -> Result<Amount, ApiError>,
+{
+    let holders: BTreeSet<String> = db
+        .find("coins", doc! {}, FindOptions::default())?
+        .iter()
+        .filter_map(|holding| holding.get_str("uid").ok().map(String::from))
+        .collect();
+
+    let mut written = 0;
+    for uid in holders {
+        let portfolio = match valuate(db, &uid, currency, &quote) {
+            Err(ApiError::PriceSourceError(e)) => {
+                warn!("Snapshot of {} without values: {}", uid, e);
+                valuate(db, &uid, currency, |ticker: &str| Err(ApiError::NotFound(ticker.into())))?
+            }
+            result => result?,
+        };
+        match db.insert_one(SNAPSHOTS, to_document(&uid, &portfolio, taken_at)) {
+            Ok(_) => written += 1,
+            Err(ApiError::Conflict(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(written)
+}
+
+/// Snapshots of `uid` taken in [`from`, `to`), one per `interval` bucket, oldest first
+pub fn find_history(
+    db: &dyn Store,
+    uid: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: Interval,
+) -> Result<Vec<HistoryPoint>, ApiError> {
+    if from >= to {
+        return Err(ApiError::ValidationError(vec!["from must be before to".into()]));
+    }
+    if to - from > Duration::days(MAX_HISTORY_DAYS) {
+        return Err(ApiError::ValidationError(vec![format!(
+            "from and to must be at most {} days apart",
+            MAX_HISTORY_DAYS
+        )]));
+    }
+
+    let filter = doc! { "uid": uid, "taken_at": { "$gte": from, "$lt": to } };
+    let options = FindOptions {
+        sort: Some(doc! { "taken_at": 1 }),
+        ..FindOptions::default()
+    };
+    let snapshots = db.find(SNAPSHOTS, filter, options)?;
+    let decimals = Decimals::load(db)?;
+    let mut points: Vec<HistoryPoint> = Vec::new();
+    let mut last_bucket = None;
+    for snapshot in snapshots {
+        let taken_at = *snapshot.get_utc_datetime("taken_at").map_err(|e| invalid("taken_at", e))?;
+        let bucket = interval.bucket(taken_at);
+        let point = doc_to_model(&decimals, &snapshot, bucket, taken_at)?;
+        // Snapshots are sorted, so a later one of the same bucket replaces the earlier
+        if last_bucket == Some(bucket) {
+            points.pop();
+        }
+        points.push(point);
+        last_bucket = Some(bucket);
+    }
+    Ok(points)
+}
+
+/// Start of the snapshot tick holding `now`, for ticks of `minutes` counted from the epoch
+pub fn snapshot_time(now: DateTime<Utc>, minutes: u64) -> DateTime<Utc> {
+    let seconds = (minutes.max(1) * 60) as i64;
+    Utc.timestamp(now.timestamp() - now.timestamp().rem_euclid(seconds), 0)
+}
+
+fn to_document(uid: &str, portfolio: &Portfolio, taken_at: DateTime<Utc>) -> bson::Document {
+    let holdings: Vec<bson::Bson> = portfolio
+        .holdings
+        .iter()
+        .map(|holding| {
+            let value = holding.value.map_or(bson::Bson::Null, |value| value.units().into());
+            bson::Bson::Document(doc! {
+                "ticker": &holding.ticker,
+                "amount": holding.amount.units(),
+                "value": value,
+            })
+        })
+        .collect();
+    doc! {
+        "uid": uid,
+        "taken_at": taken_at,
+        "currency": &portfolio.currency,
+        "holdings": holdings,
+        "total": portfolio.total.units(),
+    }
+}
+
+fn doc_to_model(
+    decimals: &Decimals,
+    doc: &bson::Document,
+    bucket: DateTime<Utc>,
+    taken_at: DateTime<Utc>,
+) -> Result<HistoryPoint, ApiError> {
+    let currency = doc.get_str("currency").map_err(|e| invalid("currency", e))?.to_string();
+    let currency_precision = currency_precision(&currency);
+    let mut holdings = Vec::new();
+    for holding in doc.get_array("holdings").map_err(|e| invalid("holdings", e))? {
+        let holding = match holding {
+            bson::Bson::Document(holding) => holding,
+            _ => return Err(ApiError::InternalServerError("Invalid holding in a snapshot".into())),
+        };
+        let ticker = holding.get_str("ticker").map_err(|e| invalid("ticker", e))?;
+        let amount = holding.get_i64("amount").map_err(|e| invalid("amount", e))?;
+        holdings.push(SnapshotHolding {
+            ticker: ticker.into(),
+            amount: Amount::from_units(amount, decimals.of(ticker)),
+            value: holding
+                .get_i64("value")
+                .ok()
+                .map(|value| Amount::from_units(value, currency_precision)),
+        });
+    }
+    let total = doc.get_i64("total").map_err(|e| invalid("total", e))?;
+    Ok(HistoryPoint {
+        at: bucket.to_rfc3339_opts(SecondsFormat::Secs, true),
+        taken_at: taken_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        total: Amount::from_units(total, currency_precision),
+        currency,
+        holdings,
+    })
+}
+
+fn invalid(field: &str, error: impl std::fmt::Display) -> ApiError {
+    ApiError::InternalServerError(format!("Invalid {} in a snapshot: {}", field, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prices::{PriceSource, StaticPrices};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn it_buckets_by_interval() {
+        let thursday = at("2026-10-15T13:45:10Z");
+        assert_eq!(Interval::Hour.bucket(thursday), at("2026-10-15T13:00:00Z"));
+        assert_eq!(Interval::Day.bucket(thursday), at("2026-10-15T00:00:00Z"));
+        assert_eq!(Interval::Week.bucket(thursday), at("2026-10-12T00:00:00Z"));
+        assert!(Interval::parse("minute").is_err());
+        assert_eq!(snapshot_time(thursday, 60), at("2026-10-15T13:00:00Z"));
+        assert_eq!(snapshot_time(thursday, 15), at("2026-10-15T13:45:00Z"));
+    }
+
+    #[test]
+    fn it_snapshots_each_holder_once_per_tick() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let other = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0.5").create();
+        fixtures.coin(&other.uid).coin("Ethereum", "ETH").amount("1").create();
+        let db = &*fixtures.ctx().db;
+        let prices = StaticPrices::new(&[("BTC", "USD", "60000")]).unwrap();
+        let quote = |ticker: &str| prices.quote(ticker, "USD");
+
+        assert_eq!(take_snapshots(db, "USD", quote, at("2026-10-15T13:00:00Z")).unwrap(), 2);
+        assert_eq!(take_snapshots(db, "USD", quote, at("2026-10-15T13:00:00Z")).unwrap(), 0);
+
+        let history = find_history(db, &user.uid, at("2026-10-15T00:00:00Z"), at("2026-10-16T00:00:00Z"), Interval::Day).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].holdings[0].amount.to_string(), "0.50000000");
+        assert_eq!(history[0].total.to_string(), "30000.00");
+        let history = find_history(db, &other.uid, at("2026-10-15T00:00:00Z"), at("2026-10-16T00:00:00Z"), Interval::Day).unwrap();
+        assert_eq!(history[0].holdings[0].value, None);
+    }
+
+    #[test]
+    fn it_keeps_the_last_snapshot_of_each_bucket() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let db = &*fixtures.ctx().db;
+        let unpriced = |ticker: &str| Err(ApiError::NotFound(ticker.into()));
+        for (hour, amount) in &[("2026-10-14T10", "1"), ("2026-10-15T09", "2"), ("2026-10-15T18", "3")] {
+            db.update_one("coins", doc! { "uid": &user.uid }, doc! { "$set": { "amount": Amount::parse(amount, 8).unwrap().units() } })
+                .unwrap();
+            take_snapshots(db, "USD", unpriced, at(&format!("{}:00:00Z", hour))).unwrap();
+        }
+
+        let from = at("2026-10-14T00:00:00Z");
+        let to = at("2026-10-16T00:00:00Z");
+        let daily = find_history(db, &user.uid, from, to, Interval::Day).unwrap();
+        let closing: Vec<String> = daily.iter().map(|point| point.holdings[0].amount.to_string()).collect();
+        assert_eq!(closing, ["1.00000000", "3.00000000"]);
+        assert_eq!(daily[1].at, "2026-10-15T00:00:00Z");
+        assert_eq!(daily[1].taken_at, "2026-10-15T18:00:00Z");
+        assert_eq!(find_history(db, &user.uid, from, to, Interval::Hour).unwrap().len(), 3);
+
+        assert!(find_history(db, &user.uid, to, from, Interval::Day).is_err());
+        assert!(find_history(db, &user.uid, from - Duration::days(400), to, Interval::Day).is_err());
+    }
+}
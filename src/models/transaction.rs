@@ -0,0 +1,284 @@
+//! Ledger of balance changes.
+//!
+//! Every change to a holding appends an entry to `coin_transactions` with the
+//! balance before and after it, so balances can be audited and recomputed.
+
+use bson::Bson;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::models::asset::decimals;
+use crate::store::{FindOptions, Store};
+
+const TRANSACTIONS: &str = "coin_transactions";
+
+/// Largest page of `find_transactions`
+pub const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A holding was opened with an initial balance
+    Open,
+    Deposit,
+    Withdraw,
+    TransferIn,
+    TransferOut,
+    /// A failed transfer returned to its sender
+    Refund,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Open => "open",
+            Kind::Deposit => "deposit",
+            Kind::Withdraw => "withdraw",
+            Kind::TransferIn => "transfer_in",
+            Kind::TransferOut => "transfer_out",
+            Kind::Refund => "refund",
+        }
+    }
+}
+
+/// Whether entries of `kind` take their amount out of the holding
+fn is_debit(kind: &str) -> bool {
+    kind == Kind::Withdraw.as_str() || kind == Kind::TransferOut.as_str()
+}
+
+/// A balance change to record
+pub struct Entry<'a> {
+    pub uid: &'a str,
+    pub ticker: &'a str,
+    pub kind: Kind,
+    pub amount: Amount,
+    pub before: Amount,
+    pub after: Amount,
+    /// Who made the change: a uid, or "cli" for administrative commands
+    pub actor: &'a str,
+    /// Transfer the change is part of
+    pub reference: Option<&'a str>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransactionResponse {
+    pub kind: String,
+    pub amount: Amount,
+    pub before: Amount,
+    pub after: Amount,
+    pub actor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransactionPage {
+    pub items: Vec<TransactionResponse>,
+    pub page: i64,
+    pub per_page: i64,
+    pub has_more: bool,
+}
+
+/// A holding whose balance differs from the sum of its ledger
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Drift {
+    pub uid: String,
+    pub ticker: String,
+    pub balance: Amount,
+    pub ledger: Amount,
+}
+
+/// Append an entry to the ledger
+pub fn record(db: &dyn Store, entry: Entry, now: DateTime<Utc>) -> Result<(), ApiError> {
+    let transaction = doc! {
+        "uid": entry.uid,
+        "ticker": entry.ticker,
+        "kind": entry.kind.as_str(),
+        "amount": entry.amount.units(),
+        "before": entry.before.units(),
+        "after": entry.after.units(),
+        "actor": entry.actor,
+        "reference": entry.reference.map_or(Bson::Null, Bson::from),
+        "created_at": now,
+    };
+    db.insert_one(TRANSACTIONS, transaction)?;
+    Ok(())
+}
+
+/// Entries of a holding, newest first. `page` starts at 1.
+pub fn find_transactions(db: &dyn Store, uid: &str, ticker: &str, page: i64, per_page: i64) -> Result<TransactionPage, ApiError> {
+    let page = page.max(1);
+    let per_page = per_page.clamp(1, MAX_PER_PAGE);
+    let skip = (page - 1)
+        .checked_mul(per_page)
+        .ok_or_else(|| ApiError::BadRequest(format!("page {} is out of range", page)))?;
+    // One extra entry tells whether another page follows
+    let options = FindOptions {
+        sort: Some(doc! { "created_at": -1, "_id": -1 }),
+        skip: Some(skip),
+        limit: Some(per_page + 1),
+    };
+    let documents = db.find(TRANSACTIONS, doc! { "uid": uid, "ticker": ticker }, options)?;
+    let has_more = documents.len() as i64 > per_page;
+    let precision = decimals(db, ticker)?;
+    let items = documents
+        .iter()
+        .take(per_page as usize)
+        .map(|doc| doc_to_model(doc, precision))
+        .collect::<Result<_, _>>()?;
+    Ok(TransactionPage { items, page, per_page, has_more })
+}
+
+/// Recompute every balance from the ledger and report the holdings that differ
+pub fn reconcile(db: &dyn Store) -> Result<Vec<Drift>, ApiError> {
+    let mut ledger: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for transaction in db.find(TRANSACTIONS, doc! {}, FindOptions::default())? {
+        let key = (field_str(&transaction, "uid")?, field_str(&transaction, "ticker")?);
+        let amount = field_i64(&transaction, "amount")?;
+        let signed = if is_debit(&field_str(&transaction, "kind")?) { -amount } else { amount };
+        *ledger.entry(key).or_insert(0) += signed;
+    }
+
+    let mut drifts = Vec::new();
+    for holding in db.find("coins", doc! {}, FindOptions::default())? {
+        let uid = field_str(&holding, "uid")?;
+        let ticker = field_str(&holding, "ticker")?;
+        let balance = field_i64(&holding, "amount")?;
+        let recomputed = ledger.remove(&(uid.clone(), ticker.clone())).unwrap_or(0);
+        if balance != recomputed {
+            drifts.push(drift(db, uid, ticker, balance, recomputed)?);
+        }
+    }
+    // Ledger entries of holdings that no longer exist
+    for ((uid, ticker), recomputed) in ledger {
+        if recomputed != 0 {
+            drifts.push(drift(db, uid, ticker, 0, recomputed)?);
+        }
+    }
+    Ok(drifts)
+}
+
+fn drift(db: &dyn Store, uid: String, ticker: String, balance: i64, ledger: i64) -> Result<Drift, ApiError> {
+    let precision = decimals(db, &ticker)?;
+    Ok(Drift {
+        uid,
+        balance: Amount::from_units(balance, precision),
+        ledger: Amount::from_units(ledger, precision),
+        ticker,
+    })
+}
+
+/// Helper function : doc --> model, amounts having `precision` decimals
+fn doc_to_model(doc: &bson::Document, precision: u32) -> Result<TransactionResponse, ApiError> {
+    let amount = |field| field_i64(doc, field).map(|units| Amount::from_units(units, precision));
+    Ok(TransactionResponse {
+        kind: field_str(doc, "kind")?,
+        amount: amount("amount")?,
+        before: amount("before")?,
+        after: amount("after")?,
+        actor: field_str(doc, "actor")?,
+        reference: doc.get_str("reference").ok().map(String::from),
+        created_at: doc
+            .get_utc_datetime("created_at")
+            .map_err(|e| invalid("created_at", e))?
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+    })
+}
+
+fn field_str(doc: &bson::Document, field: &str) -> Result<String, ApiError> {
+    doc.get_str(field).map(String::from).map_err(|e| invalid(field, e))
+}
+
+fn field_i64(doc: &bson::Document, field: &str) -> Result<i64, ApiError> {
+    doc.get_i64(field).map_err(|e| invalid(field, e))
+}
+
+fn invalid(field: &str, error: impl std::fmt::Display) -> ApiError {
+    ApiError::InternalServerError(format!("Invalid {} in the ledger: {}", field, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::coin::{deposit, withdraw};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    fn btc(value: &str) -> Amount {
+        Amount::parse(value, 8).unwrap()
+    }
+
+    #[test]
+    fn it_records_balance_changes() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let db = &*fixtures.ctx().db;
+
+        deposit(db, &user.uid, "BTC", btc("0.5"), &user.uid).unwrap();
+        withdraw(db, &user.uid, "BTC", btc("0.25"), "cli").unwrap();
+
+        let page = find_transactions(db, &user.uid, "BTC", 1, 10).unwrap();
+        assert_eq!(page.items.len(), 3);
+        assert!(!page.has_more);
+        let latest = &page.items[0];
+        assert_eq!(latest.kind, "withdraw");
+        assert_eq!((latest.before, latest.after), (btc("1.5"), btc("1.25")));
+        assert_eq!(latest.actor, "cli");
+        assert_eq!(page.items[1].kind, "deposit");
+        assert_eq!(page.items[2].kind, "open");
+        assert!(reconcile(db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_pages_newest_first() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+        for units in 1..=5 {
+            deposit(db, &user.uid, "BTC", Amount::from_units(units, 8), &user.uid).unwrap();
+        }
+
+        let first = find_transactions(db, &user.uid, "BTC", 1, 2).unwrap();
+        assert!(first.has_more);
+        assert_eq!(first.items[0].amount.units(), 5);
+        let last = find_transactions(db, &user.uid, "BTC", 3, 2).unwrap();
+        assert!(!last.has_more);
+        assert_eq!(last.items[0].amount.units(), 1);
+        assert_eq!(last.items[1].kind, "open");
+    }
+
+    #[test]
+    fn it_reports_balances_drifting_from_the_ledger() {
+        let ctx = test_context();
+        let db = &*ctx.db;
+        let now = Utc::now();
+        let entry = |uid, kind, amount: &str, after: &str| Entry {
+            uid,
+            ticker: "BTC",
+            kind,
+            amount: btc(amount),
+            before: btc("0"),
+            after: btc(after),
+            actor: "cli",
+            reference: None,
+        };
+        record(db, entry("a", Kind::Open, "1", "1"), now).unwrap();
+        record(db, entry("a", Kind::Withdraw, "0.4", "0.6"), now).unwrap();
+        record(db, entry("b", Kind::Deposit, "2", "2"), now).unwrap();
+        db.insert_one("coins", doc! { "uid": "a", "ticker": "BTC", "amount": 60_000_000_i64 }).unwrap();
+        db.insert_one("coins", doc! { "uid": "b", "ticker": "BTC", "amount": 300_000_000_i64 }).unwrap();
+
+        let drifts = reconcile(db).unwrap();
+        assert_eq!(drifts, vec![Drift {
+            uid: "b".into(),
+            ticker: "BTC".into(),
+            balance: btc("3"),
+            ledger: btc("2"),
+        }]);
+    }
+}
@@ -0,0 +1,527 @@
+//! Transfers of coins between users.
+//!
+//! The MongoDB driver in use has no multi-document transactions, so a transfer
+//! is a saga: it is recorded in `transfers`, the sender is debited, the
+//! recipient credited, and a failed credit is compensated by refunding the
+//! sender. Each step is in the ledger with the transfer id as its reference.
+//!
+//! Clients supply an idempotency key: repeating a request with the same key
+//! returns the original transfer, or its error, instead of moving coins twice.
+
+use bson::Bson;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::models::asset::decimals;
+use crate::models::coin::{create_holding, credit, debit, Cause};
+use crate::models::transaction::Kind;
+use crate::store::{FindOptions, Store};
+
+const TRANSFERS: &str = "transfers";
+
+/// A transfer still in progress after this long was interrupted
+pub const STALE_AFTER_MINUTES: i64 = 10;
+
+const PENDING: &str = "pending";
+const DEBITED: &str = "debited";
+/// Claimed for crediting the recipient
+const CREDITING: &str = "crediting";
+const COMPLETED: &str = "completed";
+const FAILED: &str = "failed";
+/// Refunded, but the credit of the recipient could not be taken back; settled by hand
+const RECONCILE: &str = "needs_reconciliation";
+
+pub struct TransferRequest<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub ticker: &'a str,
+    pub amount: Amount,
+    pub idempotency_key: &'a str,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransferResponse {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub ticker: String,
+    pub amount: Amount,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: String,
+}
+
+/// Move `amount` from one user's holding to another's.
+/// The recipient's holding is opened if they have none.
+pub fn transfer(db: &dyn Store, request: TransferRequest) -> Result<TransferResponse, ApiError> {
+    if request.from == request.to {
+        return Err(ApiError::BadRequest("Cannot transfer to yourself".into()));
+    }
+    if request.amount.is_zero() {
+        return Err(ApiError::ValidationError(vec!["amount must be greater than 0".into()]));
+    }
+
+    let now = Utc::now();
+    let document = doc! {
+        "idempotency_key": request.idempotency_key,
+        "from": request.from,
+        "to": request.to,
+        "ticker": request.ticker,
+        "amount": request.amount.units(),
+        "status": PENDING,
+        "created_at": now,
+        "updated_at": now,
+    };
+    let id = match db.insert_one(TRANSFERS, document) {
+        Ok(id) => id,
+        Err(ApiError::Conflict(_)) => return replay(db, &request),
+        Err(e) => return Err(e),
+    };
+    let reference = reference(&id);
+
+    if db.find_one("users", doc! { "uid": request.to })?.is_none() {
+        return fail(db, &id, PENDING, ApiError::NotFound(format!("No user {}", request.to)));
+    }
+    let out = Cause { kind: Kind::TransferOut, actor: request.from, reference: Some(&reference) };
+    if let Err(e) = debit(db, request.from, request.ticker, request.amount, out) {
+        return fail(db, &id, PENDING, e);
+    }
+    // Every step is claimed from the status before it; losing a claim means
+    // `recover_transfers` took the transfer over as interrupted
+    if !transition(db, &id, PENDING, DEBITED)? || !transition(db, &id, DEBITED, CREDITING)? {
+        refund_once(db, &id, request.from, request.ticker, request.amount)?;
+        return Err(interrupted());
+    }
+
+    let into = Cause { kind: Kind::TransferIn, actor: request.from, reference: Some(&reference) };
+    if let Err(e) = credit_recipient(db, &request, into) {
+        refund_once(db, &id, request.from, request.ticker, request.amount)?;
+        return fail(db, &id, CREDITING, e);
+    }
+    if !transition(db, &id, CREDITING, COMPLETED)? {
+        // Recovered as failed and refunded meanwhile, take the credit back
+        return Err(take_back_credit(db, &id, &request, &reference));
+    }
+    find_transfer(db, &id)
+}
+
+/// Settle transfers interrupted before `stale_before`. One credited before the
+/// interruption is completed; any other is failed and its sender, if debited,
+/// refunded. Returns how many were recovered.
+pub fn recover_transfers(db: &dyn Store, stale_before: DateTime<Utc>) -> Result<usize, ApiError> {
+    let filter = doc! { "status": { "$in": [PENDING, DEBITED, CREDITING] }, "updated_at": { "$lt": stale_before } };
+    let mut recovered = 0;
+    for transfer in db.find(TRANSFERS, filter, FindOptions::default())? {
+        let id = transfer.get("_id").cloned().unwrap_or(Bson::Null);
+        let reference = reference(&id);
+        let status = transfer.get_str("status").unwrap_or_default();
+        let from = transfer.get_str("from").unwrap_or_default();
+        let ticker = transfer.get_str("ticker").unwrap_or_default();
+        let transfer_entry = |uid: &str, kind: Kind| {
+            db.find_one("coin_transactions", doc! { "uid": uid, "kind": kind.as_str(), "reference": &reference })
+        };
+
+        let credited = transfer_entry(transfer.get_str("to").unwrap_or_default(), Kind::TransferIn)?.is_some();
+        // Claiming the transfer keeps two recoveries, or a recovery and a slow request, from both settling it
+        let claimed = if credited {
+            transition(db, &id, status, COMPLETED)?
+        } else {
+            let error = interrupted();
+            let claim = doc! { "_id": id.clone(), "status": status, "updated_at": { "$lt": stale_before } };
+            let update = doc! { "$set": {
+                "status": FAILED,
+                "error": stored_error(&error),
+                "error_kind": error_kind(&error),
+                "updated_at": Utc::now(),
+            } };
+            db.find_one_and_update(TRANSFERS, claim, update)?.is_some()
+        };
+        if !claimed {
+            continue;
+        }
+        if !credited && transfer_entry(from, Kind::TransferOut)?.is_some() {
+            let amount = Amount::from_units(transfer.get_i64("amount").unwrap_or_default(), decimals(db, ticker)?);
+            refund_once(db, &id, from, ticker, amount)?;
+        }
+        recovered += 1;
+    }
+    Ok(recovered)
+}
+
+/// Cutoff for `recover_transfers` relative to now
+pub fn stale_cutoff() -> DateTime<Utc> {
+    Utc::now() - Duration::minutes(STALE_AFTER_MINUTES)
+}
+
+fn credit_recipient(db: &dyn Store, request: &TransferRequest, cause: Cause) -> Result<Amount, ApiError> {
+    let holding = doc! { "uid": request.to, "ticker": request.ticker };
+    if db.find_one("coins", holding)?.is_none() {
+        let coin = db
+            .find_one("coins", doc! { "uid": request.from, "ticker": request.ticker })?
+            .and_then(|holding| holding.get_str("coin").ok().map(String::from))
+            .unwrap_or_else(|| request.ticker.to_string());
+        let zero = Amount::from_units(0, request.amount.precision());
+        match create_holding(db, request.to, &coin, request.ticker, zero, cause.actor) {
+            Ok(_) | Err(ApiError::Conflict(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    credit(db, request.to, request.ticker, request.amount, cause)
+}
+
+/// Return a debited amount to the sender, unless the transfer was refunded already
+fn refund_once(db: &dyn Store, id: &Bson, from: &str, ticker: &str, amount: Amount) -> Result<(), ApiError> {
+    let unrefunded = doc! { "_id": id.clone(), "refunded": { "$exists": false } };
+    if db.find_one_and_update(TRANSFERS, unrefunded, doc! { "$set": { "refunded": true } })?.is_none() {
+        return Ok(());
+    }
+    let reference = reference(id);
+    let cause = Cause { kind: Kind::Refund, actor: from, reference: Some(&reference) };
+    credit(db, from, ticker, amount, cause)?;
+    Ok(())
+}
+
+/// Take back the credit of a transfer refunded meanwhile, returning the error
+/// to answer with. Should the recipient have moved the coins already, the
+/// transfer is flagged as `RECONCILE` instead.
+fn take_back_credit(db: &dyn Store, id: &Bson, request: &TransferRequest, reference: &str) -> ApiError {
+    let back = Cause { kind: Kind::TransferOut, actor: request.from, reference: Some(reference) };
+    let cause = match debit(db, request.to, request.ticker, request.amount, back) {
+        Ok(_) => return interrupted(),
+        Err(e) => e,
+    };
+    error!(
+        "Transfer {} refunded {} but its credit of {} {} to {} could not be taken back: {}",
+        reference, request.from, request.amount, request.ticker, request.to, cause
+    );
+    let error = ApiError::Conflict("Transfer was interrupted after crediting the recipient and needs reconciliation".into());
+    let update = doc! { "$set": {
+        "status": RECONCILE,
+        "error": stored_error(&error),
+        "error_kind": error_kind(&error),
+        "reconcile_cause": stored_error(&cause),
+        "updated_at": Utc::now(),
+    } };
+    if let Err(e) = db.update_one(TRANSFERS, doc! { "_id": id.clone() }, update) {
+        error!("Could not flag transfer {} for reconciliation: {}", reference, e);
+    }
+    error
+}
+
+fn interrupted() -> ApiError {
+    ApiError::Conflict("Transfer was interrupted, the sender was refunded".into())
+}
+
+/// Answer a repeated request from the transfer made by the first one
+fn replay(db: &dyn Store, request: &TransferRequest) -> Result<TransferResponse, ApiError> {
+    let filter = doc! { "from": request.from, "idempotency_key": request.idempotency_key };
+    let existing = db
+        .find_one(TRANSFERS, filter)?
+        .ok_or_else(|| ApiError::Conflict("A transfer with the same key already exists".into()))?;
+    let same_request = existing.get_str("to").ok() == Some(request.to)
+        && existing.get_str("ticker").ok() == Some(request.ticker)
+        && existing.get_i64("amount").ok() == Some(request.amount.units());
+    if !same_request {
+        return Err(ApiError::Conflict("Idempotency key was already used for a different transfer".into()));
+    }
+    match existing.get_str("status") {
+        Ok(COMPLETED) => doc_to_model(db, &existing),
+        Ok(FAILED) | Ok(RECONCILE) => Err(restored_error(
+            existing.get_str("error_kind").unwrap_or_default(),
+            existing.get_str("error").unwrap_or_default(),
+        )),
+        _ => Err(ApiError::Conflict("A transfer with the same key is still in progress".into())),
+    }
+}
+
+/// Mark a transfer failed, if it is still in `from`, and return `error`
+fn fail(db: &dyn Store, id: &Bson, from: &str, error: ApiError) -> Result<TransferResponse, ApiError> {
+    let update = doc! { "$set": {
+        "status": FAILED,
+        "error": stored_error(&error),
+        "error_kind": error_kind(&error),
+        "updated_at": Utc::now(),
+    } };
+    db.update_one(TRANSFERS, doc! { "_id": id.clone(), "status": from }, update)?;
+    Err(error)
+}
+
+/// Move a transfer from status `from` to `to`, false when it is no longer in `from`
+fn transition(db: &dyn Store, id: &Bson, from: &str, to: &str) -> Result<bool, ApiError> {
+    let update = doc! { "$set": { "status": to, "updated_at": Utc::now() } };
+    Ok(db.update_one(TRANSFERS, doc! { "_id": id.clone(), "status": from }, update)?.matched_count == 1)
+}
+
+/// Message of a failure, as stored with the transfer
+fn stored_error(error: &ApiError) -> String {
+    match error {
+        ApiError::ValidationError(errors) => errors.join(", "),
+        error => error.to_string(),
+    }
+}
+
+/// Kind of a failure, so a replay answers with the status of the first request
+fn error_kind(error: &ApiError) -> &'static str {
+    match error {
+        ApiError::BadRequest(_) => "bad_request",
+        ApiError::Conflict(_) => "conflict",
+        ApiError::NotFound(_) => "not_found",
+        ApiError::ValidationError(_) => "invalid",
+        _ => "internal",
+    }
+}
+
+fn restored_error(kind: &str, message: &str) -> ApiError {
+    let message = message.to_string();
+    match kind {
+        "bad_request" => ApiError::BadRequest(message),
+        "conflict" => ApiError::Conflict(message),
+        "not_found" => ApiError::NotFound(message),
+        "invalid" => ApiError::ValidationError(vec![message]),
+        _ => ApiError::InternalServerError(message),
+    }
+}
+
+fn find_transfer(db: &dyn Store, id: &Bson) -> Result<TransferResponse, ApiError> {
+    let transfer = db
+        .find_one(TRANSFERS, doc! { "_id": id.clone() })?
+        .ok_or_else(|| ApiError::NotFound("Transfer not found".into()))?;
+    doc_to_model(db, &transfer)
+}
+
+/// Transfer id as written in the ledger
+fn reference(id: &Bson) -> String {
+    match id {
+        Bson::ObjectId(id) => id.to_hex(),
+        id => id.to_string(),
+    }
+}
+
+fn doc_to_model(db: &dyn Store, doc: &bson::Document) -> Result<TransferResponse, ApiError> {
+    let invalid = |field: &str| ApiError::InternalServerError(format!("Invalid {} in a transfer", field));
+    let text = |field: &str| doc.get_str(field).map(String::from).map_err(|_| invalid(field));
+    let ticker = text("ticker")?;
+    Ok(TransferResponse {
+        id: reference(doc.get("_id").unwrap_or(&Bson::Null)),
+        from: text("from")?,
+        to: text("to")?,
+        amount: Amount::from_units(doc.get_i64("amount").map_err(|_| invalid("amount"))?, decimals(db, &ticker)?),
+        ticker,
+        status: text("status")?,
+        error: doc.get_str("error").ok().map(String::from),
+        created_at: doc
+            .get_utc_datetime("created_at")
+            .map_err(|_| invalid("created_at"))?
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::{find_transactions, reconcile};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
+
+    fn btc(value: &str) -> Amount {
+        Amount::parse(value, 8).unwrap()
+    }
+
+    fn balance(db: &dyn Store, uid: &str) -> i64 {
+        db.find_one("coins", doc! { "uid": uid, "ticker": "BTC" })
+            .unwrap()
+            .map_or(0, |holding| holding.get_i64("amount").unwrap())
+    }
+
+    fn request<'a>(from: &'a str, to: &'a str, amount: &str, key: &'a str) -> TransferRequest<'a> {
+        TransferRequest { from, to, ticker: "BTC", amount: btc(amount), idempotency_key: key }
+    }
+
+    #[test]
+    fn it_moves_coins_and_opens_the_recipient_holding() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        let db = &*fixtures.ctx().db;
+
+        let transfer = transfer(db, request(&sender.uid, &recipient.uid, "0.4", "key-1")).unwrap();
+        assert_eq!(transfer.status, COMPLETED);
+        assert_eq!(balance(db, &sender.uid), 60_000_000);
+        assert_eq!(balance(db, &recipient.uid), 40_000_000);
+
+        let received = find_transactions(db, &recipient.uid, "BTC", 1, 10).unwrap();
+        assert_eq!(received.items[0].kind, "transfer_in");
+        assert_eq!(received.items[0].reference.as_deref(), Some(transfer.id.as_str()));
+        db.delete_many("coins", doc! { "uid": &recipient.uid }).unwrap();
+        db.delete_many("coin_transactions", doc! { "uid": &recipient.uid }).unwrap();
+    }
+
+    #[test]
+    fn it_replays_a_repeated_request() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+
+        let first = transfer(db, request(&sender.uid, &recipient.uid, "0.4", "key-1")).unwrap();
+        let again = transfer(db, request(&sender.uid, &recipient.uid, "0.4", "key-1")).unwrap();
+        assert_eq!(first.id, again.id);
+        assert_eq!(balance(db, &sender.uid), 60_000_000);
+
+        let different = transfer(db, request(&sender.uid, &recipient.uid, "0.5", "key-1"));
+        assert!(matches!(different, Err(ApiError::Conflict(_))));
+
+        // A failed transfer answers with its error, one in progress with a conflict
+        let failed = transfer(db, request(&sender.uid, &recipient.uid, "5", "key-2"));
+        assert!(matches!(failed, Err(ApiError::BadRequest(_))));
+        let again = transfer(db, request(&sender.uid, &recipient.uid, "5", "key-2"));
+        assert!(matches!(again, Err(ApiError::BadRequest(ref message)) if message == "Insufficient balance"));
+        db.update_one(TRANSFERS, doc! { "idempotency_key": "key-2" }, doc! { "$set": { "status": PENDING } }).unwrap();
+        let pending = transfer(db, request(&sender.uid, &recipient.uid, "5", "key-2"));
+        assert!(matches!(pending, Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn it_fails_without_moving_coins() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+
+        let result = transfer(db, request(&sender.uid, &recipient.uid, "2", "key-1"));
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        let result = transfer(db, request(&sender.uid, "nobody", "0.1", "key-2"));
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+        assert_eq!(balance(db, &sender.uid), 100_000_000);
+
+        let failed = db.find_one(TRANSFERS, doc! { "idempotency_key": "key-1" }).unwrap().unwrap();
+        assert_eq!(failed.get_str("status").unwrap(), FAILED);
+    }
+
+    #[test]
+    fn it_refunds_an_interrupted_transfer() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        let db = &*fixtures.ctx().db;
+
+        // A crash right after the debit
+        let past = Utc::now() - Duration::hours(1);
+        let id = db
+            .insert_one(TRANSFERS, doc! {
+                "idempotency_key": "key-1", "from": &sender.uid, "to": &recipient.uid, "ticker": "BTC",
+                "amount": 40_000_000_i64, "status": DEBITED, "created_at": past, "updated_at": past,
+            })
+            .unwrap();
+        let out = Cause { kind: Kind::TransferOut, actor: &sender.uid, reference: Some(&reference(&id)) };
+        debit(db, &sender.uid, "BTC", btc("0.4"), out).unwrap();
+
+        assert_eq!(recover_transfers(db, stale_cutoff()).unwrap(), 1);
+        assert_eq!(recover_transfers(db, stale_cutoff()).unwrap(), 0);
+        assert_eq!(balance(db, &sender.uid), 100_000_000);
+        assert!(reconcile(db).unwrap().is_empty());
+        let recovered = db.find_one(TRANSFERS, doc! { "_id": id }).unwrap().unwrap();
+        assert_eq!(recovered.get_str("status").unwrap(), FAILED);
+    }
+
+    #[test]
+    fn it_does_not_complete_a_transfer_recovered_meanwhile() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+        let past = Utc::now() - Duration::hours(1);
+        let id = db
+            .insert_one(TRANSFERS, doc! {
+                "idempotency_key": "key-1", "from": &sender.uid, "to": &recipient.uid, "ticker": "BTC",
+                "amount": 40_000_000_i64, "status": CREDITING, "created_at": past, "updated_at": past,
+            })
+            .unwrap();
+        let reference = reference(&id);
+        let out = Cause { kind: Kind::TransferOut, actor: &sender.uid, reference: Some(&reference) };
+        debit(db, &sender.uid, "BTC", btc("0.4"), out).unwrap();
+
+        // The slow request has yet to credit when the recovery runs
+        assert_eq!(recover_transfers(db, stale_cutoff()).unwrap(), 1);
+        assert!(!transition(db, &id, CREDITING, COMPLETED).unwrap());
+        refund_once(db, &id, &sender.uid, "BTC", btc("0.4")).unwrap();
+        assert_eq!(balance(db, &sender.uid), 100_000_000);
+        assert_eq!(balance(db, &recipient.uid), 0);
+        assert!(reconcile(db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_flags_a_transfer_whose_credit_cannot_be_taken_back() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+        let past = Utc::now() - Duration::hours(1);
+        let id = db
+            .insert_one(TRANSFERS, doc! {
+                "idempotency_key": "key-1", "from": &sender.uid, "to": &recipient.uid, "ticker": "BTC",
+                "amount": 40_000_000_i64, "status": CREDITING, "created_at": past, "updated_at": past,
+            })
+            .unwrap();
+        let reference = reference(&id);
+        let out = Cause { kind: Kind::TransferOut, actor: &sender.uid, reference: Some(&reference) };
+        debit(db, &sender.uid, "BTC", btc("0.4"), out).unwrap();
+
+        // Recovered and refunded before the slow request credits, whose recipient
+        // then moves the coins before the credit is taken back
+        assert_eq!(recover_transfers(db, stale_cutoff()).unwrap(), 1);
+        let into = Cause { kind: Kind::TransferIn, actor: &sender.uid, reference: Some(&reference) };
+        credit(db, &recipient.uid, "BTC", btc("0.4"), into).unwrap();
+        let spent = Cause { kind: Kind::TransferOut, actor: &recipient.uid, reference: None };
+        debit(db, &recipient.uid, "BTC", btc("0.3"), spent).unwrap();
+
+        let request = request(&sender.uid, &recipient.uid, "0.4", "key-1");
+        assert!(matches!(take_back_credit(db, &id, &request, &reference), ApiError::Conflict(_)));
+        let flagged = db.find_one(TRANSFERS, doc! { "_id": id }).unwrap().unwrap();
+        assert_eq!(flagged.get_str("status").unwrap(), RECONCILE);
+        assert!(flagged.get_str("reconcile_cause").is_ok());
+        assert_eq!(balance(db, &recipient.uid), 10_000_000);
+        assert!(matches!(replay(db, &request), Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn it_completes_a_transfer_interrupted_after_the_credit() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let db = &*fixtures.ctx().db;
+        let past = Utc::now() - Duration::hours(1);
+        let id = db
+            .insert_one(TRANSFERS, doc! {
+                "idempotency_key": "key-1", "from": &sender.uid, "to": &recipient.uid, "ticker": "BTC",
+                "amount": 40_000_000_i64, "status": CREDITING, "created_at": past, "updated_at": past,
+            })
+            .unwrap();
+        let reference = reference(&id);
+        let out = Cause { kind: Kind::TransferOut, actor: &sender.uid, reference: Some(&reference) };
+        debit(db, &sender.uid, "BTC", btc("0.4"), out).unwrap();
+        let into = Cause { kind: Kind::TransferIn, actor: &sender.uid, reference: Some(&reference) };
+        credit(db, &recipient.uid, "BTC", btc("0.4"), into).unwrap();
+
+        assert_eq!(recover_transfers(db, stale_cutoff()).unwrap(), 1);
+        let recovered = db.find_one(TRANSFERS, doc! { "_id": id }).unwrap().unwrap();
+        assert_eq!(recovered.get_str("status").unwrap(), COMPLETED);
+        assert_eq!(balance(db, &sender.uid), 60_000_000);
+        assert_eq!(balance(db, &recipient.uid), 40_000_000);
+    }
+}
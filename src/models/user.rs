@@ -1,9 +1,16 @@
 
 use serde::Serialize;
 use chrono::prelude::*;
+use chrono::Duration;
 
+use crate::cache::{get_or_load, invalidate, Cache};
 use crate::database;
 use crate::errors::ApiError;
+use crate::redact::redact_document;
+use crate::store::{FindOptions, Store};
+
+/// Failed second factors, kept until they no longer count towards a lockout
+pub const MFA_ATTEMPTS: &str = "mfa_attempts";
 
 #[derive(Clone, Debug)]
 pub struct User {
@@ -39,65 +46,258 @@ fn doc_to_model(doc: &bson::Document) -> UserResponse {
 }
 
 /// Find User by id and password info
-pub async fn find_by_auth( id: &str , password: &str ) -> Option<UserResponse> {
-    println!("[DEBUG] find_by_auth ");
-    println!("id : {}", id);
-    println!("password : {}", password);
-    let coll = database::collection("users");
+pub async fn find_by_auth( db: &dyn Store, id: &str , password: &str ) -> Result<Option<UserResponse>, ApiError> {
     let query = doc! { "uid" : id.to_string(), "password" : password.to_string() };
-    let cursor = coll.find_one(query, None).unwrap();
-    cursor.map(|x| doc_to_model(&x))
+    debug!("find_by_auth {}", redact_document(&query));
+    let user = db.find_one("users", query)?;
+    Ok(user.map(|x| doc_to_model(&x)))
 }
 
-/// Get User with query
-pub fn get_data(query : bson::Document ) -> Result<Vec<Option<UserResponse>>, ApiError> {
-    let coll = database::collection("users");
-    let cursor = coll.find(query, None).unwrap();
+/// Set a new (already hashed) password for a User.
+/// Records when the password changed so older sessions can be rejected.
+pub fn update_password(db: &dyn Store, id: &str, password: &str) -> Result<String, ApiError> {
+    let now = Utc::now();
+    let filter = doc! { "uid" : id.to_string() };
+    let query = doc! { "$set" : {
+        "password": password.to_string(),
+        "password_changed_at_ms": now.timestamp_millis(),
+        "updated_at": now
+       }
+    };
+    database::update(db, "users", filter, query)
+}
+
+/// Check whether a session token issued at `issued_at_ms` (unix milliseconds)
+/// is revoked for a User: tokens issued before the last password change are.
+/// The change time is cached only in a shared cache, where `forget_cached_user`
+/// after a password change reaches every instance; a per-instance cache would
+/// keep accepting revoked tokens on the other instances until its entry expired.
+pub fn is_session_revoked(db: &dyn Store, cache: &dyn Cache, id: &str, issued_at_ms: i64) -> Result<bool, ApiError> {
+    let changed_at = if cache.is_shared() {
+        get_or_load(cache, &session_cache_key(id), || password_changed_at(db, id))
+    } else {
+        password_changed_at(db, id)
+    };
+
+    match changed_at {
+        Ok(Some(changed_at_ms)) => Ok(issued_at_ms < changed_at_ms),
+        Ok(None) => Ok(false),
+        Err(ApiError::NotFound(_)) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// When the password of a User last changed in unix milliseconds, None if it never did
+pub fn password_changed_at(db: &dyn Store, id: &str) -> Result<Option<i64>, ApiError> {
+    let query = doc! { "uid" : id.to_string() };
+    let user = db.find_one("users", query)?;
+
+    let doc = user.ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))?;
+    Ok(doc.get_i64("password_changed_at_ms").ok())
+}
+
+/// Whether User `id` has `role`, read from the database so revoked roles take effect at once
+pub fn has_role(db: &dyn Store, id: &str, role: &str) -> Result<bool, ApiError> {
+    let user = db.find_one("users", doc! { "uid": id, "roles": role })?;
+    Ok(user.is_some())
+}
+
+/// Get User by id, NotFound if there is none
+pub fn find_user(db: &dyn Store, id: &str) -> Result<UserResponse, ApiError> {
+    db.find_one("users", doc! { "uid" : id.to_string() })?
+        .map(|doc| doc_to_model(&doc))
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))
+}
+
+/// Get User by id, through the cache
+pub fn find_cached(db: &dyn Store, cache: &dyn Cache, id: &str) -> Result<Vec<Option<UserResponse>>, ApiError> {
+    get_or_load(cache, &user_cache_key(id), || get_data(db, doc! { "uid" : id.to_string() }))
+}
+
+/// Drop everything cached about a User, after it was created, changed or deleted
+pub fn forget_cached_user(cache: &dyn Cache, id: &str) {
+    invalidate(cache, &user_cache_key(id));
+    invalidate(cache, &session_cache_key(id));
+}
+
+fn user_cache_key(id: &str) -> String {
+    format!("user:{}", id)
+}
+
+fn session_cache_key(id: &str) -> String {
+    format!("user:{}:password_changed_at_ms", id)
+}
+
+/// Two-factor authentication settings of a User
+#[derive(Clone, Debug, Default)]
+pub struct TotpSettings {
+    pub enabled: bool,
+    pub secret: Option<String>,
+    pub pending_secret: Option<String>,
+}
+
+/// Get the two-factor authentication settings of a User
+pub fn find_totp(db: &dyn Store, id: &str) -> Result<TotpSettings, ApiError> {
+    let query = doc! { "uid" : id.to_string() };
+    let user = db.find_one("users", query)?;
+
+    let doc = user.ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))?;
+    Ok(TotpSettings {
+        enabled: doc.get_bool("totp_enabled").unwrap_or(false),
+        secret: doc.get_str("totp_secret").ok().map(String::from),
+        pending_secret: doc.get_str("totp_pending_secret").ok().map(String::from),
+    })
+}
+
+/// Store a TOTP secret awaiting confirmation with a first valid code
+pub fn set_pending_totp_secret(db: &dyn Store, id: &str, secret: &str) -> Result<String, ApiError> {
+    let filter = doc! { "uid" : id.to_string() };
+    let query = doc! { "$set" : {
+        "totp_pending_secret": secret.to_string(),
+        "updated_at": Utc::now()
+       }
+    };
+    database::update(db, "users", filter, query)
+}
+
+/// Turn on two-factor authentication with a confirmed secret, `step` being
+/// the time step of the confirming code. Recovery codes must already be hashed.
+pub fn enable_totp(db: &dyn Store, id: &str, secret: &str, step: i64, recovery_codes: Vec<String>) -> Result<String, ApiError> {
+    let recovery_codes: Vec<bson::Bson> = recovery_codes.into_iter().map(bson::Bson::String).collect();
+    let filter = doc! { "uid" : id.to_string() };
+    let query = doc! {
+        "$set" : {
+            "totp_enabled": true,
+            "totp_secret": secret.to_string(),
+            "totp_last_step": step,
+            "totp_recovery_codes": recovery_codes,
+            "updated_at": Utc::now()
+        },
+        "$unset" : { "totp_pending_secret": "" }
+    };
+    database::update(db, "users", filter, query)
+}
+
+/// Record `step` as the last TOTP time step a User logged in with.
+/// Returns false if that step or a later one was already used, so each code works once.
+pub fn accept_totp_step(db: &dyn Store, id: &str, step: i64) -> Result<bool, ApiError> {
+    let filter = doc! {
+        "uid" : id.to_string(),
+        "$or" : [
+            { "totp_last_step" : { "$exists" : false } },
+            { "totp_last_step" : { "$lt" : step } }
+        ]
+    };
+    let query = doc! { "$set" : { "totp_last_step" : step } };
+    let result = db.update_one("users", filter, query)?;
+    Ok(result.matched_count > 0)
+}
+
+/// Whether User `id` failed the second factor `max_failures` times within the lockout
+pub fn is_mfa_locked(db: &dyn Store, id: &str, max_failures: i64) -> Result<bool, ApiError> {
+    let filter = doc! { "uid" : id.to_string(), "expires_at" : { "$gt" : Utc::now() } };
+    let options = FindOptions {
+        limit: Some(max_failures),
+        ..FindOptions::default()
+    };
+    Ok(db.find(MFA_ATTEMPTS, filter, options)?.len() as i64 >= max_failures)
+}
 
-    let result = cursor.into_iter()
-        .map(|document| {
-            match document {
-                Ok(doc) => Ok(Some(doc_to_model(&doc))),
-                Err(e) => return Err(e).map_err(ApiError::DBError)
-            }
-        })
-        .collect::<Result<Vec<Option<UserResponse>>, ApiError>>();
+/// Record a failed second factor of User `id`, counted for `lockout_minutes`
+pub fn record_mfa_failure(db: &dyn Store, id: &str, lockout_minutes: i64) -> Result<(), ApiError> {
+    let now = Utc::now();
+    let attempt = doc! {
+        "uid" : id.to_string(),
+        "failed_at" : now,
+        "expires_at" : now + Duration::minutes(lockout_minutes)
+    };
+    db.insert_one(MFA_ATTEMPTS, attempt)?;
+    Ok(())
+}
 
-    result.into()
+/// Delete the failed second factors that stopped counting at `now`, ahead of the TTL index
+pub fn delete_expired_mfa_failures(db: &dyn Store, now: DateTime<Utc>) -> Result<i64, ApiError> {
+    db.delete_many(MFA_ATTEMPTS, doc! { "expires_at" : { "$lte" : now } })
+}
+
+/// Forget the failed second factors of User `id` after a successful one
+pub fn clear_mfa_failures(db: &dyn Store, id: &str) -> Result<(), ApiError> {
+    db.delete_many(MFA_ATTEMPTS, doc! { "uid" : id.to_string() })?;
+    Ok(())
+}
+
+/// Remove a (hashed) recovery code from a User.
+/// Returns true only if the code existed, so each code works once.
+pub fn consume_recovery_code(db: &dyn Store, id: &str, recovery_code: &str) -> Result<bool, ApiError> {
+    let filter = doc! { "uid" : id.to_string(), "totp_recovery_codes" : recovery_code.to_string() };
+    let query = doc! { "$pull" : { "totp_recovery_codes" : recovery_code.to_string() } };
+    let result = db.update_one("users", filter, query)?;
+    Ok(result.modified_count > 0)
+}
+
+/// Get User with query
+pub fn get_data(db: &dyn Store, query : bson::Document ) -> Result<Vec<Option<UserResponse>>, ApiError> {
+    let documents = db.find("users", query, FindOptions::default())?;
+    Ok(documents.iter().map(|doc| Some(doc_to_model(doc))).collect())
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::test_context;
 
     #[test]
     pub fn test_get_data() {
-       let query = doc! { "uid" => "bsjung"};
-       let results = get_data(query);
-       /*
-       for item in results {
-         println! ("{:#?}", item);
-       }
-       */
+       let fixtures = Fixtures::new(test_context());
+       let user = fixtures.user().create();
+       let query = doc! { "uid" => &user.uid };
+       let results = get_data(&*fixtures.ctx().db, query).unwrap();
+       assert_eq!(results.len(), 1);
     }
 
     #[test]
-    #[ignore]
     pub fn test_create_data() {
       println!("Creating User");
       let data = doc! {
-            "uid" : "bsjung",
-            "email" : "bsjung@gmail.com",
+            "uid" : "bsjung3",
+            "email" : "bsjung3@gmail.com",
             "password" : "xxxx",
             "name" : "Benjamin Jung",
             "phone" : "010-5049-xxxx",
             "created_at" : Utc::now(),
             "updated_at" : Utc::now(),
            };
-      let result = database::create("users", data);
+      let result = database::create(&*test_context().db, "users", data);
     
-      if result.is_err()  {
-          println!("Could not result new user!")
-      }
+      assert!(result.is_ok(), "Could not create a new user");
+    }
+
+    #[test]
+    fn it_sees_a_password_change_made_by_another_instance() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let ctx = fixtures.ctx();
+        let issued_at_ms = Utc::now().timestamp_millis() - 60_000;
+        assert!(!is_session_revoked(&*ctx.db, &*ctx.cache, &user.uid, issued_at_ms).unwrap());
+
+        // Another instance changes the password, this instance's cache is not told
+        let changed = doc! { "$set": { "password_changed_at_ms": Utc::now().timestamp_millis() } };
+        ctx.db.update_one("users", doc! { "uid": &user.uid }, changed).unwrap();
+        assert!(is_session_revoked(&*ctx.db, &*ctx.cache, &user.uid, issued_at_ms).unwrap());
+    }
+
+    #[test]
+    fn it_revokes_tokens_issued_earlier_in_the_second_of_a_password_change() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let ctx = fixtures.ctx();
+        let second_ms = Utc::now().timestamp() * 1000;
+        let changed = doc! { "$set": { "password_changed_at_ms": second_ms + 500 } };
+        ctx.db.update_one("users", doc! { "uid": &user.uid }, changed).unwrap();
+
+        assert!(is_session_revoked(&*ctx.db, &*ctx.cache, &user.uid, second_ms + 100).unwrap());
+        assert!(!is_session_revoked(&*ctx.db, &*ctx.cache, &user.uid, second_ms + 500).unwrap());
+        assert!(!is_session_revoked(&*ctx.db, &*ctx.cache, &user.uid, second_ms + 900).unwrap());
     }
 }
@@ -0,0 +1,96 @@
+//! Fixed quotes, read once from a TOML file of tables per ticker:
+//!
+//! ```toml
+//! [BTC]
+//! USD = "64000.50"
+//! EUR = "59120"
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use super::{PriceSource, PRICE_PRECISION};
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+
+#[derive(Default)]
+pub struct StaticPrices {
+    /// Prices keyed by (ticker, currency), both uppercase
+    quotes: HashMap<(String, String), Amount>,
+}
+
+impl StaticPrices {
+    /// Quotes given as (ticker, currency, decimal price)
+    #[cfg(test)]
+    pub fn new(quotes: &[(&str, &str, &str)]) -> Result<Self, ApiError> {
+        let mut prices = StaticPrices::default();
+        for (ticker, currency, price) in quotes {
+            prices.insert(ticker, currency, price)?;
+        }
+        Ok(prices)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ApiError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ApiError::PriceSourceError(format!("Cannot read price file {}: {}", path, e)))?;
+        let tables: BTreeMap<String, BTreeMap<String, toml::Value>> = toml::from_str(&contents)
+            .map_err(|e| ApiError::PriceSourceError(format!("Cannot parse price file {}: {}", path, e)))?;
+
+        let mut prices = StaticPrices::default();
+        for (ticker, quotes) in tables {
+            for (currency, price) in quotes {
+                let price = match price {
+                    toml::Value::String(price) => price,
+                    toml::Value::Integer(price) => price.to_string(),
+                    _ => {
+                        return Err(ApiError::PriceSourceError(format!(
+                            "Price of {} in {} must be a quoted decimal in {}",
+                            ticker, currency, path
+                        )))
+                    }
+                };
+                prices.insert(&ticker, &currency, &price)?;
+            }
+        }
+        Ok(prices)
+    }
+
+    fn insert(&mut self, ticker: &str, currency: &str, price: &str) -> Result<(), ApiError> {
+        let price = Amount::parse(price, PRICE_PRECISION)?;
+        self.quotes.insert((ticker.to_uppercase(), currency.to_uppercase()), price);
+        Ok(())
+    }
+}
+
+impl PriceSource for StaticPrices {
+    fn quote(&self, ticker: &str, currency: &str) -> Result<Amount, ApiError> {
+        self.quotes
+            .get(&(ticker.to_uppercase(), currency.to_uppercase()))
+            .copied()
+            .ok_or_else(|| ApiError::NotFound(format!("No {} quote for {}", currency, ticker)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::tests::TempFile;
+
+    #[test]
+    fn it_reads_quotes_from_a_file() {
+        let file = TempFile::new("prices.toml", "[BTC]\nUSD = \"64000.50\"\nkrw = 95000000\n\n[eth]\nUSD = \"3100\"\n");
+        let prices = StaticPrices::from_file(file.path()).unwrap();
+
+        assert_eq!(prices.quote("BTC", "USD").unwrap().to_string(), "64000.50000000");
+        assert_eq!(prices.quote("btc", "KRW").unwrap().units(), 95_000_000 * 100_000_000);
+        assert_eq!(prices.quote("ETH", "USD").unwrap().to_string(), "3100.00000000");
+        assert!(matches!(prices.quote("ETH", "EUR"), Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn it_rejects_inexact_prices() {
+        let file = TempFile::new("float-prices.toml", "[BTC]\nUSD = 64000.5\n");
+        assert!(StaticPrices::from_file(file.path()).is_err());
+        assert!(StaticPrices::new(&[("BTC", "USD", "-1")]).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Quotes from a price service over HTTP or HTTPS:
+//! `GET <price_url>/quote?ticker=BTC&currency=USD` answers `{"price": "64000.50"}`,
+//! or 404 for a pair it does not quote. Requests carry the `traceparent` of the
+//! current span.
+
+use std::io::Read;
+use std::time::Duration;
+
+use super::{PriceSource, PRICE_PRECISION};
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+use crate::telemetry::{current_traceparent, in_span, TRACEPARENT_HEADER};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest response read from the service
+const MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+
+pub struct HttpPrices {
+    agent: ureq::Agent,
+    /// URL the service is mounted at, without a trailing slash
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct QuoteResponse {
+    price: String,
+}
+
+impl HttpPrices {
+    /// `url` is http(s)://host[:port] with an optional path
+    pub fn new(url: &str) -> Result<Self, ApiError> {
+        let host = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .and_then(|rest| rest.split('/').next())
+            .unwrap_or_default();
+        if host.is_empty() {
+            return Err(ApiError::PriceSourceError(format!(
+                "Invalid price_url {}, expected http(s)://host[:port]",
+                url
+            )));
+        }
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(IO_TIMEOUT)
+            .timeout(IO_TIMEOUT)
+            .build();
+        Ok(HttpPrices {
+            agent,
+            base_url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Send a GET request for a quote, returning the status code and body
+    fn get(&self, ticker: &str, currency: &str) -> Result<(u16, String), ApiError> {
+        let mut request = self
+            .agent
+            .get(&format!("{}/quote", self.base_url))
+            .query("ticker", ticker)
+            .query("currency", currency)
+            .set("Accept", "application/json");
+        if let Some(traceparent) = current_traceparent() {
+            request = request.set(TRACEPARENT_HEADER, &traceparent);
+        }
+        // Statuses other than 2xx come back as errors, without a body worth reading
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => return Ok((status, String::new())),
+            Err(e) => return Err(ApiError::PriceSourceError(e.to_string())),
+        };
+
+        let mut body = String::new();
+        response
+            .into_reader()
+            .take(MAX_RESPONSE_BYTES)
+            .read_to_string(&mut body)
+            .map_err(|e| ApiError::PriceSourceError(e.to_string()))?;
+        Ok((200, body))
+    }
+}
+
+impl PriceSource for HttpPrices {
+    fn quote(&self, ticker: &str, currency: &str) -> Result<Amount, ApiError> {
+        let attributes = [("price.ticker", ticker), ("price.currency", currency)];
+        let (status, body) = in_span("price.quote", &attributes, || self.get(ticker, currency))?;
+        match status {
+            200 => {
+                let quote: QuoteResponse = serde_json::from_str(&body)
+                    .map_err(|e| ApiError::PriceSourceError(format!("Malformed quote {}: {}", body, e)))?;
+                Amount::parse(&quote.price, PRICE_PRECISION)
+                    .map_err(|_| ApiError::PriceSourceError(format!("Invalid price {:?} for {}", quote.price, ticker)))
+            }
+            404 => Err(ApiError::NotFound(format!("No {} quote for {}", currency, ticker))),
+            status => Err(ApiError::PriceSourceError(format!("Price service answered {}", status))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::{with_span, Span};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    /// Minimal stand-in for a price service, quoting BTC in USD.
+    /// Sends the head of every request it receives.
+    fn start_server() -> (String, Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    head.push(line.trim_end().to_string());
+                }
+                let response = match head[0].as_str() {
+                    "GET /prices/quote?ticker=BTC&currency=USD HTTP/1.1" => {
+                        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         9\r\n{\"price\":\r\nb\r\n \"64000.50\"\r\n1\r\n}\r\n0\r\n\r\n"
+                    }
+                    "GET /prices/quote?ticker=BAD&currency=USD HTTP/1.1" => {
+                        "HTTP/1.1 200 OK\r\nContent-Length: 15\r\n\r\n{\"price\": \"-1\"}"
+                    }
+                    "GET /prices/quote?ticker=DOWN&currency=USD HTTP/1.1" => {
+                        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n"
+                    }
+                    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+                };
+                sender.send(head).unwrap();
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (format!("http://{}/prices/", address), receiver)
+    }
+
+    #[test]
+    fn it_quotes_from_the_service() {
+        let (url, requests) = start_server();
+        let prices = HttpPrices::new(&url).unwrap();
+
+        let span = Span::start("test", None);
+        let price = with_span(&span, || prices.quote("BTC", "USD")).unwrap();
+        assert_eq!(price.to_string(), "64000.50000000");
+
+        let head = requests.recv().unwrap();
+        let traceparent = head
+            .iter()
+            .find_map(|line| line.strip_prefix("traceparent: "))
+            .expect("The trace was not passed on");
+        assert!(traceparent.contains(&span.context().trace_id));
+    }
+
+    #[test]
+    fn it_reports_missing_quotes_and_service_errors() {
+        let (url, _requests) = start_server();
+        let prices = HttpPrices::new(&url).unwrap();
+        assert!(matches!(prices.quote("ETH", "USD"), Err(ApiError::NotFound(_))));
+        assert!(matches!(prices.quote("DOWN", "USD"), Err(ApiError::PriceSourceError(_))));
+        assert!(matches!(prices.quote("BAD", "USD"), Err(ApiError::PriceSourceError(_))));
+
+        let closed = HttpPrices::new("http://127.0.0.1:1").unwrap();
+        assert!(matches!(closed.quote("BTC", "USD"), Err(ApiError::PriceSourceError(_))));
+    }
+
+    #[test]
+    fn it_parses_service_urls() {
+        assert!(HttpPrices::new("http://").is_err());
+        assert!(HttpPrices::new("ftp://prices.internal").is_err());
+        let prices = HttpPrices::new("https://prices.example.com/v1/").unwrap();
+        assert_eq!(prices.base_url, "https://prices.example.com/v1");
+        let prices = HttpPrices::new("http://[::1]:8090").unwrap();
+        assert_eq!(prices.base_url, "http://[::1]:8090");
+    }
+}
@@ -0,0 +1,175 @@
+//! Coin prices in fiat currencies, for valuing holdings.
+//!
+//! Quotes come from a `PriceSource`: a fixed table read from a file, or a price
+//! service over HTTP. `cached_quote` keeps them in the cache for
+//! `price_ttl_seconds`, so a busy portfolio page does not hammer the service.
+
+mod file;
+mod http;
+
+pub use self::file::StaticPrices;
+pub use self::http::HttpPrices;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::{get_or_load_for, Cache};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::models::amount::Amount;
+
+/// Decimal places of quotes, enough for coins worth a fraction of a cent
+pub const PRICE_PRECISION: u32 = 8;
+
+/// Currencies without minor units
+const WHOLE_CURRENCIES: [&str; 2] = ["JPY", "KRW"];
+
+pub trait PriceSource: Send + Sync {
+    /// Price of one `ticker` in `currency`, with `PRICE_PRECISION` decimals.
+    /// Pairs the source does not quote are `ApiError::NotFound`.
+    fn quote(&self, ticker: &str, currency: &str) -> Result<Amount, ApiError>;
+}
+
+/// Create the price source selected by `price_source`: "static" (default) or "http"
+pub fn from_config(config: &Config) -> Result<Box<dyn PriceSource>, ApiError> {
+    match config.price_source.as_str() {
+        "http" => Ok(Box::new(HttpPrices::new(&config.price_url)?)),
+        _ if config.price_file.is_empty() => Ok(Box::new(StaticPrices::default())),
+        _ => Ok(Box::new(StaticPrices::from_file(&config.price_file)?)),
+    }
+}
+
+/// Quote of `ticker` in `currency`, through the cache
+pub fn cached_quote(
+    source: &dyn PriceSource,
+    cache: &dyn Cache,
+    ttl: Duration,
+    ticker: &str,
+    currency: &str,
+) -> Result<Amount, ApiError> {
+    let key = format!("quote:{}:{}", ticker, currency);
+    let price: String = get_or_load_for(cache, &key, ttl, || source.quote(ticker, currency).map(|price| price.to_string()))?;
+    Amount::parse(&price, PRICE_PRECISION)
+}
+
+/// Quotes of several tickers in `currency` through the cache, asking the
+/// source for them concurrently so one slow quote does not queue the others
+pub fn cached_quotes(
+    source: &dyn PriceSource,
+    cache: &dyn Cache,
+    ttl: Duration,
+    tickers: &[String],
+    currency: &str,
+) -> HashMap<String, Result<Amount, ApiError>> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = tickers
+            .iter()
+            .map(|ticker| (ticker, scope.spawn(move || cached_quote(source, cache, ttl, ticker, currency))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(ticker, handle)| {
+                let quote = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(ApiError::PriceSourceError(format!("Quote of {} panicked", ticker))));
+                (ticker.clone(), quote)
+            })
+            .collect()
+    })
+}
+
+/// Uppercase a currency code, refusing anything but three letters
+pub fn currency(code: &str) -> Result<String, ApiError> {
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(ApiError::ValidationError(vec![format!(
+            "currency {:?} must be a three letter code such as USD",
+            code
+        )]));
+    }
+    Ok(code.to_uppercase())
+}
+
+/// Decimal places of amounts in `currency`
+pub fn currency_precision(currency: &str) -> u32 {
+    if WHOLE_CURRENCIES.contains(&currency) {
+        0
+    } else {
+        2
+    }
+}
+
+/// Value of `amount` at `price`, rounded half up to the decimals of `currency`
+pub fn value(amount: Amount, price: Amount, currency: &str) -> Result<Amount, ApiError> {
+    let precision = currency_precision(currency);
+    let scale = 10i128.pow(amount.precision() + price.precision() - precision);
+    let product = i128::from(amount.units()) * i128::from(price.units());
+    let units = (product + scale / 2) / scale;
+    let units = i64::try_from(units)
+        .map_err(|_| ApiError::InternalServerError(format!("Value of {} at {} is too large", amount, price)))?;
+    Ok(Amount::from_units(units, precision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCache;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn price(value: &str) -> Amount {
+        Amount::parse(value, PRICE_PRECISION).unwrap()
+    }
+
+    #[test]
+    fn it_values_amounts_in_the_currency_precision() {
+        let btc = Amount::parse("0.5", 8).unwrap();
+        assert_eq!(value(btc, price("64000.015"), "USD").unwrap().to_string(), "32000.01");
+        assert_eq!(value(btc, price("64000.03"), "USD").unwrap().to_string(), "32000.02");
+        assert_eq!(value(btc, price("95000000"), "KRW").unwrap().to_string(), "47500000");
+        let sats = Amount::from_units(1, 8);
+        assert_eq!(value(sats, price("64000"), "USD").unwrap().to_string(), "0.00");
+        assert!(value(Amount::from_units(i64::MAX, 0), price("2"), "USD").is_err());
+    }
+
+    #[test]
+    fn it_accepts_three_letter_currencies() {
+        assert_eq!(currency("eur").unwrap(), "EUR");
+        assert!(currency("EURO").is_err());
+        assert!(currency("U$D").is_err());
+    }
+
+    struct CountingSource(AtomicUsize);
+
+    impl PriceSource for CountingSource {
+        fn quote(&self, ticker: &str, _: &str) -> Result<Amount, ApiError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match ticker {
+                "BTC" => Ok(price("64000")),
+                _ => Err(ApiError::NotFound(format!("No quote for {}", ticker))),
+            }
+        }
+    }
+
+    #[test]
+    fn it_caches_quotes() {
+        let source = CountingSource(AtomicUsize::new(0));
+        let cache = MemoryCache::new(10, Duration::from_secs(60));
+        let ttl = Duration::from_secs(30);
+        for _ in 0..3 {
+            assert_eq!(cached_quote(&source, &cache, ttl, "BTC", "USD").unwrap(), price("64000"));
+        }
+        assert_eq!(source.0.load(Ordering::SeqCst), 1);
+
+        // Missing quotes are asked for again
+        assert!(cached_quote(&source, &cache, ttl, "DOGE", "USD").is_err());
+        assert!(cached_quote(&source, &cache, ttl, "DOGE", "USD").is_err());
+        assert_eq!(source.0.load(Ordering::SeqCst), 3);
+
+        let tickers = vec!["BTC".to_string(), "DOGE".to_string()];
+        let quotes = cached_quotes(&source, &cache, ttl, &tickers, "USD");
+        assert_eq!(quotes["BTC"].as_ref().unwrap(), &price("64000"));
+        assert!(matches!(quotes["DOGE"], Err(ApiError::NotFound(_))));
+        assert_eq!(source.0.load(Ordering::SeqCst), 4);
+    }
+}
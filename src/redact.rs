@@ -0,0 +1,145 @@
+//! Masking of sensitive values before request data or documents are logged.
+
+use actix_web::http::HeaderMap;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Key fragments marking a field or header as sensitive
+const SENSITIVE_KEYS: [&str; 7] = [
+    "password",
+    "token",
+    "secret",
+    "authorization",
+    "cookie",
+    "recovery_code",
+    "jwt",
+];
+
+/// Whether a field or header name holds a credential
+pub fn is_sensitive(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SENSITIVE_KEYS.iter().any(|sensitive| key.contains(sensitive))
+}
+
+/// Copy of a BSON document with sensitive values masked, recursively
+pub fn redact_document(doc: &bson::Document) -> bson::Document {
+    doc.iter()
+        .map(|(key, value)| {
+            let value = if is_sensitive(key) {
+                bson::Bson::String(REDACTED.into())
+            } else {
+                redact_bson(value)
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+fn redact_bson(value: &bson::Bson) -> bson::Bson {
+    match value {
+        bson::Bson::Document(doc) => bson::Bson::Document(redact_document(doc)),
+        bson::Bson::Array(values) => bson::Bson::Array(values.iter().map(redact_bson).collect()),
+        value => value.clone(),
+    }
+}
+
+/// JSON of a request body with sensitive values masked
+pub fn redact_body<T: Serialize>(body: &T) -> Value {
+    serde_json::to_value(body)
+        .map(|value| redact_json(&value))
+        .unwrap_or(Value::Null)
+}
+
+/// Copy of a JSON value with sensitive values masked, recursively
+fn redact_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let value = if is_sensitive(key) {
+                        Value::String(REDACTED.into())
+                    } else {
+                        redact_json(value)
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact_json).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Header names and values with credentials (Authorization, Cookie, ...) masked
+pub fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                value.to_str().unwrap_or("<binary>").to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, HeaderValue};
+    use crate::handlers::auth::LoginRequest;
+    use crate::tests::helpers::tests::capture_logs;
+    use serde_json::json;
+
+    #[test]
+    fn it_redacts_sensitive_document_fields() {
+        let doc = doc! {
+            "uid": "bsjung",
+            "password": "5e884898da28",
+            "totp": { "totp_secret": "GEZDGNBV", "enabled": true },
+        };
+        let redacted = redact_document(&doc);
+        assert_eq!(redacted.get_str("uid").unwrap(), "bsjung");
+        assert_eq!(redacted.get_str("password").unwrap(), REDACTED);
+        let totp = redacted.get_document("totp").unwrap();
+        assert_eq!(totp.get_str("totp_secret").unwrap(), REDACTED);
+        assert!(totp.get_bool("enabled").unwrap());
+    }
+
+    #[test]
+    fn it_redacts_sensitive_json_fields() {
+        let body = json!({ "uid": "bsjung", "current_password": "hunter22", "token": "eyJ" });
+        let redacted = redact_json(&body);
+        assert_eq!(redacted["uid"], "bsjung");
+        assert_eq!(redacted["current_password"], REDACTED);
+        assert_eq!(redacted["token"], REDACTED);
+    }
+
+    #[test]
+    fn it_keeps_secrets_out_of_logged_request_bodies() {
+        let params = LoginRequest {
+            uid: "bsjung".into(),
+            password: "hunter22".into(),
+        };
+        let logs = capture_logs(|| debug!("login {}", redact_body(&params)));
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("bsjung"));
+        assert!(!logs[0].contains("hunter22"));
+    }
+
+    #[test]
+    fn it_redacts_credential_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer eyJ"));
+        headers.insert(header::COOKIE, HeaderValue::from_static("auth=eyJ"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let redacted = redact_headers(&headers);
+        assert!(redacted.contains(&("authorization".into(), REDACTED.into())));
+        assert!(redacted.contains(&("cookie".into(), REDACTED.into())));
+        assert!(redacted.contains(&("accept".into(), "application/json".into())));
+    }
+}
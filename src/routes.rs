@@ -1,15 +1,22 @@
 
 use crate::handlers::{
-    auth::{login, logout},
-    health::get_health,
-    user::{create_user, delete_user, get_user, get_users, update_user},
+    asset::{create_asset, delete_asset, get_asset, get_assets, patch_asset},
+    auth::{change_password, login, logout},
+    coin::{create_coin, deposit_coin, get_coins, get_transactions, transfer_coin, withdraw_coin},
+    health::{get_health, get_readiness},
+    job::{get_job_runs, get_jobs},
+    metrics::get_metrics,
+    mfa::{confirm_totp, enroll_totp, login_mfa},
+    portfolio::{get_portfolio, get_portfolio_history},
+    user::{create_user, delete_user, get_user, get_users, patch_user, update_user},
 };
 use crate::middleware::auth::Auth as AuthMiddleware;
+use crate::redact::redact_headers;
 use actix_web::{web, HttpRequest};
 
 
 async fn get_hello(req: HttpRequest) -> &'static str {
-    println!("REQ: {:?}", req);
+    debug!("REQ: {} {} {:?}", req.method(), req.path(), redact_headers(req.headers()));
     "Hello world!"
 }
 
@@ -20,6 +27,10 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
         // Healthcheck
         .route("/", web::get().to(get_health))
         .route("/health", web::get().to(get_health))
+        .route("/health/live", web::get().to(get_health))
+        .route("/health/ready", web::get().to(get_readiness))
+        // Prometheus metrics
+        .route("/metrics", web::get().to(get_metrics))
         // /api/v1 routes
         .service(
             web::scope("/api/v1")
@@ -29,16 +40,77 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                 .service(
                     web::scope("/auth")
                         .route("/login", web::post().to(login))
-                        .route("/logout", web::get().to(logout)),
+                        .route("/login/mfa", web::post().to(login_mfa))
+                        .route("/logout", web::get().to(logout))
+                        .route("/change-password", web::post().to(change_password))
+                        .route("/2fa/enroll", web::post().to(enroll_totp))
+                        .route("/2fa/confirm", web::post().to(confirm_totp)),
                 )
                 // USER routes
                 .service(
                     web::scope("/user")
                         .route("/{id}", web::get().to(get_user))
                         .route("/{id}", web::put().to(update_user))
+                        .route("/{id}", web::patch().to(patch_user))
                         .route("/{id}", web::delete().to(delete_user))
                         .route("", web::get().to(get_users))
                         .route("", web::post().to(create_user))
+                )
+                // ASSET routes
+                .service(
+                    web::scope("/asset")
+                        .route("", web::get().to(get_assets))
+                        .route("", web::post().to(create_asset))
+                        .route("/{ticker}", web::get().to(get_asset))
+                        .route("/{ticker}", web::patch().to(patch_asset))
+                        .route("/{ticker}", web::delete().to(delete_asset))
+                )
+                // COIN routes
+                .service(
+                    web::scope("/coin")
+                        .route("", web::get().to(get_coins))
+                        .route("", web::post().to(create_coin))
+                        .route("/{ticker}/deposit", web::post().to(deposit_coin))
+                        .route("/{ticker}/withdraw", web::post().to(withdraw_coin))
+                        .route("/{ticker}/transactions", web::get().to(get_transactions))
+                        .route("/{ticker}/transfer", web::post().to(transfer_coin))
+                )
+                // PORTFOLIO routes
+                .service(
+                    web::scope("/portfolio")
+                        .route("", web::get().to(get_portfolio))
+                        .route("/history", web::get().to(get_portfolio_history))
+                )
+                // JOB routes
+                .service(
+                    web::scope("/job")
+                        .route("", web::get().to(get_jobs))
+                        .route("/{name}/runs", web::get().to(get_job_runs))
                 ),
         );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::tests::{start_log_capture, take_captured_logs};
+    use actix_web::test;
+
+    #[actix_rt::test]
+    async fn it_does_not_log_credential_headers() {
+        let req = test::TestRequest::get()
+            .uri("/hello")
+            .header("authorization", "Bearer secret-jwt")
+            .header("cookie", "auth=secret-cookie")
+            .to_http_request();
+
+        start_log_capture();
+        get_hello(req).await;
+        let logs = take_captured_logs();
+
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("/hello"));
+        assert!(!logs[0].contains("secret-jwt"));
+        assert!(!logs[0].contains("secret-cookie"));
+    }
+}
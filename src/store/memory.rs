@@ -0,0 +1,318 @@
+//! Store keeping collections in memory, for hermetic tests.
+//! Unique indexes are enforced; TTL indexes are recorded but never expire documents.
+
+use bson::oid::ObjectId;
+use bson::{Bson, Document};
+use mongodb::results::UpdateResult;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::query::{apply_update, lookup, matches, sort};
+use super::{FindOptions, Store};
+use crate::database::IndexSpec;
+use crate::errors::ApiError;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    collections: Mutex<HashMap<String, Collection>>,
+}
+
+#[derive(Default)]
+struct Collection {
+    documents: Vec<Document>,
+    /// Field names of each unique index
+    unique: Vec<Vec<String>>,
+    /// Writes fail as if the server were unreachable, see `fail_writes_to`
+    failing: bool,
+}
+
+impl Collection {
+    /// Refuse a document sharing the keys of a unique index with another one.
+    /// `position` is where the candidate is stored, if it already is.
+    fn check_unique(&self, candidate: &Document, position: Option<usize>) -> Result<(), ApiError> {
+        let id_index = vec!["_id".to_string()];
+        for fields in self.unique.iter().chain(std::iter::once(&id_index)) {
+            let key = index_key(candidate, fields);
+            let duplicate = self
+                .documents
+                .iter()
+                .enumerate()
+                .any(|(index, document)| Some(index) != position && index_key(document, fields) == key);
+            if duplicate {
+                return Err(ApiError::Conflict("A record with the same unique key already exists".into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn positions(&self, filter: &Document) -> Result<Vec<usize>, ApiError> {
+        let mut positions = Vec::new();
+        for (index, document) in self.documents.iter().enumerate() {
+            if matches(document, filter)? {
+                positions.push(index);
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Update the documents at `positions`, returning how many changed
+    fn update(&mut self, positions: &[usize], update: &Document) -> Result<i64, ApiError> {
+        let mut modified = 0;
+        for &position in positions {
+            let mut document = self.documents[position].clone();
+            apply_update(&mut document, update)?;
+            if document != self.documents[position] {
+                self.check_unique(&document, Some(position))?;
+                self.documents[position] = document;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+}
+
+fn index_key(document: &Document, fields: &[String]) -> Vec<Bson> {
+    fields
+        .iter()
+        .map(|field| lookup(document, field).cloned().unwrap_or(Bson::Null))
+        .collect()
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make every later write to `collection` fail, to exercise error paths
+    pub fn fail_writes_to(&self, collection: &str) {
+        self.with_collection(collection, |collection| {
+            collection.failing = true;
+            Ok(())
+        })
+        .expect("Could not mark the collection failing");
+    }
+
+    fn with_collection<T>(&self, name: &str, f: impl FnOnce(&mut Collection) -> Result<T, ApiError>) -> Result<T, ApiError> {
+        let mut collections = self.collections.lock().expect("Could not acquire lock");
+        f(collections.entry(name.to_string()).or_default())
+    }
+
+    fn with_writable<T>(&self, name: &str, f: impl FnOnce(&mut Collection) -> Result<T, ApiError>) -> Result<T, ApiError> {
+        self.with_collection(name, |collection| {
+            if collection.failing {
+                return Err(ApiError::InternalServerError(format!("Writes to {} are failing", name)));
+            }
+            f(collection)
+        })
+    }
+}
+
+impl Store for MemoryStore {
+    fn find(&self, collection: &str, filter: Document, options: FindOptions) -> Result<Vec<Document>, ApiError> {
+        self.with_collection(collection, |collection| {
+            let mut found: Vec<Document> = collection
+                .positions(&filter)?
+                .into_iter()
+                .map(|position| collection.documents[position].clone())
+                .collect();
+            if let Some(ref specification) = options.sort {
+                sort(&mut found, specification);
+            }
+            let skip = options.skip.unwrap_or(0).max(0) as usize;
+            let limit = options.limit.filter(|limit| *limit > 0).map_or(usize::MAX, |limit| limit as usize);
+            Ok(found.into_iter().skip(skip).take(limit).collect())
+        })
+    }
+
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, ApiError> {
+        let options = FindOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        Ok(self.find(collection, filter, options)?.into_iter().next())
+    }
+
+    fn insert_one(&self, collection: &str, mut document: Document) -> Result<Bson, ApiError> {
+        if !document.contains_key("_id") {
+            let id = ObjectId::new().map_err(|e| ApiError::InternalServerError(e.to_string()))?;
+            document.insert("_id", id);
+        }
+        let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+        self.with_writable(collection, |collection| {
+            collection.check_unique(&document, None)?;
+            collection.documents.push(document);
+            Ok(id)
+        })
+    }
+
+    fn update_one(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError> {
+        self.with_writable(collection, |collection| {
+            let positions: Vec<usize> = collection.positions(&filter)?.into_iter().take(1).collect();
+            let modified_count = collection.update(&positions, &update)?;
+            Ok(UpdateResult {
+                matched_count: positions.len() as i64,
+                modified_count,
+                upserted_id: None,
+            })
+        })
+    }
+
+    fn update_many(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError> {
+        self.with_writable(collection, |collection| {
+            let positions = collection.positions(&filter)?;
+            let modified_count = collection.update(&positions, &update)?;
+            Ok(UpdateResult {
+                matched_count: positions.len() as i64,
+                modified_count,
+                upserted_id: None,
+            })
+        })
+    }
+
+    fn find_one_and_update(&self, collection: &str, filter: Document, update: Document) -> Result<Option<Document>, ApiError> {
+        self.with_writable(collection, |collection| {
+            let position = match collection.positions(&filter)?.first() {
+                Some(position) => *position,
+                None => return Ok(None),
+            };
+            let before = collection.documents[position].clone();
+            collection.update(&[position], &update)?;
+            Ok(Some(before))
+        })
+    }
+
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i64, ApiError> {
+        self.with_writable(collection, |collection| match collection.positions(&filter)?.first() {
+            Some(position) => {
+                collection.documents.remove(*position);
+                Ok(1)
+            }
+            None => Ok(0),
+        })
+    }
+
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i64, ApiError> {
+        self.with_writable(collection, |collection| {
+            let positions = collection.positions(&filter)?;
+            for position in positions.iter().rev() {
+                collection.documents.remove(*position);
+            }
+            Ok(positions.len() as i64)
+        })
+    }
+
+    fn create_indexes(&self, collection: &str, indexes: &[IndexSpec]) -> Result<(), ApiError> {
+        self.with_collection(collection, |collection| {
+            for index in indexes.iter().filter(|index| index.unique) {
+                let fields: Vec<String> = index.keys.keys().cloned().collect();
+                if collection.unique.contains(&fields) {
+                    continue;
+                }
+                // Like MongoDB, refuse a unique index the stored documents already break
+                let keys: Vec<Vec<Bson>> = collection.documents.iter().map(|document| index_key(document, &fields)).collect();
+                if keys.iter().enumerate().any(|(position, key)| keys[..position].contains(key)) {
+                    return Err(ApiError::Conflict("A record with the same unique key already exists".into()));
+                }
+                collection.unique.push(fields);
+            }
+            Ok(())
+        })
+    }
+
+    fn ping(&self) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::indexes;
+
+    fn store() -> MemoryStore {
+        let store = MemoryStore::new();
+        let users: Vec<IndexSpec> = indexes().into_iter().filter(|index| index.collection == "users").collect();
+        store.create_indexes("users", &users).unwrap();
+        store
+    }
+
+    #[test]
+    fn it_inserts_and_finds_documents() {
+        let store = store();
+        let id = store.insert_one("users", doc! { "uid": "bsjung", "email": "a@example.com" }).unwrap();
+        assert!(matches!(id, Bson::ObjectId(_)));
+
+        let found = store.find_one("users", doc! { "uid": "bsjung" }).unwrap().unwrap();
+        assert_eq!(found.get("_id"), Some(&id));
+        assert!(store.find_one("users", doc! { "uid": "nobody" }).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_enforces_unique_indexes() {
+        let store = store();
+        store.insert_one("users", doc! { "uid": "first", "email": "a@example.com" }).unwrap();
+        store.insert_one("users", doc! { "uid": "second", "email": "b@example.com" }).unwrap();
+
+        let duplicate = store.insert_one("users", doc! { "uid": "first", "email": "c@example.com" });
+        assert!(matches!(duplicate, Err(ApiError::Conflict(_))));
+
+        let update = doc! { "$set": { "email": "a@example.com" } };
+        let duplicate = store.update_one("users", doc! { "uid": "second" }, update);
+        assert!(matches!(duplicate, Err(ApiError::Conflict(_))));
+        assert_eq!(store.find_one("users", doc! { "uid": "second" }).unwrap().unwrap().get_str("email").unwrap(), "b@example.com");
+    }
+
+    #[test]
+    fn it_refuses_a_unique_index_over_duplicates() {
+        let store = MemoryStore::new();
+        store.insert_one("users", doc! { "uid": "first", "email": "a@example.com" }).unwrap();
+        store.insert_one("users", doc! { "uid": "second", "email": "a@example.com" }).unwrap();
+
+        let users: Vec<IndexSpec> = indexes().into_iter().filter(|index| index.collection == "users").collect();
+        assert!(matches!(store.create_indexes("users", &users), Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn it_reports_matched_and_modified_counts() {
+        let store = store();
+        store.insert_one("coins", doc! { "uid": "bsjung", "ticker": "BTC" }).unwrap();
+        store.insert_one("coins", doc! { "uid": "bsjung", "ticker": "ETH" }).unwrap();
+
+        let result = store.update_many("coins", doc! { "uid": "bsjung" }, doc! { "$set": { "ticker": "BTC" } }).unwrap();
+        assert_eq!((result.matched_count, result.modified_count), (2, 1));
+
+        assert_eq!(store.delete_many("coins", doc! { "ticker": "BTC" }).unwrap(), 2);
+        assert_eq!(store.delete_one("coins", doc! {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn it_sorts_and_pages_results() {
+        let store = store();
+        for version in &[3i64, 1, 2] {
+            store.insert_one("migrations", doc! { "version": *version }).unwrap();
+        }
+        let options = FindOptions {
+            sort: Some(doc! { "version": 1 }),
+            skip: Some(1),
+            limit: Some(1),
+        };
+        let found = store.find("migrations", doc! {}, options).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get_i64("version").unwrap(), 2);
+    }
+
+    #[test]
+    fn it_returns_the_document_before_find_one_and_update() {
+        let store = store();
+        store.insert_one("locks", doc! { "_id": "migrations", "owner": "a" }).unwrap();
+        assert!(matches!(store.insert_one("locks", doc! { "_id": "migrations" }), Err(ApiError::Conflict(_))));
+
+        let before = store
+            .find_one_and_update("locks", doc! { "_id": "migrations" }, doc! { "$set": { "owner": "b" } })
+            .unwrap()
+            .unwrap();
+        assert_eq!(before.get_str("owner").unwrap(), "a");
+        let after = store.find_one("locks", doc! { "_id": "migrations" }).unwrap().unwrap();
+        assert_eq!(after.get_str("owner").unwrap(), "b");
+    }
+}
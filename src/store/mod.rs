@@ -0,0 +1,53 @@
+//! Data access behind a trait, so tests can swap MongoDB for an in-memory store.
+//!
+//! `MongoStore` talks to the server through the driver; `MemoryStore` evaluates
+//! the filter and update operators the application uses against documents in a
+//! map, keeping the test suite hermetic.
+
+#[cfg(test)]
+mod memory;
+mod mongo;
+#[cfg(test)]
+mod query;
+
+#[cfg(test)]
+pub use self::memory::MemoryStore;
+pub use self::mongo::MongoStore;
+
+use bson::{Bson, Document};
+use mongodb::results::UpdateResult;
+
+use crate::database::IndexSpec;
+use crate::errors::ApiError;
+
+/// Sorting and paging of `Store::find`
+#[derive(Clone, Debug, Default)]
+pub struct FindOptions {
+    pub sort: Option<Document>,
+    pub skip: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Operations on named collections of documents.
+/// Unique index violations are reported as `ApiError::Conflict`.
+pub trait Store: Send + Sync {
+    fn find(&self, collection: &str, filter: Document, options: FindOptions) -> Result<Vec<Document>, ApiError>;
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, ApiError>;
+
+    /// Insert a document, returning its `_id`
+    fn insert_one(&self, collection: &str, document: Document) -> Result<Bson, ApiError>;
+    fn update_one(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError>;
+    fn update_many(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError>;
+
+    /// Update the first matching document, returning it as it was before the update
+    fn find_one_and_update(&self, collection: &str, filter: Document, update: Document) -> Result<Option<Document>, ApiError>;
+
+    /// Delete the first matching document, returning the number deleted
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i64, ApiError>;
+
+    /// Delete every matching document, returning the number deleted
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i64, ApiError>;
+
+    fn create_indexes(&self, collection: &str, indexes: &[IndexSpec]) -> Result<(), ApiError>;
+    fn ping(&self) -> Result<(), ApiError>;
+}
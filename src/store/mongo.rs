@@ -0,0 +1,163 @@
+//! Store backed by MongoDB. Every operation is timed and traced.
+
+use bson::{Bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::results::UpdateResult;
+use mongodb::Database;
+
+use super::{FindOptions, Store};
+use crate::database::IndexSpec;
+use crate::errors::ApiError;
+use crate::metrics::time_db;
+use crate::telemetry::set_attribute;
+
+/// Server error code of a unique index violation
+const DUPLICATE_KEY: i32 = 11000;
+
+pub struct MongoStore {
+    db: Database,
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> Self {
+        MongoStore { db }
+    }
+}
+
+impl Store for MongoStore {
+    fn find(&self, collection: &str, filter: Document, options: FindOptions) -> Result<Vec<Document>, ApiError> {
+        let options = mongodb::options::FindOptions {
+            sort: options.sort,
+            skip: options.skip,
+            limit: options.limit,
+            ..Default::default()
+        };
+        let coll = self.db.collection(collection);
+        time_db(collection, "find", || {
+            coll.find(filter, options)?.collect::<mongodb::error::Result<Vec<Document>>>()
+        })
+        .map_err(db_error)
+    }
+
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "find_one", || coll.find_one(filter, None)).map_err(db_error)
+    }
+
+    fn insert_one(&self, collection: &str, document: Document) -> Result<Bson, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "insert_one", || coll.insert_one(document, None))
+            .map(|result| result.inserted_id)
+            .map_err(db_error)
+    }
+
+    fn update_one(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "update_one", || {
+            let result = coll.update_one(filter, update, None);
+            if let Ok(ref result) = result {
+                set_attribute("db.matched_count", result.matched_count);
+            }
+            result
+        })
+        .map_err(db_error)
+    }
+
+    fn update_many(&self, collection: &str, filter: Document, update: Document) -> Result<UpdateResult, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "update_many", || {
+            let result = coll.update_many(filter, update, None);
+            if let Ok(ref result) = result {
+                set_attribute("db.matched_count", result.matched_count);
+            }
+            result
+        })
+        .map_err(db_error)
+    }
+
+    fn find_one_and_update(&self, collection: &str, filter: Document, update: Document) -> Result<Option<Document>, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "find_one_and_update", || coll.find_one_and_update(filter, update, None))
+            .map_err(db_error)
+    }
+
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i64, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "delete_one", || coll.delete_one(filter, None))
+            .map(|result| result.deleted_count)
+            .map_err(db_error)
+    }
+
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i64, ApiError> {
+        let coll = self.db.collection(collection);
+        time_db(collection, "delete_many", || {
+            let result = coll.delete_many(filter, None);
+            if let Ok(ref result) = result {
+                set_attribute("db.deleted_count", result.deleted_count);
+            }
+            result
+        })
+        .map(|result| result.deleted_count)
+        .map_err(db_error)
+    }
+
+    /// createIndexes is a no-op for indexes that already exist with the same definition
+    fn create_indexes(&self, collection: &str, indexes: &[IndexSpec]) -> Result<(), ApiError> {
+        let documents: Vec<Bson> = indexes
+            .iter()
+            .map(|index| Bson::Document(index.to_document()))
+            .collect();
+        let command = doc! { "createIndexes": collection, "indexes": documents };
+        time_db(collection, "create_indexes", || self.db.run_command(command, None)).map_err(db_error)?;
+        Ok(())
+    }
+
+    fn ping(&self) -> Result<(), ApiError> {
+        time_db("$cmd", "ping", || self.db.run_command(doc! { "ping": 1 }, None)).map_err(db_error)?;
+        Ok(())
+    }
+}
+
+/// Convert a driver error, surfacing unique index violations as conflicts
+fn db_error(error: mongodb::error::Error) -> ApiError {
+    let duplicate = match *error.kind {
+        ErrorKind::WriteError(WriteFailure::WriteError(ref write_error)) => {
+            write_error.code == DUPLICATE_KEY
+        }
+        ErrorKind::CommandError(ref command_error) => command_error.code == DUPLICATE_KEY,
+        _ => false,
+    };
+
+    if duplicate {
+        ApiError::Conflict("A record with the same unique key already exists".into())
+    } else {
+        ApiError::DBError(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::error::WriteError;
+
+    fn write_error(code: i32) -> mongodb::error::Error {
+        ErrorKind::WriteError(WriteFailure::WriteError(WriteError {
+            code,
+            code_name: None,
+            message: "E11000 duplicate key error".into(),
+        }))
+        .into()
+    }
+
+    #[test]
+    fn it_maps_a_duplicate_key_to_a_conflict() {
+        let error = db_error(write_error(DUPLICATE_KEY));
+        assert!(matches!(error, ApiError::Conflict(_)));
+    }
+
+    #[test]
+    fn it_keeps_other_write_errors() {
+        let error = db_error(write_error(121));
+        assert!(matches!(error, ApiError::DBError(_)));
+    }
+}
@@ -0,0 +1,364 @@
+//! Evaluation of MongoDB filter and update documents for the in-memory store.
+//!
+//! Supported filter operators: $eq $ne $gt $gte $lt $lte $in $nin $exists $and $or.
+//! Supported update operators: $set $unset $inc $push $pull.
+//! Field names may be dotted paths into embedded documents.
+
+use bson::{Bson, Document};
+use std::cmp::Ordering;
+
+use crate::errors::ApiError;
+
+/// Whether a document matches a filter
+pub fn matches(document: &Document, filter: &Document) -> Result<bool, ApiError> {
+    for (key, condition) in filter.iter() {
+        let matched = match key.as_str() {
+            "$and" => clauses(condition)?
+                .iter()
+                .map(|clause| matches(document, clause))
+                .collect::<Result<Vec<bool>, ApiError>>()?
+                .into_iter()
+                .all(|matched| matched),
+            "$or" => clauses(condition)?
+                .iter()
+                .map(|clause| matches(document, clause))
+                .collect::<Result<Vec<bool>, ApiError>>()?
+                .into_iter()
+                .any(|matched| matched),
+            _ => matches_field(lookup(document, key), condition)?,
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Apply the update operators to a document
+pub fn apply_update(document: &mut Document, update: &Document) -> Result<(), ApiError> {
+    for (operator, fields) in update.iter() {
+        let fields = match fields {
+            Bson::Document(fields) => fields,
+            _ => return Err(unsupported(&format!("{} with a non-document operand", operator))),
+        };
+        for (path, operand) in fields.iter() {
+            match operator.as_str() {
+                "$set" => set_path(document, path, operand.clone()),
+                "$unset" => remove_path(document, path),
+                "$inc" => {
+                    let current = lookup(document, path).cloned().unwrap_or(Bson::I32(0));
+                    set_path(document, path, add(&current, operand)?);
+                }
+                "$push" => match lookup(document, path).cloned() {
+                    Some(Bson::Array(mut items)) => {
+                        items.push(operand.clone());
+                        set_path(document, path, Bson::Array(items));
+                    }
+                    None => set_path(document, path, Bson::Array(vec![operand.clone()])),
+                    Some(_) => return Err(unsupported(&format!("$push to non-array field {}", path))),
+                },
+                "$pull" => {
+                    if let Some(Bson::Array(items)) = lookup(document, path).cloned() {
+                        let mut kept = Vec::new();
+                        for item in items {
+                            if !matches_field(Some(&item), operand)? {
+                                kept.push(item);
+                            }
+                        }
+                        set_path(document, path, Bson::Array(kept));
+                    }
+                }
+                _ => return Err(unsupported(operator)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sort documents by a sort specification such as `{ "version": 1, "name": -1 }`
+pub fn sort(documents: &mut [Document], specification: &Document) {
+    documents.sort_by(|a, b| {
+        specification
+            .iter()
+            .map(|(path, direction)| {
+                let order = sort_order(lookup(a, path), lookup(b, path));
+                if integer(direction).unwrap_or(1) < 0 {
+                    order.reverse()
+                } else {
+                    order
+                }
+            })
+            .find(|order| *order != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// The value at a dotted path, if present
+pub fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    match path.split_once('.') {
+        Some((field, rest)) => match document.get(field) {
+            Some(Bson::Document(embedded)) => lookup(embedded, rest),
+            _ => None,
+        },
+        None => document.get(path),
+    }
+}
+
+/// Order two values, integers and floats of any width compare by value
+pub fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (integer(a), integer(b)) {
+        return Some(a.cmp(&b));
+    }
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::UtcDatetime(a), Bson::UtcDatetime(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.bytes().cmp(&b.bytes())),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn matches_field(value: Option<&Bson>, condition: &Bson) -> Result<bool, ApiError> {
+    let operators = match condition {
+        Bson::Document(operators) if operators.keys().any(|key| key.starts_with('$')) => operators,
+        _ => return Ok(equals(value, condition)),
+    };
+
+    for (operator, operand) in operators.iter() {
+        let matched = match operator.as_str() {
+            "$eq" => equals(value, operand),
+            "$ne" => !equals(value, operand),
+            "$gt" => compares(value, operand, |order| order == Ordering::Greater),
+            "$gte" => compares(value, operand, |order| order != Ordering::Less),
+            "$lt" => compares(value, operand, |order| order == Ordering::Less),
+            "$lte" => compares(value, operand, |order| order != Ordering::Greater),
+            "$in" => array(operator, operand)?.iter().any(|item| equals(value, item)),
+            "$nin" => !array(operator, operand)?.iter().any(|item| equals(value, item)),
+            "$exists" => value.is_some() == (operand != &Bson::Boolean(false) && integer(operand) != Some(0)),
+            _ => return Err(unsupported(operator)),
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Equality as MongoDB sees it: a missing field equals null,
+/// and an array field matches any of its elements
+fn equals(value: Option<&Bson>, expected: &Bson) -> bool {
+    match value {
+        None => *expected == Bson::Null,
+        Some(Bson::Array(items)) if !matches!(expected, Bson::Array(_)) => items
+            .iter()
+            .any(|item| compare(item, expected) == Some(Ordering::Equal)),
+        Some(value) => compare(value, expected) == Some(Ordering::Equal),
+    }
+}
+
+fn compares(value: Option<&Bson>, operand: &Bson, accept: impl Fn(Ordering) -> bool) -> bool {
+    match value {
+        Some(Bson::Array(items)) => items
+            .iter()
+            .any(|item| compare(item, operand).is_some_and(&accept)),
+        Some(value) => compare(value, operand).is_some_and(accept),
+        None => false,
+    }
+}
+
+/// Order of values in a sort, missing and null values first
+fn sort_order(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    let a = a.unwrap_or(&Bson::Null);
+    let b = b.unwrap_or(&Bson::Null);
+    compare(a, b).unwrap_or_else(|| type_rank(a).cmp(&type_rank(b)))
+}
+
+fn type_rank(value: &Bson) -> u8 {
+    match value {
+        Bson::Null => 0,
+        Bson::I32(_) | Bson::I64(_) | Bson::FloatingPoint(_) => 1,
+        Bson::String(_) => 2,
+        Bson::Document(_) => 3,
+        Bson::Array(_) => 4,
+        Bson::ObjectId(_) => 5,
+        Bson::Boolean(_) => 6,
+        Bson::UtcDatetime(_) => 7,
+        _ => 8,
+    }
+}
+
+fn add(current: &Bson, increment: &Bson) -> Result<Bson, ApiError> {
+    match (current, increment) {
+        (Bson::I32(a), Bson::I32(b)) => Ok(a
+            .checked_add(*b)
+            .map(Bson::I32)
+            .unwrap_or(Bson::I64(i64::from(*a) + i64::from(*b)))),
+        _ => match (integer(current), integer(increment)) {
+            (Some(a), Some(b)) => a
+                .checked_add(b)
+                .map(Bson::I64)
+                .ok_or_else(|| ApiError::BadRequest("$inc overflows a 64-bit integer".into())),
+            _ => match (number(current), number(increment)) {
+                (Some(a), Some(b)) => Ok(Bson::FloatingPoint(a + b)),
+                _ => Err(ApiError::BadRequest("$inc applies to numbers only".into())),
+            },
+        },
+    }
+}
+
+fn integer(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::I32(value) => Some(i64::from(*value)),
+        Bson::I64(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::FloatingPoint(value) => Some(*value),
+        _ => integer(value).map(|value| value as f64),
+    }
+}
+
+fn set_path(document: &mut Document, path: &str, value: Bson) {
+    match path.split_once('.') {
+        Some((field, rest)) => {
+            if !matches!(document.get(field), Some(Bson::Document(_))) {
+                document.insert(field, Document::new());
+            }
+            if let Some(Bson::Document(embedded)) = document.get_mut(field) {
+                set_path(embedded, rest, value);
+            }
+        }
+        // Replace in place, as MongoDB keeps the position of existing fields
+        None => match document.get_mut(path) {
+            Some(existing) => *existing = value,
+            None => {
+                document.insert(path, value);
+            }
+        },
+    }
+}
+
+fn remove_path(document: &mut Document, path: &str) {
+    match path.split_once('.') {
+        Some((field, rest)) => {
+            if let Some(Bson::Document(embedded)) = document.get_mut(field) {
+                remove_path(embedded, rest);
+            }
+        }
+        None => {
+            document.remove(path);
+        }
+    }
+}
+
+fn clauses(condition: &Bson) -> Result<Vec<&Document>, ApiError> {
+    match condition {
+        Bson::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Bson::Document(clause) => Ok(clause),
+                _ => Err(unsupported("$and/$or clause that is not a document")),
+            })
+            .collect(),
+        _ => Err(unsupported("$and/$or without an array")),
+    }
+}
+
+fn array<'a>(operator: &str, operand: &'a Bson) -> Result<&'a Vec<Bson>, ApiError> {
+    match operand {
+        Bson::Array(items) => Ok(items),
+        _ => Err(unsupported(&format!("{} without an array", operator))),
+    }
+}
+
+fn unsupported(what: &str) -> ApiError {
+    ApiError::InternalServerError(format!("Unsupported query in the memory store: {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn user() -> Document {
+        doc! {
+            "uid": "bsjung",
+            "version": 3i64,
+            "roles": ["admin", "user"],
+            "profile": { "country": "KR" },
+        }
+    }
+
+    #[test]
+    fn it_matches_equality_and_array_elements() {
+        assert!(matches(&user(), &doc! { "uid": "bsjung", "roles": "user" }).unwrap());
+        assert!(!matches(&user(), &doc! { "roles": "guest" }).unwrap());
+        assert!(matches(&user(), &doc! { "missing": Bson::Null }).unwrap());
+        assert!(matches(&user(), &doc! { "profile.country": "KR" }).unwrap());
+    }
+
+    #[test]
+    fn it_matches_comparison_operators() {
+        assert!(matches(&user(), &doc! { "version": { "$gte": 3, "$lt": 4 } }).unwrap());
+        assert!(!matches(&user(), &doc! { "version": { "$gt": 3 } }).unwrap());
+        assert!(matches(&user(), &doc! { "uid": { "$in": ["a", "bsjung"] } }).unwrap());
+        assert!(matches(&user(), &doc! { "uid": { "$ne": "other" } }).unwrap());
+        assert!(matches(&user(), &doc! { "password": { "$exists": false } }).unwrap());
+
+        let stale = doc! { "locked_at": Utc::now() - Duration::minutes(20) };
+        assert!(matches(&stale, &doc! { "locked_at": { "$lt": Utc::now() } }).unwrap());
+    }
+
+    #[test]
+    fn it_matches_logical_operators() {
+        let filter = doc! { "$or": [{ "uid": "nobody" }, { "roles": "admin" }] };
+        assert!(matches(&user(), &filter).unwrap());
+        let filter = doc! { "$and": [{ "uid": "bsjung" }, { "roles": "guest" }] };
+        assert!(!matches(&user(), &filter).unwrap());
+    }
+
+    #[test]
+    fn it_rejects_unsupported_operators() {
+        assert!(matches(&user(), &doc! { "uid": { "$regex": "^bs" } }).is_err());
+    }
+
+    #[test]
+    fn it_applies_update_operators() {
+        let mut document = user();
+        let update = doc! {
+            "$set": { "profile.country": "US", "name": "Benjamin" },
+            "$unset": { "version": "" },
+            "$inc": { "logins": 1, "balance": 2.5 },
+            "$push": { "tags": "new" },
+            "$pull": { "roles": "admin" },
+        };
+        apply_update(&mut document, &update).unwrap();
+
+        assert_eq!(lookup(&document, "profile.country"), Some(&Bson::String("US".into())));
+        assert_eq!(document.get_str("name").unwrap(), "Benjamin");
+        assert!(!document.contains_key("version"));
+        assert_eq!(document.get("logins"), Some(&Bson::I32(1)));
+        assert_eq!(document.get("balance"), Some(&Bson::FloatingPoint(2.5)));
+        assert_eq!(document.get("tags"), Some(&Bson::Array(vec!["new".into()])));
+        assert_eq!(document.get("roles"), Some(&Bson::Array(vec!["user".into()])));
+    }
+
+    #[test]
+    fn it_sorts_by_several_fields() {
+        let mut documents = vec![
+            doc! { "version": 2, "name": "b" },
+            doc! { "version": 1, "name": "c" },
+            doc! { "version": 2, "name": "a" },
+        ];
+        sort(&mut documents, &doc! { "version": -1, "name": 1 });
+        let names: Vec<&str> = documents.iter().map(|d| d.get_str("name").unwrap()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+    }
+}
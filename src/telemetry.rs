@@ -0,0 +1,451 @@
+//! Tracing spans with W3C trace context propagation and pluggable exporters.
+//!
+//! Spans nest through a thread-local "current span": `in_span` runs a closure as a
+//! child of the current span, and `InSpan` keeps a span current while a future is polled.
+
+use crate::config::Config;
+use chrono::{DateTime, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Identifies a span within a trace, as carried by the `traceparent` header
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse a W3C `traceparent` header: `00-<trace-id>-<parent-id>-<flags>`
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let parts: Vec<&str> = header.trim().split('-').collect();
+        let (version, trace_id, span_id, flags) = match parts.as_slice() {
+            [version, trace_id, span_id, flags] => (*version, *trace_id, *span_id, *flags),
+            _ => return None,
+        };
+        let is_hex = |value: &str, len: usize| {
+            value.len() == len && value.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        };
+        let is_zero = |value: &str| value.chars().all(|c| c == '0');
+
+        if version != "00" || !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        if is_zero(trace_id) || is_zero(span_id) {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceContext {
+            trace_id: trace_id.into(),
+            span_id: span_id.into(),
+            sampled: flags & 0x01 == 0x01,
+        })
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            if self.sampled { 1 } else { 0 }
+        )
+    }
+}
+
+/// A span that has ended, as handed to exporters
+#[derive(Clone, Debug)]
+pub struct FinishedSpan {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub duration_ms: f64,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl FinishedSpan {
+    pub fn to_json(&self) -> Value {
+        let attributes: Map<String, Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+            .collect();
+        json!({
+            "trace_id": self.trace_id,
+            "span_id": self.span_id,
+            "parent_span_id": self.parent_span_id,
+            "name": self.name,
+            "start": self.start.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "duration_ms": self.duration_ms,
+            "attributes": attributes,
+        })
+    }
+}
+
+/// Destination of finished spans
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: &FinishedSpan);
+}
+
+/// Drops every span
+pub struct NoopExporter;
+
+impl SpanExporter for NoopExporter {
+    fn export(&self, _: &FinishedSpan) {}
+}
+
+/// Writes one JSON object per span to stdout
+pub struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(&self, span: &FinishedSpan) {
+        println!("{}", span.to_json());
+    }
+}
+
+/// Appends one JSON object per span to a file
+pub struct FileExporter {
+    file: Mutex<File>,
+}
+
+impl FileExporter {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileExporter {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(&self, span: &FinishedSpan) {
+        let mut file = self.file.lock().expect("Could not acquire lock");
+        if let Err(e) = writeln!(file, "{}", span.to_json()) {
+            warn!("Could not export span: {}", e);
+        }
+    }
+}
+
+lazy_static! {
+    static ref EXPORTER: RwLock<Box<dyn SpanExporter>> = RwLock::new(Box::new(NoopExporter));
+}
+
+/// Select the exporter from `trace_exporter`: "none" (default), "stdout" or "file"
+pub fn init(config: &Config) -> io::Result<()> {
+    let exporter: Box<dyn SpanExporter> = match config.trace_exporter.as_str() {
+        "stdout" => Box::new(StdoutExporter),
+        "file" => Box::new(FileExporter::new(&config.trace_file)?),
+        "none" => Box::new(NoopExporter),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown trace exporter: {}", other),
+            ))
+        }
+    };
+    set_exporter(exporter);
+    Ok(())
+}
+
+pub fn set_exporter(exporter: Box<dyn SpanExporter>) {
+    *EXPORTER.write().expect("Could not acquire lock") = exporter;
+}
+
+/// A span in progress
+pub struct Span {
+    context: TraceContext,
+    parent_span_id: Option<String>,
+    name: RefCell<String>,
+    start: DateTime<Utc>,
+    started: Instant,
+    attributes: RefCell<Vec<(String, String)>>,
+}
+
+impl Span {
+    /// Start a span, continuing the trace of `parent` if there is one
+    pub fn start(name: &str, parent: Option<&TraceContext>) -> Rc<Span> {
+        let context = TraceContext {
+            trace_id: parent
+                .map(|parent| parent.trace_id.clone())
+                .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>())),
+            span_id: format!("{:016x}", rand::random::<u64>().max(1)),
+            sampled: parent.map(|parent| parent.sampled).unwrap_or(true),
+        };
+        Rc::new(Span {
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id.clone()),
+            name: RefCell::new(name.into()),
+            start: Utc::now(),
+            started: Instant::now(),
+            attributes: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.name.replace(name.into());
+    }
+
+    pub fn set_attribute(&self, key: &str, value: impl ToString) {
+        self.attributes.borrow_mut().push((key.into(), value.to_string()));
+    }
+
+    /// End the span and hand it to the exporter
+    pub fn finish(&self) {
+        if !self.context.sampled {
+            return;
+        }
+        let span = FinishedSpan {
+            trace_id: self.context.trace_id.clone(),
+            span_id: self.context.span_id.clone(),
+            parent_span_id: self.parent_span_id.clone(),
+            name: self.name.borrow().clone(),
+            start: self.start,
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            attributes: self.attributes.borrow().clone(),
+        };
+        EXPORTER.read().expect("Could not acquire lock").export(&span);
+    }
+}
+
+thread_local! {
+    static CURRENT_SPAN: RefCell<Option<Rc<Span>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with `span` as the current span
+pub fn with_span<T>(span: &Rc<Span>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_SPAN.with(|current| current.replace(Some(span.clone())));
+    let result = f();
+    CURRENT_SPAN.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Context of the current span, if any
+pub fn current_context() -> Option<TraceContext> {
+    CURRENT_SPAN.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|span| span.context().clone())
+    })
+}
+
+/// Run `f` in a new child span of the current span
+pub fn in_span<T>(name: &str, attributes: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let span = Span::start(name, current_context().as_ref());
+    for (key, value) in attributes {
+        span.set_attribute(key, value);
+    }
+    let result = with_span(&span, f);
+    span.finish();
+    result
+}
+
+/// Wrap `f` to run in a new child span of the current span wherever it is
+/// called, e.g. on the blocking pool where the current span is not set
+pub fn in_current_trace<F, T>(name: &str, f: F) -> impl FnOnce() -> T + Send + 'static
+where
+    F: FnOnce() -> T + Send + 'static,
+{
+    let parent = current_context();
+    let name = name.to_string();
+    move || {
+        let span = Span::start(&name, parent.as_ref());
+        let result = with_span(&span, f);
+        span.finish();
+        result
+    }
+}
+
+/// Run future `f` in a new child span of the current span
+pub async fn traced<F: Future>(name: &str, f: F) -> F::Output {
+    let span = Span::start(name, current_context().as_ref());
+    let result = InSpan::new(span.clone(), f).await;
+    span.finish();
+    result
+}
+
+/// `traceparent` of the current span, to continue the trace in outgoing calls
+pub fn current_traceparent() -> Option<String> {
+    current_context().map(|context| context.to_traceparent())
+}
+
+/// Add an attribute to the current span, if any
+pub fn set_attribute(key: &str, value: impl ToString) {
+    CURRENT_SPAN.with(|current| {
+        if let Some(span) = current.borrow().as_ref() {
+            span.set_attribute(key, value);
+        }
+    })
+}
+
+/// Keeps a span current every time the wrapped future is polled
+pub struct InSpan<F> {
+    span: Rc<Span>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F> InSpan<F> {
+    pub fn new(span: Rc<Span>, inner: F) -> Self {
+        InSpan {
+            span,
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+impl<F: Future> Future for InSpan<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        with_span(&this.span, || inner.as_mut().poll(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::tests::TempFile;
+    use std::sync::Arc;
+
+    static TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[derive(Clone, Default)]
+    struct MemoryExporter {
+        spans: Arc<Mutex<Vec<FinishedSpan>>>,
+    }
+
+    impl SpanExporter for MemoryExporter {
+        fn export(&self, span: &FinishedSpan) {
+            self.spans.lock().unwrap().push(span.clone());
+        }
+    }
+
+    lazy_static! {
+        /// Installed once for the whole run, since tests run in parallel;
+        /// each test tells its spans apart by a trace id of its own
+        static ref RECORDED: MemoryExporter = {
+            let exporter = MemoryExporter::default();
+            set_exporter(Box::new(exporter.clone()));
+            exporter
+        };
+    }
+
+    /// Start recording spans, returning a parent in a trace no other test uses
+    fn start_recording() -> TraceContext {
+        lazy_static::initialize(&RECORDED);
+        let traceparent = format!("00-{:032x}-00f067aa0ba902b7-01", rand::random::<u128>().max(1));
+        TraceContext::from_traceparent(&traceparent).unwrap()
+    }
+
+    /// Recorded span `name` of trace `trace_id`
+    fn recorded_span(trace_id: &str, name: &str) -> FinishedSpan {
+        RECORDED
+            .spans
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|span| span.trace_id == trace_id && span.name == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("Span {} was not exported", name))
+    }
+
+    #[test]
+    fn it_parses_and_formats_a_traceparent() {
+        let context = TraceContext::from_traceparent(TRACEPARENT).unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        assert!(context.sampled);
+        assert_eq!(context.to_traceparent(), TRACEPARENT);
+    }
+
+    #[test]
+    fn it_rejects_an_invalid_traceparent() {
+        assert!(TraceContext::from_traceparent("00-abc-def-01").is_none());
+        assert!(TraceContext::from_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+        )
+        .is_none());
+        assert!(TraceContext::from_traceparent(
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn it_nests_spans_within_a_trace() {
+        let parent = start_recording();
+        let root = Span::start("root", Some(&parent));
+        with_span(&root, || {
+            in_span("mongodb.find", &[("db.collection", "users")], || {
+                set_attribute("db.matched_count", 1)
+            })
+        });
+        root.finish();
+
+        let child = recorded_span(&parent.trace_id, "mongodb.find");
+        assert_eq!(child.parent_span_id.as_ref(), Some(&root.context().span_id));
+        assert!(child.attributes.contains(&("db.matched_count".into(), "1".into())));
+        assert!(child.attributes.contains(&("db.collection".into(), "users".into())));
+    }
+
+    #[actix_rt::test]
+    async fn it_continues_the_trace_in_handlers_and_on_other_threads() {
+        let parent = start_recording();
+        let root = Span::start("request", Some(&parent));
+        let handler = traced("handler.trace_test", async {
+            let work = in_current_trace("blocking.trace_test", || {
+                in_span("mongodb.trace_test", &[], || ())
+            });
+            std::thread::spawn(work).join().unwrap();
+        });
+        InSpan::new(root.clone(), handler).await;
+        root.finish();
+
+        let span = |name: &str| recorded_span(&parent.trace_id, name);
+        let handler = span("handler.trace_test");
+        let blocking = span("blocking.trace_test");
+        assert_eq!(handler.parent_span_id.as_ref(), Some(&root.context().span_id));
+        assert_eq!(blocking.parent_span_id.as_ref(), Some(&handler.span_id));
+        assert_eq!(span("mongodb.trace_test").parent_span_id.as_ref(), Some(&blocking.span_id));
+    }
+
+    #[test]
+    fn it_exports_spans_to_a_file() {
+        let file = TempFile::new("spans.jsonl", "");
+        let exporter = FileExporter::new(file.path()).unwrap();
+        let span = FinishedSpan {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+            span_id: "00f067aa0ba902b7".into(),
+            parent_span_id: None,
+            name: "GET /health".into(),
+            start: Utc::now(),
+            duration_ms: 1.5,
+            attributes: vec![("http.status_code".into(), "200".into())],
+        };
+        exporter.export(&span);
+
+        let written = std::fs::read_to_string(file.path()).unwrap();
+        let value: Value = serde_json::from_str(written.trim()).unwrap();
+        assert_eq!(value["name"], "GET /health");
+        assert_eq!(value["attributes"]["http.status_code"], "200");
+    }
+}
@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    use crate::handlers::asset::{AssetPatchRequest, AssetRequest};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::{assert_success, read_json, test_context, TestClient};
+    use actix_web::http::StatusCode;
+
+    const PATH: &str = "/api/v1/asset";
+
+    fn solana() -> AssetRequest {
+        AssetRequest {
+            ticker: "sol".into(),
+            name: "Solana".into(),
+            decimals: 9,
+            enabled: true,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_lets_admins_manage_the_catalog() {
+        let fixtures = Fixtures::new(test_context());
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        let client = TestClient::login(fixtures.ctx(), &admin).await;
+
+        let asset = read_json(assert_success(client.post(PATH, solana()).await)).await;
+        assert_eq!(asset["ticker"], "SOL");
+        assert_eq!(client.post(PATH, solana()).await.status(), StatusCode::CONFLICT);
+
+        let disable = AssetPatchRequest {
+            enabled: Some(false),
+            ..AssetPatchRequest::default()
+        };
+        let asset = read_json(assert_success(client.patch(&format!("{}/sol", PATH), disable).await)).await;
+        assert_eq!(asset["enabled"], false);
+
+        let assets = read_json(assert_success(client.get(PATH).await)).await;
+        assert_eq!(assets.as_array().unwrap().len(), 5);
+        assert_success(client.delete(&format!("{}/SOL", PATH)).await);
+        assert_eq!(client.get(&format!("{}/SOL", PATH)).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn it_lets_users_read_but_not_change_the_catalog() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+
+        let asset = read_json(assert_success(client.get(&format!("{}/btc", PATH)).await)).await;
+        assert_eq!(asset["decimals"], 8);
+        assert_eq!(client.post(PATH, solana()).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.delete(&format!("{}/BTC", PATH)).await.status(), StatusCode::FORBIDDEN);
+    }
+}
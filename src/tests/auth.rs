@@ -1,23 +1,115 @@
 #[cfg(test)]
 mod tests {
     use crate::handlers::auth::LoginRequest;
-    use crate::tests::helpers::tests::{assert_get, assert_post};
+    use crate::handlers::mfa::LoginMfaRequest;
+    use crate::tests::fixtures::tests::{Fixtures, TestUser};
+    use crate::tests::helpers::tests::{assert_success, read_json, test_context, TestClient};
+    use crate::totp;
+    use actix_web::http::StatusCode;
 
     const PATH: &str = "/api/v1/auth";
 
     #[actix_rt::test]
     async fn it_logs_a_user_in() {
-        let params = LoginRequest {
-            uid: "bsjung".into(),
-            password: "123456".into(),
-        };
-        let url = format!("{}/login", PATH);
-        assert_post(&url, params).await;
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        assert_success(client.get("/api/v1/user").await);
     }
 
     #[actix_rt::test]
     async fn it_logs_a_user_out() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
         let url = format!("{}/logout", PATH);
-        assert_get(&url).await;
+        assert_success(client.get(&url).await);
+        // The client still sends the cookie, its session is over
+        assert_eq!(client.get("/api/v1/user").await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_accepts_the_jwt_as_a_bearer_token() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login_bearer(fixtures.ctx(), &user).await;
+        assert_success(client.get("/api/v1/user").await);
+
+        assert_success(client.get(&format!("{}/logout", PATH)).await);
+        assert_eq!(client.get("/api/v1/user").await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_an_invalid_bearer_token() {
+        let client = TestClient::bearer(test_context(), "not-a-jwt");
+        assert_eq!(client.get("/api/v1/user").await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_requests_without_a_session() {
+        let client = TestClient::anonymous(test_context());
+        assert_eq!(client.get("/api/v1/user").await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Turn on two-factor authentication for `user`, returning the secret
+    fn enable_totp(fixtures: &Fixtures, user: &TestUser) -> String {
+        let secret = totp::generate_secret();
+        let update = doc! { "$set": { "totp_enabled": true, "totp_secret": &secret } };
+        fixtures.ctx().db.update_one("users", doc! { "uid": &user.uid }, update).unwrap();
+        secret
+    }
+
+    /// Log in with the password, returning the "mfa pending" token
+    async fn mfa_token(client: &TestClient, user: &TestUser) -> String {
+        let params = LoginRequest {
+            uid: user.uid.clone(),
+            password: user.password.clone(),
+        };
+        let body = read_json(assert_success(client.post(&format!("{}/login", PATH), params).await)).await;
+        assert_eq!(body["status"], "mfa_required");
+        body["data"].as_str().unwrap().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn it_accepts_each_totp_code_once() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let secret = enable_totp(&fixtures, &user);
+        let client = TestClient::anonymous(fixtures.ctx());
+        let url = format!("{}/login/mfa", PATH);
+        let code = totp::current_code(&secret);
+
+        let params = LoginMfaRequest {
+            token: mfa_token(&client, &user).await,
+            code: code.clone(),
+        };
+        assert_success(client.post(&url, params).await);
+        // A code seen by someone else is useless once it was used
+        let params = LoginMfaRequest {
+            token: mfa_token(&client, &user).await,
+            code,
+        };
+        assert_eq!(client.post(&url, params).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_locks_out_after_repeated_invalid_codes() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let secret = enable_totp(&fixtures, &user);
+        let client = TestClient::anonymous(fixtures.ctx());
+        let url = format!("{}/login/mfa", PATH);
+        let token = mfa_token(&client, &user).await;
+        let attempt = |code: String| LoginMfaRequest {
+            token: token.clone(),
+            code,
+        };
+
+        for _ in 0..fixtures.ctx().config.mfa_max_failures {
+            let status = client.post(&url, attempt("not-a-code".into())).await.status();
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        let status = client.post(&url, attempt(totp::current_code(&secret))).await.status();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
     }
 }
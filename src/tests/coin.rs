@@ -0,0 +1,165 @@
+#[cfg(test)]
+mod tests {
+    use crate::handlers::coin::{AmountRequest, CoinRequest, DepositRequest, TransferRequest, IDEMPOTENCY_KEY_HEADER};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::{assert_success, read_json, test_context, TestClient};
+    use actix_web::http::StatusCode;
+
+    const PATH: &str = "/api/v1/coin";
+
+    fn amount(value: &str) -> AmountRequest {
+        AmountRequest { amount: value.into() }
+    }
+
+    fn deposit(value: &str, uid: Option<&str>) -> DepositRequest {
+        DepositRequest {
+            amount: value.into(),
+            uid: uid.map(String::from),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_opens_and_lists_holdings() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().roles(&["admin", "user"]).create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let params = CoinRequest {
+            coin: Some("ethereum".into()),
+            ticker: "eth".into(),
+            amount: "2.5".into(),
+        };
+        assert_success(client.post(PATH, params).await);
+
+        let holdings = read_json(assert_success(client.get(PATH).await)).await;
+        assert_eq!(holdings[0]["ticker"], "ETH");
+        assert_eq!(holdings[0]["coin"], "Ethereum");
+        assert_eq!(holdings[0]["amount"], "2.500000000");
+    }
+
+    #[actix_rt::test]
+    async fn it_only_lets_admins_create_coins() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let funded = |amount: &str| CoinRequest {
+            coin: None,
+            ticker: "ETH".into(),
+            amount: amount.into(),
+        };
+
+        assert_eq!(client.post(PATH, funded("2")).await.status(), StatusCode::FORBIDDEN);
+        assert_success(client.post(PATH, funded("0")).await);
+        let status = client.post(&format!("{}/BTC/deposit", PATH), deposit("1", None)).await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let admin_client = TestClient::login(fixtures.ctx(), &admin).await;
+        let response = admin_client.post(&format!("{}/BTC/deposit", PATH), deposit("0.5", Some(&user.uid))).await;
+        assert_eq!(read_json(assert_success(response)).await["amount"], "1.50000000");
+        let page = read_json(assert_success(client.get(&format!("{}/BTC/transactions", PATH)).await)).await;
+        assert_eq!(page["items"][0]["actor"], admin.uid.as_str());
+    }
+
+    #[actix_rt::test]
+    async fn it_only_opens_holdings_of_catalog_assets() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().roles(&["admin", "user"]).create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let params = |coin: &str, ticker: &str| CoinRequest {
+            coin: Some(coin.into()),
+            ticker: ticker.into(),
+            amount: "1".into(),
+        };
+
+        for (coin, ticker) in &[("Dogecoin", "DOGE"), ("Bitcoin Cash", "BTC")] {
+            let status = client.post(PATH, params(coin, ticker)).await.status();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{} should be refused", coin);
+        }
+        let status = client.post(&format!("{}/DOGE/deposit", PATH), deposit("1", None)).await.status();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn it_deposits_and_withdraws() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().roles(&["admin", "user"]).create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+
+        let response = client.post(&format!("{}/BTC/deposit", PATH), deposit("0.5", None)).await;
+        let balance = read_json(assert_success(response)).await;
+        assert_eq!(balance["amount"], "1.50000000");
+
+        let response = client.post(&format!("{}/btc/withdraw", PATH), amount("1.5")).await;
+        let balance = read_json(assert_success(response)).await;
+        assert_eq!(balance["amount"], "0.00000000");
+    }
+
+    #[actix_rt::test]
+    async fn it_refuses_to_overdraw_or_take_invalid_amounts() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().roles(&["admin", "user"]).create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let withdraw = format!("{}/BTC/withdraw", PATH);
+
+        assert_eq!(client.post(&withdraw, amount("2")).await.status(), StatusCode::BAD_REQUEST);
+        for invalid in &["0", "-1", "1.000000001", "one"] {
+            let status = client.post(&withdraw, amount(invalid)).await.status();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{} should be rejected", invalid);
+        }
+        let status = client.post(&format!("{}/ETH/deposit", PATH), deposit("1", None)).await.status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn it_lists_transactions_of_a_holding() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().roles(&["admin", "user"]).create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        assert_success(client.post(&format!("{}/BTC/deposit", PATH), deposit("0.5", None)).await);
+
+        let url = format!("{}/BTC/transactions?per_page=1", PATH);
+        let page = read_json(assert_success(client.get(&url).await)).await;
+        assert_eq!(page["items"][0]["kind"], "deposit");
+        assert_eq!(page["items"][0]["actor"], user.uid.as_str());
+        assert_eq!(page["items"][0]["after"], "1.50000000");
+        assert_eq!(page["has_more"], true);
+
+        let url = format!("{}/BTC/transactions?page=2&per_page=1", PATH);
+        let page = read_json(assert_success(client.get(&url).await)).await;
+        assert_eq!(page["items"][0]["kind"], "open");
+        assert_eq!(page["has_more"], false);
+
+        let url = format!("{}/BTC/transactions?page={}&per_page=100", PATH, i64::MAX);
+        assert_eq!(client.get(&url).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn it_transfers_once_per_idempotency_key() {
+        let fixtures = Fixtures::new(test_context());
+        let sender = fixtures.user().create();
+        let recipient = fixtures.user().create();
+        fixtures.coin(&sender.uid).amount("1").create();
+        fixtures.coin(&recipient.uid).amount("0").create();
+        let client = TestClient::login(fixtures.ctx(), &sender).await;
+        let url = format!("{}/BTC/transfer", PATH);
+        let params = || TransferRequest {
+            to: recipient.uid.clone(),
+            amount: "0.25".into(),
+        };
+
+        assert_eq!(client.post(&url, params()).await.status(), StatusCode::BAD_REQUEST);
+        for _ in 0..2 {
+            let response = client.post_with_header(&url, (IDEMPOTENCY_KEY_HEADER, "retry-1"), params()).await;
+            let transfer = read_json(assert_success(response)).await;
+            assert_eq!(transfer["status"], "completed");
+            assert_eq!(transfer["to"], recipient.uid.as_str());
+        }
+
+        let holdings = read_json(assert_success(client.get(PATH).await)).await;
+        assert_eq!(holdings[0]["amount"], "0.75000000");
+    }
+}
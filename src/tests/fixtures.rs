@@ -0,0 +1,224 @@
+#[cfg(test)]
+pub mod tests {
+    use crate::context::AppContext;
+    use crate::database;
+    use crate::jwt::hash;
+    use crate::models::amount::Amount;
+    use crate::models::asset::decimals;
+    use crate::models::coin::create_holding;
+    use crate::tests::helpers::tests::test_context;
+    use actix_web::web::Data;
+    use bson::Document;
+    use chrono::Utc;
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Password of users built without one
+    pub const DEFAULT_PASSWORD: &str = "Tr0ub4dor&3";
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+    /// Id no other fixture of this run, nor of a concurrent run, will use
+    pub fn unique_id(prefix: &str) -> String {
+        let n = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        format!("{}_{}_{}", prefix, std::process::id(), n)
+    }
+
+    /// A user created by a fixture, with the plain text password to log in
+    #[derive(Clone, Debug)]
+    pub struct TestUser {
+        pub uid: String,
+        pub email: String,
+        pub password: String,
+    }
+
+    /// Creates records for a test and deletes them when dropped
+    pub struct Fixtures {
+        ctx: Data<AppContext>,
+        created: RefCell<Vec<(&'static str, Document)>>,
+    }
+
+    impl Fixtures {
+        pub fn new(ctx: Data<AppContext>) -> Self {
+            Fixtures {
+                ctx,
+                created: RefCell::new(Vec::new()),
+            }
+        }
+
+        pub fn ctx(&self) -> Data<AppContext> {
+            self.ctx.clone()
+        }
+
+        /// Start building a user with a unique uid and email
+        pub fn user(&self) -> UserBuilder<'_> {
+            let uid = unique_id("user");
+            UserBuilder {
+                fixtures: self,
+                email: format!("{}@example.com", uid),
+                uid,
+                password: DEFAULT_PASSWORD.into(),
+                name: "Test User".into(),
+                phone: "010-0000-0000".into(),
+                roles: vec!["user".into()],
+            }
+        }
+
+        /// Start building a holding of `uid`
+        pub fn coin(&self, uid: &str) -> CoinBuilder<'_> {
+            CoinBuilder {
+                fixtures: self,
+                uid: uid.into(),
+                coin: "Bitcoin".into(),
+                ticker: "BTC".into(),
+                amount: "1".into(),
+            }
+        }
+
+        /// Insert a document, deleting it again on drop
+        fn insert(&self, collection: &'static str, document: Document, key: Document) {
+            database::create(&*self.ctx.db, collection, document).expect("Could not create a fixture");
+            self.track(collection, key);
+        }
+
+        /// Delete the documents matching `key` on drop
+        fn track(&self, collection: &'static str, key: Document) {
+            self.created.borrow_mut().push((collection, key));
+        }
+    }
+
+    impl Drop for Fixtures {
+        fn drop(&mut self) {
+            for (collection, key) in self.created.borrow_mut().drain(..).rev() {
+                if let Err(e) = self.ctx.db.delete_many(collection, key) {
+                    eprintln!("Could not clean up a {} fixture: {}", collection, e);
+                }
+            }
+        }
+    }
+
+    pub struct UserBuilder<'a> {
+        fixtures: &'a Fixtures,
+        uid: String,
+        email: String,
+        password: String,
+        name: String,
+        phone: String,
+        roles: Vec<String>,
+    }
+
+    impl UserBuilder<'_> {
+        pub fn uid(mut self, uid: &str) -> Self {
+            self.uid = uid.into();
+            self
+        }
+
+        pub fn email(mut self, email: &str) -> Self {
+            self.email = email.into();
+            self
+        }
+
+        pub fn password(mut self, password: &str) -> Self {
+            self.password = password.into();
+            self
+        }
+
+        pub fn roles(mut self, roles: &[&str]) -> Self {
+            self.roles = roles.iter().map(|role| role.to_string()).collect();
+            self
+        }
+
+        pub fn create(self) -> TestUser {
+            let user = doc! {
+                "uid": self.uid.clone(),
+                "email": self.email.clone(),
+                "password": hash(&self.fixtures.ctx.config, &self.password),
+                "name": self.name,
+                "phone": self.phone,
+                "roles": self.roles,
+                "created_at": Utc::now(),
+                "updated_at": Utc::now(),
+            };
+            self.fixtures.insert("users", user, doc! { "uid": self.uid.clone() });
+            TestUser {
+                uid: self.uid,
+                email: self.email,
+                password: self.password,
+            }
+        }
+    }
+
+    pub struct CoinBuilder<'a> {
+        fixtures: &'a Fixtures,
+        uid: String,
+        coin: String,
+        ticker: String,
+        amount: String,
+    }
+
+    impl CoinBuilder<'_> {
+        pub fn coin(mut self, coin: &str, ticker: &str) -> Self {
+            self.coin = coin.into();
+            self.ticker = ticker.into();
+            self
+        }
+
+        /// Decimal amount, such as "1.5"
+        pub fn amount(mut self, amount: &str) -> Self {
+            self.amount = amount.into();
+            self
+        }
+
+        pub fn create(self) {
+            let db = &*self.fixtures.ctx.db;
+            let precision = decimals(db, &self.ticker).expect("Could not look up the fixture decimals");
+            let amount = Amount::parse(&self.amount, precision).expect("Invalid fixture amount");
+            create_holding(db, &self.uid, &self.coin, &self.ticker, amount, "fixtures").expect("Could not create a fixture");
+            let key = doc! { "uid": self.uid, "ticker": self.ticker };
+            self.fixtures.track("coin_transactions", key.clone());
+            self.fixtures.track("coins", key);
+        }
+    }
+
+    #[test]
+    fn it_creates_users_with_unique_ids() {
+        let fixtures = Fixtures::new(test_context());
+        let first = fixtures.user().create();
+        let second = fixtures.user().create();
+        assert_ne!(first.uid, second.uid);
+        assert_ne!(first.email, second.email);
+    }
+
+    #[test]
+    fn it_creates_users_with_the_given_fields() {
+        let fixtures = Fixtures::new(test_context());
+        let uid = unique_id("named");
+        let user = fixtures
+            .user()
+            .uid(&uid)
+            .email("named@example.com")
+            .password("C0rrect-Horse")
+            .roles(&["user", "admin"])
+            .create();
+
+        let ctx = fixtures.ctx();
+        let stored = ctx.db.find_one("users", doc! { "uid": &uid }).unwrap().unwrap();
+        assert_eq!(stored.get_str("email").unwrap(), "named@example.com");
+        assert_eq!(stored.get_str("password").unwrap(), hash(&ctx.config, "C0rrect-Horse"));
+        assert_eq!(stored.get_array("roles").unwrap().len(), 2);
+        assert_eq!(user.password, "C0rrect-Horse");
+    }
+
+    #[test]
+    fn it_deletes_what_it_created_when_dropped() {
+        let ctx = test_context();
+        let fixtures = Fixtures::new(ctx.clone());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).create();
+        assert!(ctx.db.find_one("users", doc! { "uid": &user.uid }).unwrap().is_some());
+
+        drop(fixtures);
+        assert!(ctx.db.find_one("users", doc! { "uid": &user.uid }).unwrap().is_none());
+        assert!(ctx.db.find_one("coins", doc! { "uid": &user.uid }).unwrap().is_none());
+    }
+}
@@ -1,10 +1,10 @@
 #[cfg(test)]
 mod tests {
-    use crate::tests::helpers::tests::assert_get;
+    use crate::tests::helpers::tests::{assert_success, test_context, TestClient};
 
     #[actix_rt::test]
-    #[ignore]
     async fn test_health() {
-        assert_get("/health").await;
+        let client = TestClient::anonymous(test_context());
+        assert_success(client.get("/health").await);
     }
 }
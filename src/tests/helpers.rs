@@ -1,122 +1,226 @@
 #[cfg(test)]
 pub mod tests {
-    use crate::config::CONFIG;
+    use crate::config::{self, Config};
+    use crate::context::AppContext;
+    use crate::database;
     use crate::jwt::get_identity_service;
     use crate::handlers::auth::LoginRequest;
+    use crate::models::asset::register_defaults;
+    use crate::prices::StaticPrices;
     use crate::routes::routes;
-    use crate::state::{new_state, AppState};
+    use crate::store::MemoryStore;
+    use crate::tests::fixtures::tests::TestUser;
+    use actix_web::cookie::Cookie;
     use actix_web::dev::ServiceResponse;
-    use actix_web::{test, web::Data, App};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web::Data, App};
     use serde::Serialize;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Once};
 
-    /// Helper for HTTP GET integration tests
-    pub async fn test_get(route: &str) -> ServiceResponse {
-        let login_request = LoginRequest {
-            uid: "bsjung2".into(),
-            password: "123456".into(),
-        };
-
-        let mut app = test::init_service(
-            App::new()
-                .app_data(app_state())
-                .wrap(get_identity_service())
-                .configure(routes),
-        )
-        .await;
-
-        let response = test::call_service(
-            &mut app,
-            test::TestRequest::post()
-                .set_json(&login_request)
-                .uri("/api/v1/auth/login")
-                .to_request(),
-        )
-        .await;
-
-        let cookie = response.response().cookies().next().unwrap().to_owned();
-        test::call_service(
-            &mut app,
-            test::TestRequest::get()
-                .cookie(cookie.clone())
-                .uri(route)
-                .to_request(),
-        )
-        .await
+    /// Config from fixed values, so the tests need no environment or `.env`
+    pub fn test_config() -> Config {
+        config::tests::fixed_config()
     }
 
-    /// Helper for HTTP POST integration tests
-    pub async fn test_post<T: Serialize>(route: &str, params: T) -> ServiceResponse {
-        let mut app = test::init_service(
-            App::new()
-                .app_data(app_state())
-                .wrap(get_identity_service())
-                .configure(routes),
-        )
-        .await;
-        
-        //let login = login().await;
-        //let cookie = login.response().cookies().next().unwrap().to_owned();
-        test::call_service(
-            &mut app,
-            test::TestRequest::post()
-                .set_json(&params)
-                //.cookie(cookie.clone())
-                .uri(route)
-                .to_request(),
-        )
-        .await
+    /// Context on a fresh, empty in-memory store, so tests run without
+    /// MongoDB and cannot see each other's writes.
+    /// The asset catalog holds the default assets, and prices are fixed:
+    /// BTC and ETH in USD and EUR.
+    pub fn test_context() -> Data<AppContext> {
+        let db = MemoryStore::new();
+        database::ensure_indexes(&db).expect("Could not create the test indexes");
+        register_defaults(&db).expect("Could not register the test assets");
+        let mut ctx = AppContext::with_store(test_config(), Arc::new(db)).expect("Could not create the test context");
+        let quotes = [
+            ("BTC", "USD", "64000"),
+            ("BTC", "EUR", "59000"),
+            ("ETH", "USD", "3100"),
+            ("ETH", "EUR", "2860"),
+        ];
+        ctx.prices = Box::new(StaticPrices::new(&quotes).expect("Invalid test prices"));
+        Data::new(ctx)
     }
 
-    /// Helper to login for tests
-    // pub fn login_request() -> Request {
-    //     let login_request = LoginRequest {
-    //         email: "satoshi@nakamotoinstitute.org".into(),
-    //         password: "123456".into(),
-    //     };
-    //     test::TestRequest::post()
-    //         .set_json(&login_request)
-    //         .uri("/api/v1/auth/login")
-    //         .to_request()
-    // }
-
-    /// Assert that a route is successful for HTTP GET requests
-    pub async fn assert_get(route: &str) -> ServiceResponse {
-        let response = test_get(route).await;
-        assert!(response.status().is_success());
-        response
+    /// Sends requests to the routes, carrying the session cookie or a bearer token once logged in
+    pub struct TestClient {
+        ctx: Data<AppContext>,
+        cookie: Option<Cookie<'static>>,
+        token: Option<String>,
+    }
+
+    impl TestClient {
+        /// Client without a session
+        pub fn anonymous(ctx: Data<AppContext>) -> Self {
+            TestClient { ctx, cookie: None, token: None }
+        }
+
+        /// Client sending `token` in an `Authorization: Bearer` header
+        pub fn bearer(ctx: Data<AppContext>, token: &str) -> Self {
+            TestClient { ctx, cookie: None, token: Some(token.into()) }
+        }
+
+        /// Client logged in as `user` that sends the JWT from the login response
+        /// as a bearer token instead of the cookie, panics if the login fails
+        pub async fn login_bearer(ctx: Data<AppContext>, user: &TestUser) -> Self {
+            let client = Self::anonymous(ctx);
+            let params = LoginRequest {
+                uid: user.uid.clone(),
+                password: user.password.clone(),
+            };
+            let body = read_json(client.post("/api/v1/auth/login", params).await).await;
+            let token = match (body["status"].as_str(), body["data"].as_str()) {
+                (Some("ok"), Some(token)) => token,
+                _ => panic!("Could not log in as {}", user.uid),
+            };
+            Self::bearer(client.ctx, token)
+        }
+
+        /// Client logged in as `user`, panics if the login fails
+        pub async fn login(ctx: Data<AppContext>, user: &TestUser) -> Self {
+            let mut client = Self::anonymous(ctx);
+            let params = LoginRequest {
+                uid: user.uid.clone(),
+                password: user.password.clone(),
+            };
+            let response = client.post("/api/v1/auth/login", params).await;
+            let cookie = response
+                .response()
+                .cookies()
+                .next()
+                .unwrap_or_else(|| panic!("Could not log in as {}", user.uid))
+                .into_owned();
+            client.cookie = Some(cookie);
+            client
+        }
+
+        pub async fn get(&self, route: &str) -> ServiceResponse {
+            self.send(TestRequest::get().uri(route)).await
+        }
+
+        pub async fn delete(&self, route: &str) -> ServiceResponse {
+            self.send(TestRequest::delete().uri(route)).await
+        }
+
+        pub async fn post<T: Serialize>(&self, route: &str, params: T) -> ServiceResponse {
+            self.send(TestRequest::post().uri(route).set_json(&params)).await
+        }
+
+        pub async fn post_with_header<T: Serialize>(&self, route: &str, header: (&str, &str), params: T) -> ServiceResponse {
+            let request = TestRequest::post().uri(route).header(header.0, header.1).set_json(&params);
+            self.send(request).await
+        }
+
+        pub async fn put<T: Serialize>(&self, route: &str, params: T) -> ServiceResponse {
+            self.send(TestRequest::put().uri(route).set_json(&params)).await
+        }
+
+        pub async fn patch<T: Serialize>(&self, route: &str, params: T) -> ServiceResponse {
+            self.send(TestRequest::patch().uri(route).set_json(&params)).await
+        }
+
+        async fn send(&self, request: TestRequest) -> ServiceResponse {
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(self.ctx.clone())
+                    .wrap(get_identity_service(&self.ctx.config))
+                    .configure(routes),
+            )
+            .await;
+            let request = match self.cookie {
+                Some(ref cookie) => request.cookie(cookie.clone()),
+                None => request,
+            };
+            let request = match self.token {
+                Some(ref token) => request.header("authorization", format!("Bearer {}", token)),
+                None => request,
+            };
+            test::call_service(&mut app, request.to_request()).await
+        }
     }
 
-    /// Assert that a route is successful for HTTP POST requests
-    pub async fn assert_post<T: Serialize>(route: &str, params: T) -> ServiceResponse {
-        let response = test_post(route, params).await;
-        assert!(response.status().is_success());
+    /// Assert that a response is successful, returning it
+    pub fn assert_success(response: ServiceResponse) -> ServiceResponse {
+        assert!(response.status().is_success(), "Unexpected status {}", response.status());
         response
     }
 
-    /// Login to routes  
-    pub async fn login() -> ServiceResponse {
-        let login_request = LoginRequest {
-            uid: "bsjung@gmail.com".into(),
-            password: "123456".into(),
-        };
-        let mut app = test::init_service(
-            App::new()
-                .wrap(get_identity_service())
-                .configure(routes),
-        )
-        .await;
-        test::call_service(
-            &mut app,
-            test::TestRequest::post()
-                .set_json(&login_request)
-                .uri("/api/v1/auth/login")
-                .to_request(),
-        )
-        .await
+    /// Read a response body as JSON
+    pub async fn read_json(response: ServiceResponse) -> serde_json::Value {
+        let body = test::read_body(response).await;
+        serde_json::from_slice(&body).expect("Response body is not JSON")
+    }
+
+    /// File in the temp directory, removed when dropped
+    pub struct TempFile {
+        path: PathBuf,
+    }
+
+    impl TempFile {
+        /// Write `contents` to a file whose name ends with `name` and is unique to this call
+        pub fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{:x}-{}", std::process::id(), rand::random::<u64>(), name));
+            fs::write(&path, contents).expect("Could not write a temp file");
+            TempFile { path }
+        }
+
+        pub fn path(&self) -> &str {
+            self.path.to_str().expect("Temp file path is not UTF-8")
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Logger that keeps records on the thread that emitted them,
+    /// so tests running in parallel do not see each other's logs
+    struct CaptureLogger;
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with(|captured| {
+                if let Some(lines) = captured.borrow_mut().as_mut() {
+                    lines.push(format!("{} {}", record.target(), record.args()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static INIT_CAPTURE: Once = Once::new();
+
+    /// Start capturing log lines emitted on this thread
+    pub fn start_log_capture() {
+        INIT_CAPTURE.call_once(|| {
+            log::set_boxed_logger(Box::new(CaptureLogger)).expect("A logger is already set");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        CAPTURED.with(|captured| captured.replace(Some(Vec::new())));
+    }
+
+    /// Stop capturing and return the lines captured since `start_log_capture`
+    pub fn take_captured_logs() -> Vec<String> {
+        CAPTURED.with(|captured| captured.replace(None)).unwrap_or_default()
     }
 
-    // Mock applicate state
-    pub fn app_state() -> AppState<'static, String> {
-        new_state::<String>()
+    /// Run `f` and return every log line it emitted
+    pub fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+        start_log_capture();
+        f();
+        take_captured_logs()
     }
 }
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod tests {
+    use crate::jobs::{instance_name, jobs, run_job};
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::{assert_success, read_json, test_context, TestClient};
+    use actix_web::http::StatusCode;
+    use chrono::{Duration, Utc};
+
+    const PATH: &str = "/api/v1/job";
+
+    #[actix_rt::test]
+    async fn it_shows_admins_the_job_history() {
+        let fixtures = Fixtures::new(test_context());
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        let ctx = fixtures.ctx();
+        let recovery = jobs(&ctx.config).unwrap().into_iter().find(|job| job.name == "transfer_recovery").unwrap();
+        let owner = instance_name();
+        run_job(&ctx, &recovery, &owner, Utc::now() - Duration::minutes(10)).unwrap();
+        run_job(&ctx, &recovery, &owner, Utc::now()).unwrap();
+        let client = TestClient::login(ctx, &admin).await;
+
+        let listed = read_json(assert_success(client.get(PATH).await)).await;
+        assert_eq!(listed[0]["name"], "portfolio_snapshots");
+        assert_eq!(listed[0]["last_run"], serde_json::Value::Null);
+        assert_eq!(listed[1]["schedule"], "*/10 * * * *");
+        assert_eq!(listed[1]["last_run"]["detail"], "Recovered 0 interrupted transfer(s)");
+
+        let runs = read_json(assert_success(client.get(&format!("{}/transfer_recovery/runs?limit=1", PATH)).await)).await;
+        assert_eq!(runs.as_array().unwrap().len(), 1);
+        assert_eq!(runs[0]["status"], "succeeded");
+        let unknown = client.get(&format!("{}/cleanup/runs", PATH)).await;
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn it_hides_jobs_from_other_users() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+
+        assert_eq!(client.get(PATH).await.status(), StatusCode::FORBIDDEN);
+        let runs = client.get(&format!("{}/portfolio_snapshots/runs", PATH)).await;
+        assert_eq!(runs.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -1,6 +1,11 @@
 //! Integration tests
 
+pub mod asset;
 pub mod auth;
+pub mod coin;
+pub mod fixtures;
 pub mod health;
 pub mod helpers;
+pub mod job;
+pub mod portfolio;
 pub mod user;
@@ -0,0 +1,73 @@
+#[cfg(test)]
+mod tests {
+    use crate::jobs::snapshot_portfolios;
+    use crate::tests::fixtures::tests::Fixtures;
+    use crate::tests::helpers::tests::{assert_success, read_json, test_context, TestClient};
+    use actix_web::http::StatusCode;
+    use chrono::{Duration, Utc};
+
+    const PATH: &str = "/api/v1/portfolio";
+
+    #[actix_rt::test]
+    async fn it_values_holdings_in_the_requested_currency() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0.5").create();
+        fixtures.coin(&user.uid).coin("Ethereum", "ETH").amount("2").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+
+        let portfolio = read_json(assert_success(client.get(PATH).await)).await;
+        assert_eq!(portfolio["currency"], "USD");
+        assert_eq!(portfolio["holdings"][0]["ticker"], "BTC");
+        assert_eq!(portfolio["holdings"][0]["value"], "32000.00");
+        assert_eq!(portfolio["holdings"][1]["value"], "6200.00");
+        assert_eq!(portfolio["total"], "38200.00");
+
+        let portfolio = read_json(assert_success(client.get(&format!("{}?currency=eur", PATH)).await)).await;
+        assert_eq!(portfolio["currency"], "EUR");
+        assert_eq!(portfolio["total"], "35220.00");
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_holdings_without_a_quote() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("1").create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+
+        let portfolio = read_json(assert_success(client.get(&format!("{}?currency=GBP", PATH)).await)).await;
+        assert_eq!(portfolio["holdings"][0]["price"], serde_json::Value::Null);
+        assert_eq!(portfolio["unpriced"][0], "BTC");
+        assert_eq!(portfolio["total"], "0.00");
+
+        let status = client.get(&format!("{}?currency=dollars", PATH)).await.status();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn it_requires_a_login() {
+        let client = TestClient::anonymous(test_context());
+        assert_eq!(client.get(PATH).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_returns_snapshot_history() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        fixtures.coin(&user.uid).amount("0.5").create();
+        let ctx = fixtures.ctx();
+        snapshot_portfolios(&ctx, Utc::now() - Duration::days(2)).unwrap();
+        snapshot_portfolios(&ctx, Utc::now()).unwrap();
+        let client = TestClient::login(ctx, &user).await;
+
+        let history = read_json(assert_success(client.get(&format!("{}/history", PATH)).await)).await;
+        assert_eq!(history.as_array().unwrap().len(), 2);
+        assert_eq!(history[1]["holdings"][0]["amount"], "0.50000000");
+        assert_eq!(history[1]["total"], "32000.00");
+
+        let url = format!("{}/history?interval=month", PATH);
+        assert_eq!(client.get(&url).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let url = format!("{}/history?from=yesterday", PATH);
+        assert_eq!(client.get(&url).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
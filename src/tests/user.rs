@@ -1,36 +1,159 @@
 #[cfg(test)]
 mod tests {
-    use crate::handlers::user::{UserRequest};
-    use crate::tests::helpers::tests::{assert_get, assert_post};
-    use actix_web::web::Path;
+    use crate::handlers::user::{UserPatchRequest, UserRequest};
+    use crate::tests::fixtures::tests::{unique_id, Fixtures, TestUser};
+    use crate::tests::helpers::tests::{assert_success, test_context, TestClient};
+    use actix_web::http::StatusCode;
 
     const PATH: &str = "/api/v1/user";
 
     #[actix_rt::test]
-    #[ignore]
     async fn it_get_user() {
-        let uid: Path<String> = String::from(PATH).into();
-        let user_id = uid.split('/').last().unwrap();
-        let url = format!("{}/{}", PATH, user_id);
-        assert_get(&url).await;
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let url = format!("{}/{}", PATH, user.uid);
+        assert_success(client.get(&url).await);
     }
 
     #[actix_rt::test]
-    #[ignore]
     async fn it_get_users() {
-        assert_get(PATH).await;
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        assert_success(client.get(PATH).await);
     }
 
     #[actix_rt::test]
-    #[ignore]
     async fn it_create_user() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let uid = unique_id("created");
         let params = UserRequest {
-            uid: "bsjung2".into(),
-            email: "bsjung@gmail.com".into(),
-            password: "123456".into(),
+            email: format!("{}@example.com", uid),
+            uid,
+            password: "Tr0ub4dor&3".into(),
             name: "Benjaming".into(),
             phone: "010-xxxx-xxxx".into(),
         };
-        assert_post(PATH, params).await;
+        assert_success(client.post(PATH, params.clone()).await);
+
+        let created = TestUser {
+            uid: params.uid,
+            email: params.email,
+            password: params.password,
+        };
+        TestClient::login(fixtures.ctx(), &created).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_update_user() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let params = UserRequest {
+            uid: user.uid.clone(),
+            email: user.email.clone(),
+            password: "Tr0ub4dor&3".into(),
+            name: "Benjaming".into(),
+            phone: "010-xxxx-xxxx".into(),
+        };
+        let url = format!("{}/{}", PATH, user.uid);
+        assert_success(client.put(&url, params).await);
+
+        let updated = TestUser {
+            password: "Tr0ub4dor&3".into(),
+            ..user
+        };
+        TestClient::login(fixtures.ctx(), &updated).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_patch_user() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let params = UserPatchRequest {
+            phone: Some("010-yyyy-yyyy".into()),
+            ..UserPatchRequest::default()
+        };
+        let url = format!("{}/{}", PATH, user.uid);
+        assert_success(client.patch(&url, params).await);
+    }
+
+    #[actix_rt::test]
+    async fn it_does_not_patch_another_user() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let other = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let params = UserPatchRequest {
+            phone: Some("010-yyyy-yyyy".into()),
+            ..UserPatchRequest::default()
+        };
+        let url = format!("{}/{}", PATH, other.uid);
+        assert_eq!(client.patch(&url, params).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn it_lets_only_admins_patch_a_password() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let params = UserPatchRequest {
+            password: Some("Tr0ub4dor&3".into()),
+            ..UserPatchRequest::default()
+        };
+        let url = format!("{}/{}", PATH, user.uid);
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        assert_eq!(client.patch(&url, params.clone()).await.status(), StatusCode::FORBIDDEN);
+
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        let admin_client = TestClient::login(fixtures.ctx(), &admin).await;
+        assert_success(admin_client.patch(&url, params).await);
+        let patched = TestUser {
+            password: "Tr0ub4dor&3".into(),
+            ..user
+        };
+        TestClient::login(fixtures.ctx(), &patched).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_checks_a_patched_password_against_the_stored_email() {
+        let fixtures = Fixtures::new(test_context());
+        let email = format!("{}@example.com", unique_id("zebrafinch"));
+        let user = fixtures.user().email(&email).create();
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        let client = TestClient::login(fixtures.ctx(), &admin).await;
+        let local_part = email.split('@').next().unwrap();
+        let params = UserPatchRequest {
+            password: Some(format!("{}-Tr0ub4dor&3", local_part)),
+            ..UserPatchRequest::default()
+        };
+        let url = format!("{}/{}", PATH, user.uid);
+        assert_eq!(client.patch(&url, params).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn it_does_not_patch_an_unknown_user() {
+        let fixtures = Fixtures::new(test_context());
+        let admin = fixtures.user().roles(&["admin", "user"]).create();
+        let client = TestClient::login(fixtures.ctx(), &admin).await;
+        let params = UserPatchRequest {
+            phone: Some("010-yyyy-yyyy".into()),
+            ..UserPatchRequest::default()
+        };
+        let url = format!("{}/{}", PATH, unique_id("nobody"));
+        assert_eq!(client.patch(&url, params).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn it_delete_user() {
+        let fixtures = Fixtures::new(test_context());
+        let user = fixtures.user().create();
+        let other = fixtures.user().create();
+        let client = TestClient::login(fixtures.ctx(), &user).await;
+        let url = format!("{}/{}", PATH, other.uid);
+        assert_success(client.delete(&url).await);
     }
 }
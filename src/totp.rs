@@ -0,0 +1,155 @@
+//! Time-based one-time passwords (RFC 6238) for two-factor authentication.
+
+use base32::Alphabet;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::Sha1;
+
+const SECRET_BYTES: usize = 20;
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODE_COUNT: usize = 8;
+
+/// Number of steps before and after the current one that are still accepted
+const ALLOWED_DRIFT: i64 = 1;
+
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// Generate a new random base32-encoded shared secret
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill(&mut bytes[..]);
+    base32::encode(BASE32, &bytes)
+}
+
+/// Build the otpauth URI that authenticator apps read from a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = encode_uri_component(issuer),
+        account = encode_uri_component(account),
+        secret = secret,
+        digits = DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+/// Verify a code against the secret at the current time,
+/// returning the time step it was generated for.
+/// A code stays valid for a few steps: callers must refuse steps already used.
+pub fn verify(secret: &str, code: &str) -> Option<i64> {
+    verify_at(secret, code, Utc::now().timestamp())
+}
+
+/// Verify a code against the secret at a given unix time,
+/// tolerating a small clock drift between server and device
+fn verify_at(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let key = base32::decode(BASE32, secret)?;
+    let counter = timestamp / STEP_SECONDS;
+
+    (-ALLOWED_DRIFT..=ALLOWED_DRIFT)
+        .map(|drift| counter + drift)
+        .filter(|step| *step >= 0)
+        .find(|step| hotp(&key, *step as u64, DIGITS) == code)
+}
+
+/// Code of the secret at the current time, as an authenticator app shows it
+#[cfg(test)]
+pub fn current_code(secret: &str) -> String {
+    let key = base32::decode(BASE32, secret).expect("Invalid base32 secret");
+    hotp(&key, (Utc::now().timestamp() / STEP_SECONDS) as u64, DIGITS)
+}
+
+/// Generate single-use recovery codes, to be shown to the user once
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .collect()
+}
+
+/// HMAC-based one-time password (RFC 4226)
+fn hotp(key: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(&counter.to_be_bytes());
+    let hash = mac.result().code();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((u32::from(hash[offset]) & 0x7f) << 24)
+        | (u32::from(hash[offset + 1]) << 16)
+        | (u32::from(hash[offset + 2]) << 8)
+        | u32::from(hash[offset + 3]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 test secret "12345678901234567890"
+    static SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn it_matches_the_rfc_test_vectors() {
+        let key = base32::decode(BASE32, SECRET).unwrap();
+        assert_eq!(hotp(&key, 59 / 30, 8), "94287082");
+        assert_eq!(hotp(&key, 1111111109 / 30, 8), "07081804");
+        assert_eq!(hotp(&key, 20000000000 / 30, 8), "65353130");
+    }
+
+    #[test]
+    fn it_verifies_a_code_within_the_allowed_drift() {
+        assert_eq!(verify_at(SECRET, "287082", 59), Some(1));
+        assert_eq!(verify_at(SECRET, "287082", 59 + 30), Some(1));
+        assert_eq!(verify_at(SECRET, "287082", 59 + 90), None);
+        assert_eq!(verify_at(SECRET, "000000", 59), None);
+    }
+
+    #[test]
+    fn it_generates_a_secret_usable_for_verification() {
+        let secret = generate_secret();
+        let key = base32::decode(BASE32, &secret).unwrap();
+        assert_eq!(key.len(), SECRET_BYTES);
+    }
+
+    #[test]
+    fn it_builds_an_otpauth_uri() {
+        let uri = otpauth_uri("rust mongodb", "bsjung", SECRET);
+        assert_eq!(
+            uri,
+            "otpauth://totp/rust%20mongodb:bsjung?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=rust%20mongodb&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn it_generates_unique_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_ne!(codes[0], codes[1]);
+    }
+}
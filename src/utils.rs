@@ -1,6 +1,9 @@
+use crate::config::Config;
 use crate::errors::ApiError;
 use actix_web::{body::Body, web::{HttpResponse, Json}};
+use chrono::{SecondsFormat, Utc};
 use serde::Serialize;
+use serde_json::json;
 
 /// Helper function to reduce boilerplate of an OK/Json response
 pub fn respond_json<T>(data: T) -> Result<Json<T>, ApiError> where T: Serialize, {
@@ -13,24 +16,65 @@ pub fn respond_ok() -> Result<HttpResponse, ApiError> {
 }
 
 /// Log
-pub fn init_logger() {
+///
+/// `log_format` selects plain text lines (default, for local dev) or one JSON object per line.
+/// `log_filters` sets per-module levels, e.g. `info,actix_web=warn`; RUST_LOG takes precedence.
+pub fn init_logger(config: &Config) {
+    use crate::middleware::request_id::{current_request_id, current_user_id};
     use chrono::Local;
     use std::io::Write;
 
     let env = env_logger::Env::default()
-        .filter_or(env_logger::DEFAULT_FILTER_ENV, "info");
-    env_logger::Builder::from_env(env)
-        .format(|buf, record| {
+        .filter_or(env_logger::DEFAULT_FILTER_ENV, config.log_filters.as_str());
+    let mut builder = env_logger::Builder::from_env(env);
+
+    if config.log_format == "json" {
+        builder.format(|buf, record| {
+            let line = json_log_line(
+                &record.level().to_string(),
+                record.target(),
+                &record.args().to_string(),
+                current_request_id(),
+                current_user_id(),
+            );
+            writeln!(buf, "{}", line)
+        });
+    } else {
+        builder.format(|buf, record| {
+            let request_id = current_request_id()
+                .map(|request_id| format!(" [{}]", request_id))
+                .unwrap_or_default();
             writeln!(
                 buf,
-                "{} {} [{}] {}",
+                "{} {} [{}]{} {}",
                 Local::now().format("%Y-%m-%d %H:%M:%S"),
                 buf.default_styled_level(record.level()),
                 record.module_path().unwrap_or("<unnamed>"),
+                request_id,
                 &record.args()
             )
-        })
-        .init();
+        });
+    }
+    builder.init();
+}
+
+/// Format a log record as a single-line JSON object
+fn json_log_line(
+    level: &str,
+    target: &str,
+    message: &str,
+    request_id: Option<String>,
+    user_id: Option<String>,
+) -> String {
+    json!({
+        "ts": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": level,
+        "target": target,
+        "message": message,
+        "request_id": request_id,
+        "user_id": user_id,
+    })
+    .to_string()
 }
 
 #[cfg(test)]
@@ -57,4 +101,16 @@ mod tests {
         let result = respond_ok();
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn it_formats_a_json_log_line() {
+        let line = json_log_line("INFO", "access", "status=200", Some("abc-123".into()), None);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["target"], "access");
+        assert_eq!(value["message"], "status=200");
+        assert_eq!(value["request_id"], "abc-123");
+        assert!(value["user_id"].is_null());
+        assert!(value["ts"].is_string());
+    }
 }
@@ -1,8 +1,10 @@
 //! Validation-related functions to work with the validator crate.
 
+use crate::config::Config;
 use crate::errors::ApiError;
 use actix_web::web::Json;
-use validator::{Validate, ValidationErrors};
+use std::borrow::Cow;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 /// Validate a struct and collect and return the errors
 pub fn validate<T>(params: &Json<T>) -> Result<(), ApiError>
@@ -15,6 +17,97 @@ where
   }
 }
 
+/// Passwords rejected regardless of the other rules
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Password strength rules, configured through Config
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+  pub min_length: usize,
+  pub require_lowercase: bool,
+  pub require_uppercase: bool,
+  pub require_digit: bool,
+  pub require_symbol: bool,
+}
+
+impl PasswordPolicy {
+  pub fn from_config(config: &Config) -> Self {
+    PasswordPolicy {
+      min_length: config.password_min_length,
+      require_lowercase: config.password_require_lowercase,
+      require_uppercase: config.password_require_uppercase,
+      require_digit: config.password_require_digit,
+      require_symbol: config.password_require_symbol,
+    }
+  }
+}
+
+/// Custom validator rejecting passwords that contain the uid or email
+pub fn validate_password_identity(
+  password: &str,
+  uid: &str,
+  email: &str,
+) -> Result<(), ValidationError> {
+  check_password_identity(password, uid, email).map_err(password_error)
+}
+
+/// Validate a new password against the configured password policy
+pub fn validate_password(config: &Config, password: &str, uid: &str, email: &str) -> Result<(), ApiError> {
+  check_password(password, &PasswordPolicy::from_config(config))
+    .and_then(|_| check_password_identity(password, uid, email))
+    .map_err(|message| ApiError::ValidationError(vec![message]))
+}
+
+fn check_password(password: &str, policy: &PasswordPolicy) -> Result<(), String> {
+  if password.chars().count() < policy.min_length {
+    return Err(format!(
+      "password must be at least {} characters",
+      policy.min_length
+    ));
+  }
+  if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+    return Err("password must contain a lowercase letter".into());
+  }
+  if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+    return Err("password must contain an uppercase letter".into());
+  }
+  if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+    return Err("password must contain a digit".into());
+  }
+  if policy.require_symbol && password.chars().all(char::is_alphanumeric) {
+    return Err("password must contain a symbol".into());
+  }
+  if is_common_password(password) {
+    return Err("password is too common".into());
+  }
+  Ok(())
+}
+
+fn check_password_identity(password: &str, uid: &str, email: &str) -> Result<(), String> {
+  let password = password.to_lowercase();
+  let local_part = email.split('@').next().unwrap_or_default();
+  let contains_identity = [uid, email, local_part]
+    .iter()
+    .filter(|value| value.len() >= 3)
+    .any(|value| password.contains(&value.to_lowercase()));
+
+  if contains_identity {
+    return Err("password must not contain the UserID or email".into());
+  }
+  Ok(())
+}
+
+fn is_common_password(password: &str) -> bool {
+  let password = password.to_lowercase();
+  COMMON_PASSWORDS.lines().any(|common| common == password)
+}
+
+fn password_error(message: String) -> ValidationError {
+  let mut error = ValidationError::new("password");
+  error.message = Some(Cow::Owned(message));
+  error
+}
+
 /// Collect ValidationErrors and return a vector of the messages
 /// Adds a default_error when none is supplied
 fn collect_errors(error: ValidationErrors) -> Vec<String> {
@@ -59,6 +152,52 @@ mod tests {
     assert!(response.len() > 0);
   }
 
+  fn get_test_policy() -> PasswordPolicy {
+    PasswordPolicy {
+      min_length: 8,
+      require_lowercase: true,
+      require_uppercase: true,
+      require_digit: true,
+      require_symbol: true,
+    }
+  }
+
+  #[test]
+  fn it_rejects_a_short_password() {
+    let response = check_password("aB3$", &get_test_policy());
+    assert_eq!(response, Err("password must be at least 8 characters".into()));
+  }
+
+  #[test]
+  fn it_enforces_character_classes() {
+    let policy = get_test_policy();
+    assert!(check_password("ab3$efgh", &policy).is_err());
+    assert!(check_password("AB3$EFGH", &policy).is_err());
+    assert!(check_password("aB$defgh", &policy).is_err());
+    assert!(check_password("aB3defgh", &policy).is_err());
+    assert!(check_password("aB3$efgh", &policy).is_ok());
+  }
+
+  #[test]
+  fn it_rejects_a_common_password() {
+    let policy = PasswordPolicy {
+      min_length: 6,
+      require_lowercase: false,
+      require_uppercase: false,
+      require_digit: false,
+      require_symbol: false,
+    };
+    let response = check_password("Password123", &policy);
+    assert_eq!(response, Err("password is too common".into()));
+  }
+
+  #[test]
+  fn it_rejects_a_password_containing_the_uid_or_email() {
+    assert!(check_password_identity("xbsjung2x!", "bsjung2", "someone@example.com").is_err());
+    assert!(check_password_identity("Someone#99", "bsjung2", "someone@example.com").is_err());
+    assert!(check_password_identity("Tr0ub4dor&3", "bsjung2", "someone@example.com").is_ok());
+  }
+
   /*
   #[test]
   fn it_validates() {